@@ -0,0 +1,52 @@
+//! Timing of `IncrementalCombinations` updates against a full recompute
+//!
+//! Run with `cargo bench --bench incremental`; prints one line of timings.
+
+use geo_wasm::{
+    find_best_combinations_with_options, CombinationOptions, IncrementalCombinations, PointGroup,
+};
+use std::time::Instant;
+
+/// Deterministic pseudo-random points in a 1° box, flat [lat, lon, ...]
+fn sample_points(seed: u64, count: usize) -> Vec<f64> {
+    let mut state = seed;
+    let mut next = || {
+        // SplitMix64
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        ((z ^ (z >> 31)) >> 11) as f64 / (1u64 << 53) as f64
+    };
+    (0..count)
+        .flat_map(|_| [40.0 + next(), -75.0 + next()])
+        .collect()
+}
+
+fn main() {
+    let points_a = sample_points(43, 2000);
+    let points_b = sample_points(44, 2000);
+    let options = CombinationOptions::new();
+    let mut combos = IncrementalCombinations::new(&points_a, &points_b, 40.5, -74.5, 10, &options);
+
+    // A far-off point drops out of the top N: a pure merge
+    let start = Instant::now();
+    combos
+        .update_point(PointGroup::A, 1999, 10.0, 10.0)
+        .unwrap();
+    let merged = start.elapsed();
+
+    // Moving a point of the best pair forces a reselect from the cache
+    let best_a = combos.results()[0] as usize;
+    let start = Instant::now();
+    combos
+        .update_point(PointGroup::A, best_a, 10.0, 10.0)
+        .unwrap();
+    let reselected = start.elapsed();
+
+    let start = Instant::now();
+    find_best_combinations_with_options(&points_a, &points_b, 40.5, -74.5, 10, &options);
+    let recomputed = start.elapsed();
+
+    println!("2000x2000: merge {merged:?}, reselect {reselected:?}, full recompute {recomputed:?}");
+}
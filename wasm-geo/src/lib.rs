@@ -1,163 +1,8422 @@
 use wasm_bindgen::prelude::*;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
 use std::f64::consts::PI;
+use std::ops::ControlFlow;
 
 const EARTH_RADIUS_KM: f64 = 6371.0;
 
+/// Order of the two values in each pair of a flat coordinate array
+///
+/// Internally everything is lat,lon; `LonLat` (GeoJSON order) is swapped at
+/// the boundary. Named scalar arguments such as `target_lat` are unaffected.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CoordOrder {
+    #[default]
+    LatLon,
+    LonLat,
+}
+
+/// Unit of the angles in coordinate inputs and midpoint outputs
+///
+/// With `Radians`, latitudes are expected in [-π/2, π/2] and longitudes in
+/// [-π, π]. Distances and scores stay in kilometers either way.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AngleUnit {
+    #[default]
+    Degrees,
+    Radians,
+}
+
+/// How a result's score is reported by the batch engine
+///
+/// The kernels turn the km score into a 0-1 similarity (1 at distance 0),
+/// with the shape parameter set alongside via `set_score_kernel`.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ScoreKernel {
+    /// Raw distance in km
+    #[default]
+    Distance,
+    /// 1 - d / cutoff, reaching 0 at the cutoff distance
+    Linear,
+    /// exp(-d² / 2σ²) with σ as the parameter
+    Gaussian,
+    /// 0.5^(d / h), halving every h km
+    Exponential,
+}
+
+/// Whether coordinates are on the sphere or in a flat projected plane
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Geometry {
+    /// Lat/lon on the sphere, with great-circle distances and midpoints
+    #[default]
+    Spherical,
+    /// x,y in a projected CRS: Euclidean distances and averaged midpoints
+    Planar,
+}
+
+/// How a cost raster is read at a point
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RasterSampling {
+    /// Value of the cell containing the point
+    #[default]
+    Nearest,
+    /// Interpolated between the four surrounding cell centers
+    Bilinear,
+}
+
+/// How the meeting point of a pair is computed in the batch engine
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MidpointMethod {
+    /// Spherical great-circle midpoint (fastest)
+    #[default]
+    GreatCircle,
+    /// Midpoint along the constant-bearing rhumb line
+    Rhumb,
+    /// Ellipsoidal WGS-84 midpoint via Vincenty; pairs where it doesn't
+    /// converge are dropped
+    Vincenty,
+}
+
+/// What the batch engine minimizes for each pair
+///
+/// The travel modes treat the target as the meeting place and score a pair
+/// by its two participants' distances to it (da and db); the midpoint is
+/// still computed, reported and used by the filters, except in `PickupRoute`.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ScoringMode {
+    /// Distance from the pair's midpoint to the target
+    #[default]
+    TargetProximity,
+    /// da + db
+    SumTravel,
+    /// max(da, db)
+    MaxTravel,
+    /// 2·da·db / (da + db), at most the mean and pulled toward the shorter
+    /// leg; 0 when both participants are at the target
+    HarmonicTravel,
+    /// |da/speedA - db/speedB|, the gap between arrival times, in hours with
+    /// `set_group_speeds` (km/h) and as |da - db| km without; a group with
+    /// speed 0 never arrives, so its pairs are dropped
+    ArrivalSkew,
+    /// Driver A picks up passenger B on the way: dist(A, B) + db. There is
+    /// no midpoint; B's location stands in for it in the filters and output
+    PickupRoute,
+}
+
+/// Combine two participants' travel distances under a travel scoring mode;
+/// `TargetProximity` has no travel meaning and sums like `SumTravel`
+#[inline]
+fn combine_travel(mode: ScoringMode, da: f64, db: f64) -> f64 {
+    match mode {
+        ScoringMode::TargetProximity | ScoringMode::SumTravel | ScoringMode::PickupRoute => da + db,
+        ScoringMode::MaxTravel => da.max(db),
+        ScoringMode::HarmonicTravel if da + db == 0.0 => 0.0,
+        ScoringMode::HarmonicTravel => 2.0 * da * db / (da + db),
+        ScoringMode::ArrivalSkew => (da - db).abs(),
+    }
+}
+
+/// Transform a km score through a kernel; negative scores count as 0 km
+/// except for `Distance`, which passes the score through
+#[inline]
+fn apply_kernel(kernel: ScoreKernel, param_km: f64, score: f64) -> f64 {
+    let d = score.max(0.0);
+    match kernel {
+        ScoreKernel::Distance => score,
+        ScoreKernel::Linear => (1.0 - d / param_km).max(0.0),
+        ScoreKernel::Gaussian => (-d * d / (2.0 * param_km * param_km)).exp(),
+        ScoreKernel::Exponential => 0.5f64.powf(d / param_km),
+    }
+}
+
+/// How distances to several targets combine into one score
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TargetAggregation {
+    /// Total distance to all targets
+    #[default]
+    Sum,
+    /// Average distance, on the same km scale as a single target
+    Mean,
+}
+
+/// View a flat coordinate array in lat,lon order, swapping pairs if needed
+fn to_lat_lon(points: &[f64], order: CoordOrder) -> Cow<'_, [f64]> {
+    match order {
+        CoordOrder::LatLon => Cow::Borrowed(points),
+        CoordOrder::LonLat => {
+            Cow::Owned(points.chunks_exact(2).flat_map(|p| [p[1], p[0]]).collect())
+        }
+    }
+}
+
 /// Convert degrees to radians
 #[inline]
 fn to_rad(deg: f64) -> f64 {
     deg * PI / 180.0
 }
 
+/// Convert radians to degrees
+#[inline]
+fn to_deg(rad: f64) -> f64 {
+    rad * 180.0 / PI
+}
+
+/// Wrap any longitude in degrees into [-180, 180)
+///
+/// This is the crate's convention for output longitudes: 180 maps to -180,
+/// values already in range pass through unchanged, NaN stays NaN.
+#[wasm_bindgen]
+pub fn normalize_longitude(lon: f64) -> f64 {
+    if (-180.0..180.0).contains(&lon) {
+        return lon;
+    }
+    let wrapped = (lon + 180.0).rem_euclid(360.0) - 180.0;
+    // rem_euclid can round up to exactly 360 for tiny negative inputs
+    if wrapped >= 180.0 {
+        wrapped - 360.0
+    } else {
+        wrapped
+    }
+}
+
 /// Haversine distance between two points in kilometers
 #[inline]
 fn haversine_distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
-    let lat1_rad = to_rad(lat1);
-    let lat2_rad = to_rad(lat2);
+    haversine_distance_cos(
+        lat1,
+        lon1,
+        to_rad(lat1).cos(),
+        lat2,
+        lon2,
+        to_rad(lat2).cos(),
+    )
+}
+
+/// Haversine distance in kilometers between two points in degrees
+#[wasm_bindgen]
+pub fn haversine_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    haversine_distance(lat1, lon1, lat2, lon2)
+}
+
+/// Haversine distance in kilometers between two points given in radians
+///
+/// Latitudes in [-π/2, π/2], longitudes in [-π, π]; no degree conversion is
+/// done, for callers whose data is already in radians
+#[wasm_bindgen]
+pub fn haversine_distance_rad(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let a = ((lat2 - lat1) / 2.0).sin().powi(2)
+        + lat1.cos() * lat2.cos() * ((lon2 - lon1) / 2.0).sin().powi(2);
+
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+/// Whether two points are within `tol_m` meters of each other by haversine distance
+///
+/// Unlike an epsilon on degrees, the tolerance means the same at every latitude.
+#[wasm_bindgen]
+pub fn coords_approx_equal(lat1: f64, lon1: f64, lat2: f64, lon2: f64, tol_m: f64) -> bool {
+    haversine_distance(lat1, lon1, lat2, lon2) * 1000.0 <= tol_m
+}
+
+/// Haversine distance in kilometers using precomputed latitude cosines
+#[inline]
+fn haversine_distance_cos(
+    lat1: f64,
+    lon1: f64,
+    cos_lat1: f64,
+    lat2: f64,
+    lon2: f64,
+    cos_lat2: f64,
+) -> f64 {
     let delta_lat = to_rad(lat2 - lat1);
     let delta_lon = to_rad(lon2 - lon1);
 
-    let a = (delta_lat / 2.0).sin().powi(2)
-        + lat1_rad.cos() * lat2_rad.cos() * (delta_lon / 2.0).sin().powi(2);
+    let a = (delta_lat / 2.0).sin().powi(2) + cos_lat1 * cos_lat2 * (delta_lon / 2.0).sin().powi(2);
 
     let c = 2.0 * a.sqrt().asin();
 
     EARTH_RADIUS_KM * c
 }
 
-/// Calculate geodesic midpoint between two points
+/// A point with its latitude trig precomputed for the hot loops
+#[derive(Clone, Copy)]
+struct TrigPoint {
+    lat: f64,
+    lon: f64,
+    lon_rad: f64,
+    sin_lat: f64,
+    cos_lat: f64,
+}
+
+impl TrigPoint {
+    #[inline]
+    fn new(lat: f64, lon: f64) -> Self {
+        let lat_rad = to_rad(lat);
+        TrigPoint {
+            lat,
+            lon,
+            lon_rad: to_rad(lon),
+            sin_lat: lat_rad.sin(),
+            cos_lat: lat_rad.cos(),
+        }
+    }
+
+    /// Build from radians, skipping the degree conversion of the trig inputs
+    #[inline]
+    fn from_rad(lat_rad: f64, lon_rad: f64) -> Self {
+        TrigPoint {
+            lat: to_deg(lat_rad),
+            lon: to_deg(lon_rad),
+            lon_rad,
+            sin_lat: lat_rad.sin(),
+            cos_lat: lat_rad.cos(),
+        }
+    }
+}
+
+/// Precompute trig for every point of a flat [lat0, lon0, ...] array
+fn precompute_trig(points: &[f64]) -> Vec<TrigPoint> {
+    points
+        .chunks_exact(2)
+        .map(|p| TrigPoint::new(p[0], p[1]))
+        .collect()
+}
+
+/// Geodesic midpoint of two points with precomputed trig
 /// Returns (lat, lon) in degrees
 #[inline]
-fn geodesic_midpoint(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> (f64, f64) {
+fn geodesic_midpoint_trig(p1: &TrigPoint, p2: &TrigPoint) -> (f64, f64) {
+    let (lat_mid, lon_mid) = geodesic_midpoint_trig_rad(p1, p2);
+    (
+        lat_mid * 180.0 / PI,
+        normalize_longitude(lon_mid * 180.0 / PI),
+    )
+}
+
+/// Geodesic midpoint of two points with precomputed trig
+/// Returns (lat, lon) in radians
+#[inline]
+fn geodesic_midpoint_trig_rad(p1: &TrigPoint, p2: &TrigPoint) -> (f64, f64) {
+    let delta_lon = p2.lon_rad - p1.lon_rad;
+
+    let bx = p2.cos_lat * delta_lon.cos();
+    let by = p2.cos_lat * delta_lon.sin();
+
+    let lat_mid = (p1.sin_lat + p2.sin_lat).atan2(((p1.cos_lat + bx).powi(2) + by.powi(2)).sqrt());
+
+    let lon_mid = p1.lon_rad + by.atan2(p1.cos_lat + bx);
+
+    (lat_mid, lon_mid)
+}
+
+/// Unit vector on the sphere for a lat/lon in degrees
+#[inline]
+fn to_vector(lat: f64, lon: f64) -> [f64; 3] {
+    let lat_rad = to_rad(lat);
+    let lon_rad = to_rad(lon);
+    [
+        lat_rad.cos() * lon_rad.cos(),
+        lat_rad.cos() * lon_rad.sin(),
+        lat_rad.sin(),
+    ]
+}
+
+/// Lat/lon in degrees of a (not necessarily unit) vector
+#[inline]
+fn from_vector(v: [f64; 3]) -> (f64, f64) {
+    let lat = v[2].atan2((v[0] * v[0] + v[1] * v[1]).sqrt());
+    let lon = v[1].atan2(v[0]);
+    (to_deg(lat), normalize_longitude(to_deg(lon)))
+}
+
+#[inline]
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+#[inline]
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+#[inline]
+fn norm(v: [f64; 3]) -> f64 {
+    dot(v, v).sqrt()
+}
+
+/// Initial great-circle bearing from point 1 to point 2
+/// Returns degrees in [0, 360)
+#[wasm_bindgen]
+pub fn initial_bearing(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
     let lat1_rad = to_rad(lat1);
-    let lon1_rad = to_rad(lon1);
     let lat2_rad = to_rad(lat2);
-    let lon2_rad = to_rad(lon2);
+    let delta_lon = to_rad(lon2 - lon1);
+
+    let y = delta_lon.sin() * lat2_rad.cos();
+    let x = lat1_rad.cos() * lat2_rad.sin() - lat1_rad.sin() * lat2_rad.cos() * delta_lon.cos();
 
-    let delta_lon = lon2_rad - lon1_rad;
+    (to_deg(y.atan2(x)) + 360.0) % 360.0
+}
 
-    let bx = lat2_rad.cos() * delta_lon.cos();
-    let by = lat2_rad.cos() * delta_lon.sin();
+/// Distance and both end bearings between two points, sharing the trig
+///
+/// The final bearing is the course on arrival at point 2; it equals the
+/// initial bearing only along a meridian or the equator. Coincident points
+/// give 0 for all three.
+/// Returns [distanceKm, initialBearingDeg, finalBearingDeg], bearings in [0, 360)
+#[wasm_bindgen]
+pub fn inverse_geodesic(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> Vec<f64> {
+    let (sin_lat1, cos_lat1) = to_rad(lat1).sin_cos();
+    let (sin_lat2, cos_lat2) = to_rad(lat2).sin_cos();
+    let delta_lon = to_rad(lon2 - lon1);
+    let (sin_delta_lon, cos_delta_lon) = delta_lon.sin_cos();
 
-    let lat_mid = (lat1_rad.sin() + lat2_rad.sin())
-        .atan2(((lat1_rad.cos() + bx).powi(2) + by.powi(2)).sqrt());
+    let a = (to_rad(lat2 - lat1) / 2.0).sin().powi(2)
+        + cos_lat1 * cos_lat2 * (delta_lon / 2.0).sin().powi(2);
+    let distance_km = EARTH_RADIUS_KM * 2.0 * a.sqrt().asin();
 
-    let lon_mid = lon1_rad + by.atan2(lat1_rad.cos() + bx);
+    let initial =
+        (sin_delta_lon * cos_lat2).atan2(cos_lat1 * sin_lat2 - sin_lat1 * cos_lat2 * cos_delta_lon);
+    // Reverse of the initial bearing from point 2 back to point 1
+    let final_ =
+        (sin_delta_lon * cos_lat1).atan2(sin_lat2 * cos_lat1 * cos_delta_lon - cos_lat2 * sin_lat1);
 
-    (lat_mid * 180.0 / PI, lon_mid * 180.0 / PI)
+    vec![
+        distance_km,
+        (to_deg(initial) + 360.0) % 360.0,
+        (to_deg(final_) + 360.0) % 360.0,
+    ]
 }
 
-/// Result structure for a combination
-#[derive(Clone, Copy)]
-struct ComboResult {
-    index_a: u32,
-    index_b: u32,
-    score: f64,
-    midpoint_lat: f64,
-    midpoint_lon: f64,
+/// Point reached by travelling a distance along an initial bearing
+/// Returns (lat, lon) in degrees with lon in [-180, 180)
+#[inline]
+fn destination(lat: f64, lon: f64, bearing: f64, distance_km: f64) -> (f64, f64) {
+    let lat_rad = to_rad(lat);
+    let bearing_rad = to_rad(bearing);
+    let angular = distance_km / EARTH_RADIUS_KM;
+
+    let dest_lat =
+        (lat_rad.sin() * angular.cos() + lat_rad.cos() * angular.sin() * bearing_rad.cos()).asin();
+    let dest_lon = to_rad(lon)
+        + (bearing_rad.sin() * angular.sin() * lat_rad.cos())
+            .atan2(angular.cos() - lat_rad.sin() * dest_lat.sin());
+
+    (to_deg(dest_lat), normalize_longitude(to_deg(dest_lon)))
 }
 
-/// Calculate all combination scores and return top N results
+/// Bearing of the A->B great circle where it passes through its midpoint
 ///
-/// Input arrays are flat: [lat0, lon0, lat1, lon1, ...]
-/// Returns flat array: [indexA, indexB, score, midLat, midLon, ...] for top N
+/// This is the course out of the midpoint, heading on toward B (the initial
+/// bearing from the midpoint to B), in degrees [0, 360). Equal to the course
+/// into the midpoint, since a great circle has no kink there.
 #[wasm_bindgen]
-pub fn find_best_combinations(
-    points_a: &[f64],
-    points_b: &[f64],
-    target_lat: f64,
-    target_lon: f64,
-    top_n: usize,
-) -> Vec<f64> {
-    let num_a = points_a.len() / 2;
-    let num_b = points_b.len() / 2;
-
-    // Pre-allocate for all combinations
-    let total_combos = num_a * num_b;
-    let mut results: Vec<ComboResult> = Vec::with_capacity(total_combos);
+pub fn midpoint_azimuth(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (mid_lat, mid_lon) =
+        geodesic_midpoint_trig(&TrigPoint::new(lat1, lon1), &TrigPoint::new(lat2, lon2));
+    initial_bearing(mid_lat, mid_lon, lat2, lon2)
+}
 
-    // Calculate all combinations
-    for i in 0..num_a {
-        let lat_a = points_a[i * 2];
-        let lon_a = points_a[i * 2 + 1];
+/// Point reached by travelling `distance_km` from a start along an initial bearing
+/// Returns [lat, lon] with lon in [-180, 180)
+#[wasm_bindgen]
+pub fn destination_point(lat: f64, lon: f64, bearing: f64, distance_km: f64) -> Vec<f64> {
+    let (dest_lat, dest_lon) = destination(lat, lon, bearing, distance_km);
+    vec![dest_lat, dest_lon]
+}
 
-        for j in 0..num_b {
-            let lat_b = points_b[j * 2];
-            let lon_b = points_b[j * 2 + 1];
+/// Destination points at one distance along each of several bearings
+///
+/// Batched `destination_point` for drawing range rings and sector outlines.
+/// Returns flat array: [lat0, lon0, lat1, lon1, ...], one point per bearing,
+/// each lon in [-180, 180)
+#[wasm_bindgen]
+pub fn destination_fan(lat: f64, lon: f64, distance_km: f64, bearings: &[f64]) -> Vec<f64> {
+    let mut output = Vec::with_capacity(bearings.len() * 2);
+    for &bearing in bearings {
+        let (dest_lat, dest_lon) = destination(lat, lon, bearing, distance_km);
+        output.push(dest_lat);
+        output.push(dest_lon);
+    }
 
-            // Calculate midpoint
-            let (mid_lat, mid_lon) = geodesic_midpoint(lat_a, lon_a, lat_b, lon_b);
+    output
+}
 
-            // Calculate score (distance from midpoint to target)
-            let score = haversine_distance(mid_lat, mid_lon, target_lat, target_lon);
+/// Outline of everything within `distance_km` of a center (a geodesic circle)
+///
+/// Vertices are destination points at `segments` evenly spaced bearings,
+/// clockwise from north, with the first repeated at the end to close the ring.
+/// Longitudes are unwrapped so consecutive vertices never jump by more than
+/// 180 degrees: a ring across the antimeridian draws as one polygon, at the
+/// cost of lons outside [-180, 180) (`normalize_longitude` maps them back).
+/// When the circle encloses a pole the unwrapped ring ends 360 degrees from
+/// where it starts, so it is closed along that pole through two extra
+/// vertices, the only ones not `distance_km` from the center.
+/// Returns flat array: [lat0, lon0, lat1, lon1, ...], empty for fewer than
+/// 3 segments
+#[wasm_bindgen]
+pub fn isoline(center_lat: f64, center_lon: f64, distance_km: f64, segments: usize) -> Vec<f64> {
+    if segments < 3 {
+        return Vec::new();
+    }
 
-            results.push(ComboResult {
-                index_a: i as u32,
-                index_b: j as u32,
-                score,
-                midpoint_lat: mid_lat,
-                midpoint_lon: mid_lon,
-            });
+    let mut output = Vec::with_capacity((segments + 3) * 2);
+    let mut prev_lon = f64::NAN;
+    for i in 0..=segments {
+        let bearing = 360.0 * (i % segments) as f64 / segments as f64;
+        let (lat, mut lon) = destination(center_lat, center_lon, bearing, distance_km);
+        if i > 0 {
+            lon = prev_lon + normalize_longitude(lon - prev_lon);
         }
+        output.push(lat);
+        output.push(lon);
+        prev_lon = lon;
     }
 
-    // Partial sort to get top N (faster than full sort for large arrays)
-    let n = top_n.min(results.len());
-    results.select_nth_unstable_by(n.saturating_sub(1), |a, b| {
-        a.score.partial_cmp(&b.score).unwrap_or(std::cmp::Ordering::Equal)
-    });
+    // Around a pole the longitude sweep adds up to a full turn
+    let first_lon = output[1];
+    if (prev_lon - first_lon).abs() > 180.0 {
+        let pole_lat = if center_lat >= 0.0 { 90.0 } else { -90.0 };
+        output.extend_from_slice(&[
+            pole_lat, prev_lon, pole_lat, first_lon, output[0], first_lon,
+        ]);
+    }
 
-    // Sort just the top N
-    results[..n].sort_by(|a, b| {
-        a.score.partial_cmp(&b.score).unwrap_or(std::cmp::Ordering::Equal)
-    });
+    output
+}
 
-    // Flatten results into output array
-    let mut output = Vec::with_capacity(n * 5);
-    for result in results.iter().take(n) {
-        output.push(result.index_a as f64);
-        output.push(result.index_b as f64);
-        output.push(result.score);
-        output.push(result.midpoint_lat);
-        output.push(result.midpoint_lon);
+/// Point a fraction of the way along the great circle between two unit vectors
+///
+/// Coincident points give the start; antipodal ones have no unique great
+/// circle and give NaN.
+#[inline]
+fn interpolate_vectors(a: [f64; 3], b: [f64; 3], fraction: f64) -> [f64; 3] {
+    let sin_delta = norm(cross(a, b));
+    let cos_delta = dot(a, b);
+    if sin_delta < 1e-12 {
+        return if cos_delta > 0.0 { a } else { [f64::NAN; 3] };
     }
+    let delta = sin_delta.atan2(cos_delta);
 
-    output
+    let wa = ((1.0 - fraction) * delta).sin() / sin_delta;
+    let wb = (fraction * delta).sin() / sin_delta;
+    [
+        wa * a[0] + wb * b[0],
+        wa * a[1] + wb * b[1],
+        wa * a[2] + wb * b[2],
+    ]
 }
 
-/// Calculate all midpoints for heatmap generation
-/// Returns flat array: [lat0, lon0, lat1, lon1, ...]
+/// Point at `fraction` of the way along the great circle from point 1 to point 2
+///
+/// Fraction 0 returns point 1 and 1 returns point 2 exactly; values outside
+/// [0, 1] extrapolate along the same great circle.
+/// Returns [lat, lon], or NaNs for antipodal points
 #[wasm_bindgen]
-pub fn calculate_all_midpoints(
-    points_a: &[f64],
-    points_b: &[f64],
-) -> Vec<f64> {
-    let num_a = points_a.len() / 2;
-    let num_b = points_b.len() / 2;
+pub fn intermediate_point(lat1: f64, lon1: f64, lat2: f64, lon2: f64, fraction: f64) -> Vec<f64> {
+    if fraction == 0.0 {
+        return vec![lat1, lon1];
+    }
+    if fraction == 1.0 {
+        return vec![lat2, lon2];
+    }
 
-    let total_combos = num_a * num_b;
-    let mut output = Vec::with_capacity(total_combos * 2);
+    let (lat, lon) = from_vector(interpolate_vectors(
+        to_vector(lat1, lon1),
+        to_vector(lat2, lon2),
+        fraction,
+    ));
+    vec![lat, lon]
+}
+
+/// Difference in Mercator-projected latitude between two latitudes in radians
+#[inline]
+fn mercator_delta(lat1_rad: f64, lat2_rad: f64) -> f64 {
+    ((lat2_rad / 2.0 + PI / 4.0).tan() / (lat1_rad / 2.0 + PI / 4.0).tan()).ln()
+}
+
+/// Shortest longitude difference in radians, in [-PI, PI]
+#[inline]
+fn shortest_delta_lon(lon1: f64, lon2: f64) -> f64 {
+    let delta_lon = to_rad(lon2 - lon1);
+    if delta_lon.abs() > PI {
+        if delta_lon > 0.0 {
+            delta_lon - 2.0 * PI
+        } else {
+            delta_lon + 2.0 * PI
+        }
+    } else {
+        delta_lon
+    }
+}
 
-    for i in 0..num_a {
-        let lat_a = points_a[i * 2];
-        let lon_a = points_a[i * 2 + 1];
+/// Constant compass course (rhumb line) from point 1 to point 2
+/// Returns degrees in [0, 360), taking the shorter way around in longitude
+#[wasm_bindgen]
+pub fn rhumb_bearing(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let delta_psi = mercator_delta(to_rad(lat1), to_rad(lat2));
+    let delta_lon = shortest_delta_lon(lon1, lon2);
 
-        for j in 0..num_b {
-            let lat_b = points_b[j * 2];
-            let lon_b = points_b[j * 2 + 1];
+    (to_deg(delta_lon.atan2(delta_psi)) + 360.0) % 360.0
+}
 
-            let (mid_lat, mid_lon) = geodesic_midpoint(lat_a, lon_a, lat_b, lon_b);
+/// Midpoint along the rhumb line between two points
+/// Returns [lat, lon] with lon in [-180, 180)
+#[wasm_bindgen]
+pub fn rhumb_midpoint(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> Vec<f64> {
+    let (lat, lon) = rhumb_midpoint_deg(lat1, lon1, lat2, lon2);
+    vec![lat, lon]
+}
 
-            output.push(mid_lat);
-            output.push(mid_lon);
+/// Rhumb-line midpoint as (lat, lon) in degrees
+fn rhumb_midpoint_deg(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> (f64, f64) {
+    let lat1_rad = to_rad(lat1);
+    let lat2_rad = to_rad(lat2);
+    let lon1_rad = to_rad(lon1);
+    // Shortest way round, so unwrap lon2 next to lon1
+    let lon2_rad = lon1_rad + shortest_delta_lon(lon1, lon2);
+
+    let lat_mid = (lat1_rad + lat2_rad) / 2.0;
+    let f1 = (PI / 4.0 + lat1_rad / 2.0).tan();
+    let f2 = (PI / 4.0 + lat2_rad / 2.0).tan();
+    let f_mid = (PI / 4.0 + lat_mid / 2.0).tan();
+
+    let lon_mid = if (f2 / f1).ln().abs() < 1e-12 {
+        // Along a parallel the rhumb line is just the parallel
+        (lon1_rad + lon2_rad) / 2.0
+    } else {
+        ((lon2_rad - lon1_rad) * f_mid.ln() + lon1_rad * f2.ln() - lon2_rad * f1.ln())
+            / (f2 / f1).ln()
+    };
+
+    (to_deg(lat_mid), normalize_longitude(to_deg(lon_mid)))
+}
+
+/// Length of the rhumb line between two points in kilometers
+///
+/// Takes the shorter way around in longitude, like `rhumb_bearing`. Along a
+/// parallel it is the parallel's arc.
+#[wasm_bindgen]
+pub fn rhumb_distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let lat1_rad = to_rad(lat1);
+    let delta_lat = to_rad(lat2) - lat1_rad;
+    let delta_psi = mercator_delta(lat1_rad, to_rad(lat2));
+    let delta_lon = shortest_delta_lon(lon1, lon2);
+
+    // Stretch of the longitude difference, the parallel's cosine when flat
+    let q = if delta_psi.abs() > 1e-12 {
+        delta_lat / delta_psi
+    } else {
+        lat1_rad.cos()
+    };
+
+    EARTH_RADIUS_KM * delta_lat.hypot(q * delta_lon)
+}
+
+/// How much shorter the great circle is than the rhumb line between two points
+///
+/// Returns [greatCircleKm, rhumbKm, savingsKm, savingsPercent], the percent
+/// taken of the rhumb length (0 for coincident points). The two agree along
+/// the equator and meridians; savings grow with latitude on east-west legs.
+#[wasm_bindgen]
+pub fn route_comparison(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> Vec<f64> {
+    let great_circle_km = haversine_distance(lat1, lon1, lat2, lon2);
+    let rhumb_km = rhumb_distance(lat1, lon1, lat2, lon2);
+    let savings_km = rhumb_km - great_circle_km;
+    let savings_percent = if rhumb_km > 0.0 {
+        savings_km / rhumb_km * 100.0
+    } else {
+        0.0
+    };
+
+    vec![great_circle_km, rhumb_km, savings_km, savings_percent]
+}
+
+/// Smallest angle between two bearings in degrees
+/// Returns a value in [0, 180]
+#[wasm_bindgen]
+pub fn bearing_difference(bearing1: f64, bearing2: f64) -> f64 {
+    let diff = (bearing2 - bearing1).rem_euclid(360.0);
+    if diff > 180.0 {
+        360.0 - diff
+    } else {
+        diff
+    }
+}
+
+/// WGS-84 semi-major axis in meters
+const WGS84_A: f64 = 6_378_137.0;
+
+/// WGS-84 flattening
+const WGS84_F: f64 = 1.0 / 298.257_223_563;
+
+/// Iteration cap for the Vincenty formulae
+const VINCENTY_MAX_ITERATIONS: usize = 200;
+
+/// Vincenty inverse problem on the WGS-84 ellipsoid
+/// Returns (distance in meters, initial azimuth in radians), or None when the
+/// iteration fails to converge (nearly antipodal points)
+fn vincenty_inverse(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> Option<(f64, f64)> {
+    let b = WGS84_A * (1.0 - WGS84_F);
+    let l = to_rad(lon2 - lon1);
+    let u1 = ((1.0 - WGS84_F) * to_rad(lat1).tan()).atan();
+    let u2 = ((1.0 - WGS84_F) * to_rad(lat2).tan()).atan();
+    let (sin_u1, cos_u1) = u1.sin_cos();
+    let (sin_u2, cos_u2) = u2.sin_cos();
+
+    let mut lambda = l;
+    for _ in 0..VINCENTY_MAX_ITERATIONS {
+        let (sin_lambda, cos_lambda) = lambda.sin_cos();
+        let sin_sigma = ((cos_u2 * sin_lambda).powi(2)
+            + (cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_lambda).powi(2))
+        .sqrt();
+        if sin_sigma == 0.0 {
+            // Coincident points
+            return Some((0.0, 0.0));
+        }
+        let cos_sigma = sin_u1 * sin_u2 + cos_u1 * cos_u2 * cos_lambda;
+        let sigma = sin_sigma.atan2(cos_sigma);
+        let sin_alpha = cos_u1 * cos_u2 * sin_lambda / sin_sigma;
+        let cos_sq_alpha = 1.0 - sin_alpha * sin_alpha;
+        // Equatorial lines have cos²α = 0
+        let cos_2sigma_m = if cos_sq_alpha != 0.0 {
+            cos_sigma - 2.0 * sin_u1 * sin_u2 / cos_sq_alpha
+        } else {
+            0.0
+        };
+        let c = WGS84_F / 16.0 * cos_sq_alpha * (4.0 + WGS84_F * (4.0 - 3.0 * cos_sq_alpha));
+        let lambda_prev = lambda;
+        lambda = l
+            + (1.0 - c)
+                * WGS84_F
+                * sin_alpha
+                * (sigma
+                    + c * sin_sigma
+                        * (cos_2sigma_m + c * cos_sigma * (-1.0 + 2.0 * cos_2sigma_m.powi(2))));
+
+        if (lambda - lambda_prev).abs() < 1e-12 {
+            let u_sq = cos_sq_alpha * (WGS84_A * WGS84_A - b * b) / (b * b);
+            let big_a =
+                1.0 + u_sq / 16384.0 * (4096.0 + u_sq * (-768.0 + u_sq * (320.0 - 175.0 * u_sq)));
+            let big_b = u_sq / 1024.0 * (256.0 + u_sq * (-128.0 + u_sq * (74.0 - 47.0 * u_sq)));
+            let delta_sigma = big_b
+                * sin_sigma
+                * (cos_2sigma_m
+                    + big_b / 4.0
+                        * (cos_sigma * (-1.0 + 2.0 * cos_2sigma_m.powi(2))
+                            - big_b / 6.0
+                                * cos_2sigma_m
+                                * (-3.0 + 4.0 * sin_sigma.powi(2))
+                                * (-3.0 + 4.0 * cos_2sigma_m.powi(2))));
+            let distance = b * big_a * (sigma - delta_sigma);
+            let azimuth =
+                (cos_u2 * lambda.sin()).atan2(cos_u1 * sin_u2 - sin_u1 * cos_u2 * lambda.cos());
+            return Some((distance, azimuth));
         }
     }
 
-    output
+    None
 }
 
-/// Get the number of combinations that would be calculated
+/// Vincenty direct problem on the WGS-84 ellipsoid
+/// Returns (lat, lon) in degrees with lon in [-180, 180)
+fn vincenty_direct(lat: f64, lon: f64, azimuth: f64, distance_m: f64) -> (f64, f64) {
+    let b = WGS84_A * (1.0 - WGS84_F);
+    let (sin_alpha1, cos_alpha1) = azimuth.sin_cos();
+    let tan_u1 = (1.0 - WGS84_F) * to_rad(lat).tan();
+    let cos_u1 = 1.0 / (1.0 + tan_u1 * tan_u1).sqrt();
+    let sin_u1 = tan_u1 * cos_u1;
+    let sigma1 = tan_u1.atan2(cos_alpha1);
+    let sin_alpha = cos_u1 * sin_alpha1;
+    let cos_sq_alpha = 1.0 - sin_alpha * sin_alpha;
+    let u_sq = cos_sq_alpha * (WGS84_A * WGS84_A - b * b) / (b * b);
+    let big_a = 1.0 + u_sq / 16384.0 * (4096.0 + u_sq * (-768.0 + u_sq * (320.0 - 175.0 * u_sq)));
+    let big_b = u_sq / 1024.0 * (256.0 + u_sq * (-128.0 + u_sq * (74.0 - 47.0 * u_sq)));
+
+    let mut sigma = distance_m / (b * big_a);
+    let mut cos_2sigma_m = (2.0 * sigma1 + sigma).cos();
+    for _ in 0..VINCENTY_MAX_ITERATIONS {
+        cos_2sigma_m = (2.0 * sigma1 + sigma).cos();
+        let sin_sigma = sigma.sin();
+        let delta_sigma = big_b
+            * sin_sigma
+            * (cos_2sigma_m
+                + big_b / 4.0
+                    * (sigma.cos() * (-1.0 + 2.0 * cos_2sigma_m.powi(2))
+                        - big_b / 6.0
+                            * cos_2sigma_m
+                            * (-3.0 + 4.0 * sin_sigma.powi(2))
+                            * (-3.0 + 4.0 * cos_2sigma_m.powi(2))));
+        let sigma_prev = sigma;
+        sigma = distance_m / (b * big_a) + delta_sigma;
+        if (sigma - sigma_prev).abs() < 1e-12 {
+            break;
+        }
+    }
+
+    let (sin_sigma, cos_sigma) = sigma.sin_cos();
+    let x = sin_u1 * sin_sigma - cos_u1 * cos_sigma * cos_alpha1;
+    let lat2 = (sin_u1 * cos_sigma + cos_u1 * sin_sigma * cos_alpha1)
+        .atan2((1.0 - WGS84_F) * (sin_alpha * sin_alpha + x * x).sqrt());
+    let lambda =
+        (sin_sigma * sin_alpha1).atan2(cos_u1 * cos_sigma - sin_u1 * sin_sigma * cos_alpha1);
+    let c = WGS84_F / 16.0 * cos_sq_alpha * (4.0 + WGS84_F * (4.0 - 3.0 * cos_sq_alpha));
+    let l = lambda
+        - (1.0 - c)
+            * WGS84_F
+            * sin_alpha
+            * (sigma
+                + c * sin_sigma
+                    * (cos_2sigma_m + c * cos_sigma * (-1.0 + 2.0 * cos_2sigma_m.powi(2))));
+
+    (to_deg(lat2), normalize_longitude(lon + to_deg(l)))
+}
+
+/// Ellipsoidal midpoint as (lat, lon), NaN when Vincenty doesn't converge
+fn vincenty_midpoint_deg(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> (f64, f64) {
+    match vincenty_inverse(lat1, lon1, lat2, lon2) {
+        Some((distance, azimuth)) => vincenty_direct(lat1, lon1, azimuth, distance / 2.0),
+        None => (f64::NAN, f64::NAN),
+    }
+}
+
+/// Midpoint on the WGS-84 ellipsoid, halfway along the geodesic from point 1
+///
+/// More accurate than the spherical midpoint for long baselines.
+/// Returns [lat, lon], or [NaN, NaN] for nearly antipodal points where the
+/// Vincenty iteration does not converge.
 #[wasm_bindgen]
-pub fn get_combination_count(num_a: usize, num_b: usize) -> usize {
-    num_a * num_b
+pub fn vincenty_midpoint(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> Vec<f64> {
+    let (lat, lon) = vincenty_midpoint_deg(lat1, lon1, lat2, lon2);
+    vec![lat, lon]
+}
+
+/// Signed distance in km from a point to the great circle through start and end
+/// Negative values are left of the start->end direction, positive are right
+#[wasm_bindgen]
+pub fn cross_track_distance(
+    lat: f64,
+    lon: f64,
+    start_lat: f64,
+    start_lon: f64,
+    end_lat: f64,
+    end_lon: f64,
+) -> f64 {
+    let angular_13 = haversine_distance(start_lat, start_lon, lat, lon) / EARTH_RADIUS_KM;
+    let bearing_13 = to_rad(initial_bearing(start_lat, start_lon, lat, lon));
+    let bearing_12 = to_rad(initial_bearing(start_lat, start_lon, end_lat, end_lon));
+
+    (angular_13.sin() * (bearing_13 - bearing_12).sin()).asin() * EARTH_RADIUS_KM
+}
+
+/// Distance in km from start to the point's foot on the start->end great circle
+/// Negative when the foot lies behind the start
+#[wasm_bindgen]
+pub fn along_track_distance(
+    lat: f64,
+    lon: f64,
+    start_lat: f64,
+    start_lon: f64,
+    end_lat: f64,
+    end_lon: f64,
+) -> f64 {
+    let angular_13 = haversine_distance(start_lat, start_lon, lat, lon) / EARTH_RADIUS_KM;
+    let bearing_13 = to_rad(initial_bearing(start_lat, start_lon, lat, lon));
+    let bearing_12 = to_rad(initial_bearing(start_lat, start_lon, end_lat, end_lon));
+    let angular_xt = (angular_13.sin() * (bearing_13 - bearing_12).sin()).asin();
+
+    let along = (angular_13.cos() / angular_xt.cos())
+        .clamp(-1.0, 1.0)
+        .acos();
+    along.copysign((bearing_12 - bearing_13).cos()) * EARTH_RADIUS_KM
+}
+
+/// Nearest point to (lat, lon) on the great-circle segment from start to end
+///
+/// The foot of the perpendicular is clamped to the segment, so points beyond
+/// either end snap to that endpoint.
+/// Returns [projLat, projLon, distanceKm]
+#[wasm_bindgen]
+pub fn project_to_segment(
+    lat: f64,
+    lon: f64,
+    start_lat: f64,
+    start_lon: f64,
+    end_lat: f64,
+    end_lon: f64,
+) -> Vec<f64> {
+    let (proj_lat, proj_lon, distance_km) =
+        project_to_segment_deg(lat, lon, start_lat, start_lon, end_lat, end_lon);
+    vec![proj_lat, proj_lon, distance_km]
+}
+
+/// Segment projection as (projLat, projLon, distanceKm)
+fn project_to_segment_deg(
+    lat: f64,
+    lon: f64,
+    start_lat: f64,
+    start_lon: f64,
+    end_lat: f64,
+    end_lon: f64,
+) -> (f64, f64, f64) {
+    let length_km = haversine_distance(start_lat, start_lon, end_lat, end_lon);
+    let along_km = along_track_distance(lat, lon, start_lat, start_lon, end_lat, end_lon);
+
+    let (proj_lat, proj_lon) = if length_km == 0.0 || along_km <= 0.0 {
+        (start_lat, start_lon)
+    } else if along_km >= length_km {
+        (end_lat, end_lon)
+    } else {
+        let bearing = initial_bearing(start_lat, start_lon, end_lat, end_lon);
+        destination(start_lat, start_lon, bearing, along_km)
+    };
+
+    (
+        proj_lat,
+        proj_lon,
+        haversine_distance(lat, lon, proj_lat, proj_lon),
+    )
+}
+
+/// Intersection of the great circle through points 1-2 with the one through 3-4
+///
+/// The circles are treated as infinite, so the crossing need not lie within
+/// either segment. Of the two antipodal crossings, the one nearer the four
+/// input points is returned.
+/// Returns [lat, lon], or [NaN, NaN] when the circles coincide or a pair
+/// doesn't define a circle (identical or antipodal points).
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn intersection(
+    lat1: f64,
+    lon1: f64,
+    lat2: f64,
+    lon2: f64,
+    lat3: f64,
+    lon3: f64,
+    lat4: f64,
+    lon4: f64,
+) -> Vec<f64> {
+    let p1 = to_vector(lat1, lon1);
+    let p2 = to_vector(lat2, lon2);
+    let p3 = to_vector(lat3, lon3);
+    let p4 = to_vector(lat4, lon4);
+
+    // Each circle lies in the plane normal to n; the planes meet along i
+    let n1 = cross(p1, p2);
+    let n2 = cross(p3, p4);
+    let i = cross(n1, n2);
+    if norm(n1) < 1e-12 || norm(n2) < 1e-12 || norm(i) < 1e-12 {
+        return vec![f64::NAN, f64::NAN];
+    }
+
+    let center = [
+        p1[0] + p2[0] + p3[0] + p4[0],
+        p1[1] + p2[1] + p3[1] + p4[1],
+        p1[2] + p2[2] + p3[2] + p4[2],
+    ];
+    let i = if dot(i, center) < 0.0 {
+        [-i[0], -i[1], -i[2]]
+    } else {
+        i
+    };
+
+    let (lat, lon) = from_vector(i);
+    vec![lat, lon]
+}
+
+/// Longitudes where the great circle through points 1-2 reaches a latitude
+///
+/// Uses Clairaut's relation on the full great circle, not just the segment.
+/// A circle either misses a parallel, touches it at its highest latitude, or
+/// crosses it twice going north and going south.
+/// Returns [lon1, lon2] in [-180, 180), equal at a tangent, or [NaN, NaN]
+/// when the circle never reaches `target_lat` or the points don't define one.
+#[wasm_bindgen]
+pub fn crossing_latitude(lat1: f64, lon1: f64, lat2: f64, lon2: f64, target_lat: f64) -> Vec<f64> {
+    let phi1 = to_rad(lat1);
+    let phi2 = to_rad(lat2);
+    let phi = to_rad(target_lat);
+    let delta_lon = to_rad(lon2 - lon1);
+
+    let x = phi1.sin() * phi2.cos() * phi.cos() * delta_lon.sin();
+    let y =
+        phi1.sin() * phi2.cos() * phi.cos() * delta_lon.cos() - phi1.cos() * phi2.sin() * phi.cos();
+    let z = phi1.cos() * phi2.cos() * phi.sin() * delta_lon.sin();
+
+    let horizontal = (x * x + y * y).sqrt();
+    if horizontal < 1e-12 || z.abs() > horizontal {
+        return vec![f64::NAN, f64::NAN];
+    }
+
+    // Longitude of the circle's highest point, and the half-spread about it
+    let lon_max = (-y).atan2(x);
+    let spread = (z / horizontal).acos();
+
+    let normalize = |lon_rad: f64| normalize_longitude(to_deg(to_rad(lon1) + lon_rad));
+    vec![normalize(lon_max - spread), normalize(lon_max + spread)]
+}
+
+/// Latitude where the great circle through points 1-2 crosses a meridian
+///
+/// The full circle crosses `target_lon` once and the opposite meridian once;
+/// this is the crossing on `target_lon` itself.
+/// Returns NaN when the circle runs along the meridian or the points don't
+/// define a circle.
+#[wasm_bindgen]
+pub fn crossing_longitude(lat1: f64, lon1: f64, lat2: f64, lon2: f64, target_lon: f64) -> f64 {
+    let n = cross(to_vector(lat1, lon1), to_vector(lat2, lon2));
+    let lon = to_rad(target_lon);
+    let meridian_normal = [-lon.sin(), lon.cos(), 0.0];
+
+    let d = cross(n, meridian_normal);
+    if norm(n) < 1e-12 || norm(d) < 1e-12 * norm(n) {
+        return f64::NAN;
+    }
+
+    // Of the two antipodal directions, take the one on target_lon's side
+    let outward = d[0] * lon.cos() + d[1] * lon.sin();
+    let (up, out) = if outward < 0.0 {
+        (-d[2], -outward)
+    } else {
+        (d[2], outward)
+    };
+    to_deg(up.atan2(out))
+}
+
+/// Split a polyline wherever it crosses the antimeridian
+///
+/// Input array is flat: [lat0, lon0, lat1, lon1, ...], with lons in any
+/// range (e.g. the unwrapped output of `isoline`). Each step is taken the
+/// shorter way around, so one longer than 180 degrees counts as a crossing.
+/// There the path is cut at the great-circle crossing latitude: one sub-path
+/// ends on 180 (or -180) and the next starts at the same latitude on the
+/// other side. Sub-paths are separated by a [NaN, NaN] pair, and separators
+/// already in the input are kept, so the output can be split again.
+/// Returns flat array with lons in [-180, 180), except for cuts ending on 180
+#[wasm_bindgen]
+pub fn split_antimeridian(path: &[f64]) -> Vec<f64> {
+    let mut output = Vec::with_capacity(path.len() + 8);
+    let push = |lat: f64, lon: f64, output: &mut Vec<f64>| {
+        let len = output.len();
+        if len < 2 || output[len - 2] != lat || output[len - 1] != lon {
+            output.push(lat);
+            output.push(lon);
+        }
+    };
+
+    let mut prev: Option<(f64, f64)> = None;
+    for point in path.chunks_exact(2) {
+        let (lat, lon) = (point[0], normalize_longitude(point[1]));
+        if lat.is_nan() && lon.is_nan() {
+            output.extend_from_slice(&[f64::NAN, f64::NAN]);
+            prev = None;
+            continue;
+        }
+
+        if let Some((prev_lat, prev_lon)) = prev {
+            let step = lon - prev_lon;
+            if step.abs() > 180.0 {
+                // Heading east the cut is at 180, heading west at -180
+                let end_lon = if step < 0.0 { 180.0 } else { -180.0 };
+                let cross_lat = if prev_lon == -180.0 {
+                    prev_lat
+                } else if lon == -180.0 {
+                    lat
+                } else {
+                    let gc = crossing_longitude(prev_lat, prev_lon, lat, lon, 180.0);
+                    if gc.is_nan() {
+                        let t = (end_lon - prev_lon) / normalize_longitude(step);
+                        prev_lat + t * (lat - prev_lat)
+                    } else {
+                        gc
+                    }
+                };
+                push(cross_lat, end_lon, &mut output);
+                output.extend_from_slice(&[f64::NAN, f64::NAN]);
+                output.push(cross_lat);
+                output.push(-end_lon);
+            }
+        }
+        push(lat, lon, &mut output);
+        prev = Some((lat, lon));
+    }
+
+    output
+}
+
+/// Angular spread of the bearings from an anchor to each point
+///
+/// Input array is flat: [lat0, lon0, lat1, lon1, ...]
+/// Returns the smallest arc in degrees that contains every bearing, so a
+/// cluster straddling north (e.g. 350° to 20°) gives 30, not 330.
+/// Fewer than two points give 0.
+#[wasm_bindgen]
+pub fn direction_spread(points: &[f64], from_lat: f64, from_lon: f64) -> f64 {
+    let num_points = points.len() / 2;
+    if num_points < 2 {
+        return 0.0;
+    }
+
+    let mut bearings: Vec<f64> = (0..num_points)
+        .map(|i| initial_bearing(from_lat, from_lon, points[i * 2], points[i * 2 + 1]))
+        .collect();
+    bearings.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+    // Gaps between neighboring bearings, including the one that wraps
+    // through 0°. Around the circle they sum to a full turn, unless one
+    // exceeded a half turn and `bearing_difference` folded it: then every
+    // point lies in the half circle, and the folded gap's two ends bound the
+    // spread, which is the largest difference.
+    let gaps: Vec<f64> = (0..num_points)
+        .map(|i| bearing_difference(bearings[i], bearings[(i + 1) % num_points]))
+        .collect();
+    let largest_gap = gaps.iter().copied().fold(0.0, f64::max);
+    if gaps.iter().sum::<f64>() < 360.0 - 1e-9 {
+        largest_gap
+    } else {
+        360.0 - largest_gap
+    }
+}
+
+/// Find the two points of a set that are farthest apart
+///
+/// Input array is flat: [lat0, lon0, lat1, lon1, ...]
+/// Returns [indexA, indexB, distanceKm], or an empty array for fewer than two points.
+/// Checks every pair, so cost is O(n²) distance evaluations.
+#[wasm_bindgen]
+pub fn diameter(points: &[f64]) -> Vec<f64> {
+    let num_points = points.len() / 2;
+    if num_points < 2 {
+        return Vec::new();
+    }
+
+    let mut best = (0, 1, f64::NEG_INFINITY);
+    for i in 0..num_points {
+        for j in (i + 1)..num_points {
+            let distance = haversine_distance(
+                points[i * 2],
+                points[i * 2 + 1],
+                points[j * 2],
+                points[j * 2 + 1],
+            );
+            if distance > best.2 {
+                best = (i, j, distance);
+            }
+        }
+    }
+
+    vec![best.0 as f64, best.1 as f64, best.2]
+}
+
+/// Weighted sum of the unit vectors of a point set
+///
+/// Points without a matching weight are skipped.
+fn weighted_vector_sum(points: &[f64], weights: &[f64]) -> [f64; 3] {
+    let mut sum = [0.0; 3];
+    for (p, &w) in points.chunks_exact(2).zip(weights) {
+        let v = to_vector(p[0], p[1]);
+        sum[0] += w * v[0];
+        sum[1] += w * v[1];
+        sum[2] += w * v[2];
+    }
+    sum
+}
+
+/// Weighted geographic centroid, via the mean of the points' 3D unit vectors
+///
+/// Input arrays: points flat [lat0, lon0, ...], weights [w0, w1, ...]
+/// Points beyond the end of `weights` are ignored.
+/// Returns [lat, lon], or [NaN, NaN] when the weighted vectors cancel out
+/// (e.g. no weight, or an antipodal pair of equal weight)
+#[wasm_bindgen]
+pub fn weighted_centroid(points: &[f64], weights: &[f64]) -> Vec<f64> {
+    let sum = weighted_vector_sum(points, weights);
+    if norm(sum) < 1e-12 {
+        return vec![f64::NAN, f64::NAN];
+    }
+
+    let (lat, lon) = from_vector(sum);
+    vec![lat, lon]
+}
+
+/// Karcher (Fréchet) mean: the point minimizing the sum of squared great-circle distances
+///
+/// Input array is flat: [lat0, lon0, lat1, lon1, ...]
+/// `weighted_centroid` is the extrinsic mean (average the 3D vectors, project
+/// back to the sphere); this is the intrinsic one, found by gradient steps in
+/// the tangent plane starting from that centroid. They agree closely for
+/// tight clusters and drift apart as the spread grows. Iterates until the
+/// step is below `tol_km` or `max_iters` steps were taken; points antipodal
+/// to the current estimate pull in no direction and are ignored.
+/// Returns [lat, lon, iterations, finalStepKm], where the last is the size
+/// of the step still to take (the gradient norm as a distance); above
+/// `tol_km` means it did not converge. Empty array for no points.
+#[wasm_bindgen]
+pub fn karcher_mean(points: &[f64], max_iters: usize, tol_km: f64) -> Vec<f64> {
+    let num_points = points.len() / 2;
+    if num_points == 0 {
+        return Vec::new();
+    }
+
+    let vectors: Vec<[f64; 3]> = points
+        .chunks_exact(2)
+        .map(|p| to_vector(p[0], p[1]))
+        .collect();
+    let start = weighted_vector_sum(points, &vec![1.0; num_points]);
+    let mut mean = if norm(start) < 1e-12 {
+        vectors[0]
+    } else {
+        start
+    };
+    let length = norm(mean);
+    mean = [mean[0] / length, mean[1] / length, mean[2] / length];
+
+    let mut iterations = 0;
+    let step = loop {
+        // Average of the log maps: each point's direction in the tangent
+        // plane at the mean, scaled by its angular distance
+        let mut gradient = [0.0; 3];
+        for v in &vectors {
+            let cos_theta = dot(mean, *v).clamp(-1.0, 1.0);
+            let tangent = [
+                v[0] - cos_theta * mean[0],
+                v[1] - cos_theta * mean[1],
+                v[2] - cos_theta * mean[2],
+            ];
+            let sin_theta = norm(tangent);
+            if sin_theta < 1e-15 {
+                continue;
+            }
+            let scale = sin_theta.atan2(cos_theta) / sin_theta / num_points as f64;
+            gradient[0] += scale * tangent[0];
+            gradient[1] += scale * tangent[1];
+            gradient[2] += scale * tangent[2];
+        }
+
+        let angle = norm(gradient);
+        if angle * EARTH_RADIUS_KM < tol_km || iterations == max_iters {
+            break angle * EARTH_RADIUS_KM;
+        }
+
+        // Exponential map: walk `angle` along the gradient direction
+        let (sin_a, cos_a) = angle.sin_cos();
+        mean = [
+            cos_a * mean[0] + sin_a * gradient[0] / angle,
+            cos_a * mean[1] + sin_a * gradient[1] / angle,
+            cos_a * mean[2] + sin_a * gradient[2] / angle,
+        ];
+        iterations += 1;
+    };
+
+    let (lat, lon) = from_vector(mean);
+    vec![lat, lon, iterations as f64, step]
+}
+
+/// Nelder-Mead minimization of an N-dimensional function from a start point
+///
+/// The initial simplex has legs of `size` along each axis. Stops once every
+/// vertex is within `tol` of the best or after `max_iters` steps.
+/// Returns (best point, its value, steps taken)
+fn nelder_mead<const N: usize, F>(
+    f: F,
+    start: [f64; N],
+    size: f64,
+    tol: f64,
+    max_iters: usize,
+) -> ([f64; N], f64, usize)
+where
+    F: Fn([f64; N]) -> f64,
+{
+    let mut simplex: Vec<[f64; N]> = (0..=N)
+        .map(|k| {
+            let mut vertex = start;
+            if k > 0 {
+                vertex[k - 1] += size;
+            }
+            vertex
+        })
+        .collect();
+    let mut values: Vec<f64> = simplex.iter().map(|&v| f(v)).collect();
+    let lerp = |from: [f64; N], to: [f64; N], t: f64| -> [f64; N] {
+        std::array::from_fn(|d| from[d] + t * (to[d] - from[d]))
+    };
+
+    let mut iterations = 0;
+    loop {
+        // Order the vertices best to worst
+        let mut order: Vec<usize> = (0..=N).collect();
+        order.sort_by(|&i, &j| values[i].total_cmp(&values[j]));
+        simplex = order.iter().map(|&i| simplex[i]).collect();
+        values = order.iter().map(|&i| values[i]).collect();
+
+        let spread = simplex[1..]
+            .iter()
+            .map(|&v| plane_distance(v, simplex[0]))
+            .fold(0.0, f64::max);
+        if spread < tol || iterations == max_iters {
+            return (simplex[0], values[0], iterations);
+        }
+        iterations += 1;
+
+        let centroid: [f64; N] =
+            std::array::from_fn(|d| simplex[..N].iter().map(|v| v[d]).sum::<f64>() / N as f64);
+        let reflected = lerp(simplex[N], centroid, 2.0);
+        let reflected_value = f(reflected);
+        if reflected_value < values[0] {
+            let expanded = lerp(simplex[N], centroid, 3.0);
+            let expanded_value = f(expanded);
+            (simplex[N], values[N]) = if expanded_value < reflected_value {
+                (expanded, expanded_value)
+            } else {
+                (reflected, reflected_value)
+            };
+        } else if reflected_value < values[N - 1] {
+            (simplex[N], values[N]) = (reflected, reflected_value);
+        } else {
+            let contracted = lerp(simplex[N], centroid, 0.5);
+            let contracted_value = f(contracted);
+            if contracted_value < values[N] {
+                (simplex[N], values[N]) = (contracted, contracted_value);
+            } else {
+                // Shrink everything toward the best vertex
+                for k in 1..=N {
+                    simplex[k] = lerp(simplex[0], simplex[k], 0.5);
+                    values[k] = f(simplex[k]);
+                }
+            }
+        }
+    }
+}
+
+/// Euclidean distance between two points of a Nelder-Mead search space
+fn plane_distance<const N: usize>(p: [f64; N], q: [f64; N]) -> f64 {
+    p.iter()
+        .zip(&q)
+        .map(|(a, b)| (a - b) * (a - b))
+        .sum::<f64>()
+        .sqrt()
+}
+
+/// Nelder-Mead, restarted from its best point until a restart moves it less
+/// than `tol` or improves nothing
+///
+/// Restarts get past the ridges of non-smooth objectives, where a single
+/// run can stall. At most `max_iters` steps are taken in total.
+/// Returns (best point, its value), never worse than the start
+fn nelder_mead_restarted<const N: usize, F>(
+    f: F,
+    start: [f64; N],
+    size: f64,
+    tol: f64,
+    max_iters: usize,
+) -> ([f64; N], f64)
+where
+    F: Fn([f64; N]) -> f64,
+{
+    let (mut best, mut best_value) = (start, f(start));
+    let mut iterations = 0;
+    while iterations < max_iters {
+        let (found, value, used) = nelder_mead(&f, best, size, tol, max_iters - iterations);
+        iterations += used.max(1);
+        if value >= best_value {
+            break;
+        }
+        let moved = plane_distance(found, best);
+        (best, best_value) = (found, value);
+        if moved < tol {
+            break;
+        }
+    }
+
+    (best, best_value)
+}
+
+/// What `optimize_meeting_point` minimizes
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Objective {
+    /// Total distance from every point (the geometric median)
+    #[default]
+    TotalTravel,
+    /// Largest distance from any point (the minimax center)
+    MaxTravel,
+}
+
+/// Best meeting point anywhere for one group, not just at a candidate
+///
+/// Input array is flat: [lat0, lon0, lat1, lon1, ...]
+/// Minimizes the objective over great-circle distances with Nelder-Mead in
+/// the azimuthal-equidistant tangent plane at the centroid (the medoid if
+/// the points cancel out), starting from a simplex as wide as the mean
+/// distance to it. The total-travel surface is convex and converges to the
+/// geometric median. The max-travel surface has ridges where the farthest
+/// point changes, on which Nelder-Mead can stall short of the optimum, so
+/// the search restarts from its best point until a restart moves it less
+/// than `tol_km`. Either way it stops after `max_iters` steps in total.
+/// Returns [lat, lon, objectiveValueKm], or an empty array for no points
+#[wasm_bindgen]
+pub fn optimize_meeting_point(
+    points: &[f64],
+    objective: Objective,
+    max_iters: usize,
+    tol_km: f64,
+) -> Vec<f64> {
+    let num_points = points.len() / 2;
+    if num_points == 0 {
+        return Vec::new();
+    }
+
+    let centroid = weighted_centroid(points, &vec![1.0; num_points]);
+    let (seed_lat, seed_lon) = if centroid[0].is_nan() {
+        let center = medoid(points);
+        (center[1], center[2])
+    } else {
+        (centroid[0], centroid[1])
+    };
+
+    // Plane coordinates are km east and north of the seed
+    let to_point = |v: [f64; 2]| {
+        if v == [0.0, 0.0] {
+            (seed_lat, seed_lon)
+        } else {
+            destination(
+                seed_lat,
+                seed_lon,
+                to_deg(v[0].atan2(v[1])),
+                v[0].hypot(v[1]),
+            )
+        }
+    };
+    let cost = |v: [f64; 2]| {
+        let (lat, lon) = to_point(v);
+        let distances = points
+            .chunks_exact(2)
+            .map(|p| haversine_distance(lat, lon, p[0], p[1]));
+        match objective {
+            Objective::TotalTravel => distances.sum(),
+            Objective::MaxTravel => distances.fold(0.0, f64::max),
+        }
+    };
+
+    let mean_km = points
+        .chunks_exact(2)
+        .map(|p| haversine_distance(seed_lat, seed_lon, p[0], p[1]))
+        .sum::<f64>()
+        / num_points as f64;
+    let size = mean_km.max(tol_km);
+
+    let (best, best_value) = nelder_mead_restarted(cost, [0.0, 0.0], size, tol_km, max_iters);
+
+    let (lat, lon) = to_point(best);
+    vec![lat, lon, best_value]
+}
+
+/// Spherical variance of a point set: 1 - R̄, with R̄ the length of the mean unit vector
+///
+/// Input array is flat: [lat0, lon0, lat1, lon1, ...]
+/// Returns a value in [0, 1]: 0 when all points coincide, approaching 1 as
+/// they spread evenly around the globe (an antipodal pair gives 1). NaN for
+/// no points.
+#[wasm_bindgen]
+pub fn spherical_variance(points: &[f64]) -> f64 {
+    let num_points = points.len() / 2;
+    let weights = vec![1.0; num_points];
+    let mean_length = norm(weighted_vector_sum(points, &weights)) / num_points as f64;
+
+    (1.0 - mean_length).clamp(0.0, 1.0)
+}
+
+/// Center-refinement rounds in `covering_circle_quantile`
+const COVERING_REFINE_ROUNDS: usize = 3;
+
+/// Approximate smallest circle covering a fraction of the points
+///
+/// Input array is flat: [lat0, lon0, lat1, lon1, ...]
+/// Heuristic: start from the centroid of all points, take the radius that
+/// covers ceil(fraction · n) of them by distance, then re-center on the
+/// centroid of just those covered points and repeat a few rounds, keeping the
+/// smallest circle found. Outliers outside the fraction stop pulling the
+/// center after the first round. Not the exact minimum, which is far costlier.
+/// `fraction` is clamped to (0, 1]; at least one point is covered.
+/// Returns [centerLat, centerLon, radiusKm], or an empty array for no points.
+#[wasm_bindgen]
+pub fn covering_circle_quantile(points: &[f64], fraction: f64) -> Vec<f64> {
+    let num_points = points.len() / 2;
+    if num_points == 0 {
+        return Vec::new();
+    }
+    let covered = ((fraction.min(1.0) * num_points as f64).ceil() as usize).clamp(1, num_points);
+
+    let mut center = weighted_centroid(points, &vec![1.0; num_points]);
+    if center[0].is_nan() {
+        center = vec![points[0], points[1]];
+    }
+
+    let mut best = vec![f64::NAN, f64::NAN, f64::INFINITY];
+    for _ in 0..=COVERING_REFINE_ROUNDS {
+        let mut by_distance: Vec<(f64, usize)> = (0..num_points)
+            .map(|i| {
+                (
+                    haversine_distance(center[0], center[1], points[i * 2], points[i * 2 + 1]),
+                    i,
+                )
+            })
+            .collect();
+        by_distance.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+
+        let radius_km = by_distance[covered - 1].0;
+        if radius_km < best[2] {
+            best = vec![center[0], center[1], radius_km];
+        }
+
+        let mut weights = vec![0.0; num_points];
+        for &(_, i) in &by_distance[..covered] {
+            weights[i] = 1.0;
+        }
+        let next = weighted_centroid(points, &weights);
+        if next[0].is_nan() {
+            break;
+        }
+        center = next;
+    }
+
+    best
+}
+
+/// Demand-weighted meeting point with its total demand and spread
+///
+/// Input arrays: points flat [lat0, lon0, ...], weights [w0, w1, ...] such as
+/// population; points beyond the end of `weights` are ignored.
+/// Returns [lat, lon, totalWeight, meanWeightedDistKm], where the last is
+/// sum(w * distance to center) / sum(w). Center and distance are NaN when
+/// `weighted_centroid` is undefined.
+#[wasm_bindgen]
+pub fn demand_center(points: &[f64], weights: &[f64]) -> Vec<f64> {
+    let center = weighted_centroid(points, weights);
+    let (lat, lon) = (center[0], center[1]);
+
+    let mut total_weight = 0.0;
+    let mut weighted_distance = 0.0;
+    for (p, &w) in points.chunks_exact(2).zip(weights) {
+        total_weight += w;
+        weighted_distance += w * haversine_distance(lat, lon, p[0], p[1]);
+    }
+
+    vec![lat, lon, total_weight, weighted_distance / total_weight]
+}
+
+/// Weighted centroid with each point weighted by 1 / accuracy², so vague
+/// geocodes pull the center less
+///
+/// `accuracy_m` holds one accuracy radius in meters per point. A radius of 0
+/// means an exact fix and outweighs any nonzero one: if some radii are 0,
+/// only those points count, equally.
+/// Returns [lat, lon] as `weighted_centroid`; errors if the array lengths
+/// disagree or a radius is negative or NaN
+#[wasm_bindgen]
+pub fn accuracy_weighted_center(points: &[f64], accuracy_m: &[f64]) -> Result<Vec<f64>, JsError> {
+    if points.len() != accuracy_m.len() * 2 {
+        return Err(JsError::new(&format!(
+            "{} accuracy radii for {} points",
+            accuracy_m.len(),
+            points.len() / 2
+        )));
+    }
+    if let Some(bad) = accuracy_m.iter().find(|r| r.is_nan() || **r < 0.0) {
+        return Err(JsError::new(&format!(
+            "accuracy radius must be non-negative, got {bad}"
+        )));
+    }
+
+    let weights: Vec<f64> = if accuracy_m.contains(&0.0) {
+        accuracy_m
+            .iter()
+            .map(|&r| if r == 0.0 { 1.0 } else { 0.0 })
+            .collect()
+    } else {
+        accuracy_m.iter().map(|&r| 1.0 / (r * r)).collect()
+    };
+
+    Ok(weighted_centroid(points, &weights))
+}
+
+/// Find the input point with the smallest total distance to all the others
+///
+/// Input array is flat: [lat0, lon0, lat1, lon1, ...]
+/// Returns [index, lat, lon] of that point, or an empty array for no points.
+/// Unlike a centroid the result is always one of the inputs. Each pair's
+/// distance is computed once and added to both ends, so cost is n(n-1)/2
+/// distance evaluations; ties go to the lowest index.
+#[wasm_bindgen]
+pub fn medoid(points: &[f64]) -> Vec<f64> {
+    let num_points = points.len() / 2;
+    if num_points == 0 {
+        return Vec::new();
+    }
+
+    let mut totals = vec![0.0; num_points];
+    for i in 0..num_points {
+        for j in (i + 1)..num_points {
+            let distance = haversine_distance(
+                points[i * 2],
+                points[i * 2 + 1],
+                points[j * 2],
+                points[j * 2 + 1],
+            );
+            totals[i] += distance;
+            totals[j] += distance;
+        }
+    }
+
+    let mut best = 0;
+    for (i, &total) in totals.iter().enumerate() {
+        if total < totals[best] {
+            best = i;
+        }
+    }
+
+    vec![best as f64, points[best * 2], points[best * 2 + 1]]
+}
+
+/// Count the points within each of several radii of a target
+///
+/// Input array is flat: [lat0, lon0, lat1, lon1, ...]
+/// Returns one count per radius, in the order given. Counts are cumulative:
+/// a point counts toward every radius it is within (distance <= radius).
+#[wasm_bindgen]
+pub fn radial_counts(
+    points: &[f64],
+    target_lat: f64,
+    target_lon: f64,
+    radii_km: &[f64],
+) -> Vec<u32> {
+    // Bucket each point by the smallest radius that contains it
+    let mut order: Vec<usize> = (0..radii_km.len()).collect();
+    order.sort_by(|&a, &b| {
+        radii_km[a]
+            .partial_cmp(&radii_km[b])
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    let sorted_radii: Vec<f64> = order.iter().map(|&i| radii_km[i]).collect();
+
+    let mut buckets = vec![0u32; radii_km.len()];
+    for p in points.chunks_exact(2) {
+        let distance = haversine_distance(p[0], p[1], target_lat, target_lon);
+        let bucket = sorted_radii.partition_point(|&r| r < distance);
+        if bucket < buckets.len() {
+            buckets[bucket] += 1;
+        }
+    }
+
+    // Running totals make each radius include everything closer
+    let mut counts = vec![0u32; radii_km.len()];
+    let mut total = 0;
+    for (bucket, &radius_index) in buckets.iter().zip(&order) {
+        total += bucket;
+        counts[radius_index] = total;
+    }
+
+    counts
+}
+
+/// How many members of each group are within `max_km` of a candidate point
+///
+/// Input arrays are flat: [lat0, lon0, lat1, lon1, ...]
+/// Unlike `radial_counts` this is one radius, counted per group.
+/// Returns [countA, countB], with distance <= max_km counting as reachable
+#[wasm_bindgen]
+pub fn reachability(
+    candidate_lat: f64,
+    candidate_lon: f64,
+    points_a: &[f64],
+    points_b: &[f64],
+    max_km: f64,
+) -> Vec<u32> {
+    [points_a, points_b]
+        .iter()
+        .map(|points| {
+            points
+                .chunks_exact(2)
+                .filter(|p| haversine_distance(candidate_lat, candidate_lon, p[0], p[1]) <= max_km)
+                .count() as u32
+        })
+        .collect()
+}
+
+/// Nearest point of a flat [lat0, lon0, ...] array to a location
+/// Returns (index, distanceKm), or None for an empty array; ties go to the lowest index
+fn nearest_index(lat: f64, lon: f64, points: &[f64]) -> Option<(usize, f64)> {
+    points
+        .chunks_exact(2)
+        .map(|p| haversine_distance(lat, lon, p[0], p[1]))
+        .enumerate()
+        .fold(
+            None,
+            |best: Option<(usize, f64)>, (i, distance)| match best {
+                Some((_, best_distance)) if best_distance <= distance => best,
+                _ => Some((i, distance)),
+            },
+        )
+}
+
+/// How much farther each participant of a chosen pair travels than they had to
+///
+/// Both participants travel to the midpoint of their pair. A participant's
+/// personal best is the shortest trip they could have had by pairing with any
+/// member of the other group instead, which is half the distance to that
+/// group's nearest member.
+/// Returns [travelA, bestTravelA, sacrificeA, bestIndexB,
+///          travelB, bestTravelB, sacrificeB, bestIndexA] in km, where
+/// bestIndexB is the B member that would give A its personal best (and vice
+/// versa). Returns an empty array if an index is out of range.
+#[wasm_bindgen]
+pub fn participant_sacrifice(
+    points_a: &[f64],
+    points_b: &[f64],
+    index_a: usize,
+    index_b: usize,
+) -> Vec<f64> {
+    if index_a >= points_a.len() / 2 || index_b >= points_b.len() / 2 {
+        return Vec::new();
+    }
+
+    let (lat_a, lon_a) = (points_a[index_a * 2], points_a[index_a * 2 + 1]);
+    let (lat_b, lon_b) = (points_b[index_b * 2], points_b[index_b * 2 + 1]);
+
+    // Both travel half the pair's separation to reach the midpoint
+    let travel = haversine_distance(lat_a, lon_a, lat_b, lon_b) / 2.0;
+
+    let mut output = Vec::with_capacity(8);
+    for (lat, lon, others) in [(lat_a, lon_a, points_b), (lat_b, lon_b, points_a)] {
+        // The nearest member of the other group is never farther than the
+        // chosen partner, so it always exists here
+        let (best_index, best_distance) = nearest_index(lat, lon, others).unwrap_or((0, 0.0));
+        let best_travel = best_distance / 2.0;
+        output.extend([travel, best_travel, travel - best_travel, best_index as f64]);
+    }
+
+    output
+}
+
+/// Candidate nearest to the geodesic midpoint of two points
+///
+/// For "meet at the cafe closest to the ideal spot": `candidates` is a flat
+/// [lat0, lon0, ...] array, and the pick is by distance to the midpoint, not
+/// to either endpoint. Ties go to the lowest index.
+/// Returns [candidateIndex, candidateLat, candidateLon, distToMidpointKm], or
+/// an empty array when there are no candidates
+#[wasm_bindgen]
+pub fn midpoint_then_nearest(
+    lat1: f64,
+    lon1: f64,
+    lat2: f64,
+    lon2: f64,
+    candidates: &[f64],
+) -> Vec<f64> {
+    let (mid_lat, mid_lon) =
+        geodesic_midpoint_trig(&TrigPoint::new(lat1, lon1), &TrigPoint::new(lat2, lon2));
+    match nearest_index(mid_lat, mid_lon, candidates) {
+        Some((index, distance)) => {
+            vec![
+                index as f64,
+                candidates[index * 2],
+                candidates[index * 2 + 1],
+                distance,
+            ]
+        }
+        None => Vec::new(),
+    }
+}
+
+/// Which of several groups a point belongs to, by its nearest group centroid
+///
+/// `group_centroids` is a flat [lat0, lon0, ...] array, one centroid per
+/// group (e.g. from `weighted_centroid`), compared by haversine distance.
+/// Ties go to the lowest group index.
+/// Returns [nearestGroupIndex, distKm], or an empty array when there are no
+/// groups
+#[wasm_bindgen]
+pub fn classify_point(query_lat: f64, query_lon: f64, group_centroids: &[f64]) -> Vec<f64> {
+    match nearest_index(query_lat, query_lon, group_centroids) {
+        Some((index, distance)) => vec![index as f64, distance],
+        None => Vec::new(),
+    }
+}
+
+/// Rank given venues by how far the two groups have to travel to them
+///
+/// Each candidate is reached by the nearest member of each group, and their
+/// two distances are combined by `mode` (`TargetProximity` and
+/// `PickupRoute` count as `SumTravel`). Nearest members minimize every
+/// distance mode, so that pick is the best one per candidate, in
+/// O(candidates * (|A| + |B|)). `ArrivalSkew` is an error: its best pair
+/// is not the nearest one, and its times need the group speeds.
+/// Returns flat array: [candidateIndex, score, bestAIndex, bestBIndex, ...]
+/// sorted by score, ties by candidate index; empty if either group is empty
+#[wasm_bindgen]
+pub fn score_candidates(
+    candidates: &[f64],
+    points_a: &[f64],
+    points_b: &[f64],
+    mode: ScoringMode,
+) -> Result<Vec<f64>, JsError> {
+    if mode == ScoringMode::ArrivalSkew {
+        return Err(JsError::new("candidates cannot be scored by arrival skew"));
+    }
+
+    let mut scored = Vec::with_capacity(candidates.len() / 2);
+    for (i, c) in candidates.chunks_exact(2).enumerate() {
+        let (Some((best_a, da)), Some((best_b, db))) = (
+            nearest_index(c[0], c[1], points_a),
+            nearest_index(c[0], c[1], points_b),
+        ) else {
+            return Ok(Vec::new());
+        };
+        scored.push((i, combine_travel(mode, da, db), best_a, best_b));
+    }
+    scored.sort_by(|x, y| x.1.total_cmp(&y.1));
+
+    Ok(scored
+        .into_iter()
+        .flat_map(|(i, score, best_a, best_b)| [i as f64, score, best_a as f64, best_b as f64])
+        .collect())
+}
+
+/// Distance in km at which the proximity part of `midpoint_quality` falls to 1/e
+const QUALITY_PROXIMITY_SCALE_KM: f64 = 25.0;
+
+/// Weight of proximity against fairness in `midpoint_quality`
+const QUALITY_PROXIMITY_WEIGHT: f64 = 0.5;
+
+/// Blend of target proximity and travel fairness for one pair, from 0 to 100
+///
+/// Proximity is exp(-d / 25 km) for the midpoint's distance d to the target.
+/// Fairness is the shorter of the two participants' distances to the target
+/// divided by the longer (1 when equal). The two are averaged with equal
+/// weight and scaled to 100.
+#[wasm_bindgen]
+pub fn midpoint_quality(
+    lat_a: f64,
+    lon_a: f64,
+    lat_b: f64,
+    lon_b: f64,
+    target_lat: f64,
+    target_lon: f64,
+) -> f64 {
+    let (mid_lat, mid_lon) =
+        geodesic_midpoint_trig(&TrigPoint::new(lat_a, lon_a), &TrigPoint::new(lat_b, lon_b));
+    let proximity = (-haversine_distance(mid_lat, mid_lon, target_lat, target_lon)
+        / QUALITY_PROXIMITY_SCALE_KM)
+        .exp();
+
+    let dist_a = haversine_distance(lat_a, lon_a, target_lat, target_lon);
+    let dist_b = haversine_distance(lat_b, lon_b, target_lat, target_lon);
+    let longer = dist_a.max(dist_b);
+    let fairness = if longer > 0.0 {
+        dist_a.min(dist_b) / longer
+    } else {
+        1.0
+    };
+
+    100.0 * (QUALITY_PROXIMITY_WEIGHT * proximity + (1.0 - QUALITY_PROXIMITY_WEIGHT) * fairness)
+}
+
+/// Whether a point lies on the segment between two vertices (planar lat/lon)
+#[inline]
+fn on_segment(lat: f64, lon: f64, lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> bool {
+    let cross = (lat2 - lat1) * (lon - lon1) - (lon2 - lon1) * (lat - lat1);
+    let scale = (lat2 - lat1).abs().max((lon2 - lon1).abs()).max(1.0);
+    cross.abs() <= 1e-12 * scale
+        && lat >= lat1.min(lat2)
+        && lat <= lat1.max(lat2)
+        && lon >= lon1.min(lon2)
+        && lon <= lon1.max(lon2)
+}
+
+/// Check whether a point lies inside a polygon ring
+///
+/// Polygon is flat: [lat0, lon0, lat1, lon1, ...], implicitly closed.
+/// Uses ray casting with edges as straight lines in lat/lon, which suits
+/// regional polygons that don't cross the antimeridian. Points on the
+/// boundary count as inside.
+#[wasm_bindgen]
+pub fn point_in_polygon(lat: f64, lon: f64, polygon: &[f64]) -> bool {
+    let num_vertices = polygon.len() / 2;
+    if num_vertices < 3 {
+        return false;
+    }
+
+    let mut inside = false;
+    let mut j = num_vertices - 1;
+    for i in 0..num_vertices {
+        let (lat_i, lon_i) = (polygon[i * 2], polygon[i * 2 + 1]);
+        let (lat_j, lon_j) = (polygon[j * 2], polygon[j * 2 + 1]);
+
+        if on_segment(lat, lon, lat_i, lon_i, lat_j, lon_j) {
+            return true;
+        }
+
+        // Count edges crossed by a ray heading east (increasing lon)
+        if (lat_i > lat) != (lat_j > lat) {
+            let crossing_lon = lon_i + (lat - lat_i) * (lon_j - lon_i) / (lat_j - lat_i);
+            if lon < crossing_lon {
+                inside = !inside;
+            }
+        }
+        j = i;
+    }
+
+    inside
+}
+
+/// Indices of the points that lie inside a polygon ring
+///
+/// Same polygon format and boundary rule as `point_in_polygon`
+#[wasm_bindgen]
+pub fn points_within_polygon(points: &[f64], polygon: &[f64]) -> Vec<u32> {
+    points
+        .chunks_exact(2)
+        .enumerate()
+        .filter(|(_, p)| point_in_polygon(p[0], p[1], polygon))
+        .map(|(i, _)| i as u32)
+        .collect()
+}
+
+/// Signed distance in km from a point to the edge of a polygon ring
+///
+/// Same polygon format as `point_in_polygon`. The magnitude is the distance
+/// to the nearest great-circle edge (see `project_to_segment`); it is
+/// negative when `point_in_polygon` puts the point inside, and ~0 on the
+/// boundary. Inside/outside uses that function's straight lat/lon edges, so
+/// it suits the same regional polygons. Rings of one or two vertices have
+/// no inside and give the plain distance; an empty ring gives NaN.
+#[wasm_bindgen]
+pub fn distance_to_polygon_boundary(lat: f64, lon: f64, polygon: &[f64]) -> f64 {
+    let num_vertices = polygon.len() / 2;
+    if num_vertices == 0 {
+        return f64::NAN;
+    }
+    if num_vertices == 1 {
+        return haversine_distance(lat, lon, polygon[0], polygon[1]);
+    }
+
+    let distance_km = (0..num_vertices)
+        .map(|i| {
+            let j = (i + 1) % num_vertices;
+            project_to_segment_deg(
+                lat,
+                lon,
+                polygon[i * 2],
+                polygon[i * 2 + 1],
+                polygon[j * 2],
+                polygon[j * 2 + 1],
+            )
+            .2
+        })
+        .fold(f64::INFINITY, f64::min);
+
+    if point_in_polygon(lat, lon, polygon) {
+        -distance_km
+    } else {
+        distance_km
+    }
+}
+
+/// Format one coordinate as whole degrees, minutes and rounded seconds
+fn format_dms_component(value: f64, positive: char, negative: char) -> String {
+    let hemisphere = if value < 0.0 { negative } else { positive };
+    let total_seconds = (value.abs() * 3600.0).round() as u64;
+    let degrees = total_seconds / 3600;
+    let minutes = (total_seconds % 3600) / 60;
+    let seconds = total_seconds % 60;
+
+    format!("{}°{}'{}\"{}", degrees, minutes, seconds, hemisphere)
+}
+
+/// Format a coordinate as degrees-minutes-seconds, e.g. 40°26'46"N 79°58'56"W
+/// Seconds are rounded to the nearest whole second
+#[wasm_bindgen]
+pub fn format_dms(lat: f64, lon: f64) -> String {
+    format!(
+        "{} {}",
+        format_dms_component(lat, 'N', 'S'),
+        format_dms_component(lon, 'E', 'W')
+    )
+}
+
+/// Parse one coordinate in DMS or decimal form
+/// Returns the signed value and the hemisphere letter, if there was one
+fn parse_dms_component(part: &str) -> Result<(f64, Option<char>), String> {
+    let part = part.trim();
+    let mut hemisphere = None;
+    let mut body = part;
+    if let Some(c) = part.chars().last().filter(|c| c.is_ascii_alphabetic()) {
+        hemisphere = Some(c.to_ascii_uppercase());
+        body = &part[..part.len() - 1];
+    } else if let Some(c) = part.chars().next().filter(|c| c.is_ascii_alphabetic()) {
+        hemisphere = Some(c.to_ascii_uppercase());
+        body = &part[1..];
+    }
+    if !matches!(hemisphere, None | Some('N' | 'S' | 'E' | 'W')) {
+        return Err(format!("unknown hemisphere in \"{}\"", part));
+    }
+
+    let fields: Vec<&str> = body
+        .split(|c: char| c.is_whitespace() || matches!(c, '°' | '\'' | '"' | '′' | '″'))
+        .filter(|f| !f.is_empty())
+        .collect();
+    if fields.is_empty() || fields.len() > 3 {
+        return Err(format!(
+            "expected degrees, minutes and seconds in \"{}\"",
+            part
+        ));
+    }
+
+    let mut values = [0.0; 3];
+    for (value, field) in values.iter_mut().zip(&fields) {
+        *value = field
+            .parse::<f64>()
+            .map_err(|_| format!("invalid number \"{}\" in \"{}\"", field, part))?;
+    }
+    let [degrees, minutes, seconds] = values;
+
+    let negative_degrees = fields[0].starts_with('-');
+    if negative_degrees && hemisphere.is_some() {
+        return Err(format!("sign and hemisphere both given in \"{}\"", part));
+    }
+    if !(0.0..60.0).contains(&minutes) || !(0.0..60.0).contains(&seconds) {
+        return Err(format!(
+            "minutes and seconds must be in [0, 60) in \"{}\"",
+            part
+        ));
+    }
+
+    let magnitude = degrees.abs() + minutes / 60.0 + seconds / 3600.0;
+    let negative = negative_degrees || matches!(hemisphere, Some('S' | 'W'));
+
+    Ok((if negative { -magnitude } else { magnitude }, hemisphere))
+}
+
+/// Parse a coordinate pair written as DMS or signed decimal degrees
+fn parse_dms_pair(s: &str) -> Result<(f64, f64), String> {
+    let s = s.trim();
+
+    // Split on a comma, after a trailing hemisphere letter, before a leading
+    // one, or between two plain numbers
+    let split_at = if let Some(i) = s.find(',') {
+        Some((i, i + 1))
+    } else {
+        let letters: Vec<usize> = s
+            .char_indices()
+            .filter(|(_, c)| matches!(c.to_ascii_uppercase(), 'N' | 'S' | 'E' | 'W'))
+            .map(|(i, _)| i)
+            .collect();
+        match letters.as_slice() {
+            [first, second] if *first == 0 => Some((*second, *second)),
+            [first, _] => Some((first + 1, first + 1)),
+            [only] if *only > 0 => Some((only + 1, only + 1)),
+            [] => s.find(char::is_whitespace).map(|i| (i, i)),
+            _ => None,
+        }
+    };
+    let (first_end, second_start) =
+        split_at.ok_or_else(|| format!("expected two coordinates in \"{}\"", s))?;
+
+    let (first, first_hemisphere) = parse_dms_component(&s[..first_end])?;
+    let (second, second_hemisphere) = parse_dms_component(&s[second_start..])?;
+
+    // Hemisphere letters may put longitude first
+    let is_lon = |h: Option<char>| matches!(h, Some('E' | 'W'));
+    let is_lat = |h: Option<char>| matches!(h, Some('N' | 'S'));
+    let (lat, lon) = if is_lon(first_hemisphere) || is_lat(second_hemisphere) {
+        (second, first)
+    } else {
+        (first, second)
+    };
+    if first_hemisphere.is_some()
+        && second_hemisphere.is_some()
+        && is_lat(first_hemisphere) == is_lat(second_hemisphere)
+    {
+        return Err(format!(
+            "both coordinates are on the same axis in \"{}\"",
+            s
+        ));
+    }
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+        return Err(format!("coordinate out of range in \"{}\"", s));
+    }
+
+    Ok((lat, lon))
+}
+
+/// Parse a degrees-minutes-seconds coordinate pair
+///
+/// Accepts N/S/E/W as prefixes or suffixes (e.g. 40°26'46"N 79°58'56"W),
+/// signed decimal degrees (e.g. "40.446, -79.982"), and mixes of the two.
+/// Without hemisphere letters the pair is read as lat, lon.
+/// Returns [lat, lon], or an error for malformed input.
+#[wasm_bindgen]
+pub fn parse_dms(s: &str) -> Result<Vec<f64>, JsError> {
+    parse_dms_pair(s)
+        .map(|(lat, lon)| vec![lat, lon])
+        .map_err(|message| JsError::new(&message))
+}
+
+/// Base-32 alphabet used by geohashes
+const GEOHASH_ALPHABET: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// Longest geohash produced; 12 characters is already a cell of a few cm
+const GEOHASH_MAX_PRECISION: usize = 12;
+
+/// Geohash of a point with `precision` characters (clamped to 1-12)
+///
+/// Each character narrows the cell about 32-fold: 5 characters is roughly a
+/// 4.9 × 4.9 km cell, 7 is 153 × 153 m, 9 is 4.8 × 4.8 m. Points sharing a
+/// prefix share the cell of that length.
+#[wasm_bindgen]
+pub fn geohash_encode(lat: f64, lon: f64, precision: usize) -> String {
+    let (hash, len) = geohash_bytes(lat, lon, precision);
+    hash[..len].iter().map(|&c| c as char).collect()
+}
+
+/// Geohash as ASCII bytes without allocating, for the hot loops
+/// Returns the buffer and the number of characters used (1-12)
+fn geohash_bytes(lat: f64, lon: f64, precision: usize) -> ([u8; GEOHASH_MAX_PRECISION], usize) {
+    let precision = precision.clamp(1, GEOHASH_MAX_PRECISION);
+    let mut lat_range = (-90.0, 90.0);
+    let mut lon_range = (-180.0, 180.0);
+
+    let mut hash = [0u8; GEOHASH_MAX_PRECISION];
+    let mut even_bit = true;
+    for slot in hash.iter_mut().take(precision) {
+        let mut index = 0;
+        for _ in 0..5 {
+            // Bits alternate between longitude and latitude, longitude first
+            let (range, value) = if even_bit {
+                (&mut lon_range, lon)
+            } else {
+                (&mut lat_range, lat)
+            };
+            let mid = (range.0 + range.1) / 2.0;
+            index <<= 1;
+            if value >= mid {
+                index |= 1;
+                range.0 = mid;
+            } else {
+                range.1 = mid;
+            }
+            even_bit = !even_bit;
+        }
+        *slot = GEOHASH_ALPHABET[index];
+    }
+
+    (hash, precision)
+}
+
+/// Convex hull of a point set, treating lat/lon as planar coordinates
+///
+/// Input array is flat: [lat0, lon0, lat1, lon1, ...]
+/// Returns the hull vertices flat in counter-clockwise order (lon as x, lat
+/// as y), without repeating the first vertex. Collinear points on an edge are
+/// dropped. Fewer than three distinct points return those points.
+/// Suits regional data that doesn't cross the antimeridian.
+#[wasm_bindgen]
+pub fn convex_hull(points: &[f64]) -> Vec<f64> {
+    let mut sorted: Vec<(f64, f64)> = points.chunks_exact(2).map(|p| (p[1], p[0])).collect();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    sorted.dedup();
+    if sorted.len() < 3 {
+        return sorted.iter().flat_map(|&(lon, lat)| [lat, lon]).collect();
+    }
+
+    let turn = |o: (f64, f64), a: (f64, f64), b: (f64, f64)| {
+        (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0)
+    };
+
+    // Andrew's monotone chain: lower hull left to right, upper hull right to left
+    let build = |iter: &mut dyn Iterator<Item = (f64, f64)>| {
+        let mut chain: Vec<(f64, f64)> = Vec::new();
+        for p in iter {
+            while chain.len() >= 2 && turn(chain[chain.len() - 2], chain[chain.len() - 1], p) <= 0.0
+            {
+                chain.pop();
+            }
+            chain.push(p);
+        }
+        chain.pop();
+        chain
+    };
+    let mut hull = build(&mut sorted.iter().copied());
+    hull.extend(build(&mut sorted.iter().rev().copied()));
+
+    hull.iter().flat_map(|&(lon, lat)| [lat, lon]).collect()
+}
+
+/// Minimum-area rectangle enclosing a point set, treating lat/lon as planar
+///
+/// Input array is flat: [lat0, lon0, lat1, lon1, ...]
+/// The best rectangle has a side along an edge of the `convex_hull`, so each
+/// hull edge is tried, keeping the first of equal areas. Like the hull, it is
+/// planar in degrees (lon as x, lat as y), a fair fit for regional data away
+/// from the poles and the antimeridian.
+/// Returns the four corners flat as [lat, lon, ...] in counter-clockwise
+/// order. Sets whose hull has no area (a single point, or all collinear)
+/// give a flat rectangle: that point four times, or the segment's ends as
+/// [end1, end2, end2, end1]. No points give an empty array.
+#[wasm_bindgen]
+pub fn min_bounding_rect(points: &[f64]) -> Vec<f64> {
+    let hull: Vec<(f64, f64)> = convex_hull(points)
+        .chunks_exact(2)
+        .map(|p| (p[1], p[0]))
+        .collect();
+    match hull.len() {
+        0 => return Vec::new(),
+        1 => {
+            return [hull[0]; 4]
+                .iter()
+                .flat_map(|&(lon, lat)| [lat, lon])
+                .collect()
+        }
+        2 => {
+            return [hull[0], hull[1], hull[1], hull[0]]
+                .iter()
+                .flat_map(|&(lon, lat)| [lat, lon])
+                .collect()
+        }
+        _ => {}
+    }
+
+    // Rectangle per edge as (area, unit axis, min and max along it and its normal)
+    let mut best: Option<(f64, (f64, f64), [f64; 4])> = None;
+    for (i, &(x0, y0)) in hull.iter().enumerate() {
+        let (x1, y1) = hull[(i + 1) % hull.len()];
+        let length = (x1 - x0).hypot(y1 - y0);
+        let axis = ((x1 - x0) / length, (y1 - y0) / length);
+
+        let mut extent = [
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+        ];
+        for &(x, y) in &hull {
+            let along = x * axis.0 + y * axis.1;
+            let across = y * axis.0 - x * axis.1;
+            extent = [
+                extent[0].min(along),
+                extent[1].max(along),
+                extent[2].min(across),
+                extent[3].max(across),
+            ];
+        }
+        let area = (extent[1] - extent[0]) * (extent[3] - extent[2]);
+        if best.is_none_or(|(best_area, _, _)| area < best_area) {
+            best = Some((area, axis, extent));
+        }
+    }
+
+    let (_, (ux, uy), [min_u, max_u, min_v, max_v]) = best.expect("hull has edges");
+    // Back from (along, across) to (lon, lat): along * axis + across * normal
+    let corner = |along: f64, across: f64| [across * ux + along * uy, along * ux - across * uy];
+    [
+        corner(min_u, min_v),
+        corner(max_u, min_v),
+        corner(max_u, max_v),
+        corner(min_u, max_v),
+    ]
+    .concat()
+}
+
+/// Area in km² enclosed by a polygon on the sphere
+///
+/// Input array is flat: [lat0, lon0, lat1, lon1, ...], closed implicitly
+/// Uses the spherical-excess line integral, so winding order doesn't matter
+/// and edges crossing the antimeridian are handled. Fewer than three
+/// vertices give 0.
+#[wasm_bindgen]
+pub fn spherical_polygon_area_km2(points: &[f64]) -> f64 {
+    let n = points.len() / 2;
+    if n < 3 {
+        return 0.0;
+    }
+
+    let mut total = 0.0;
+    for i in 0..n {
+        let j = (i + 1) % n;
+        let lat1 = to_rad(points[i * 2]);
+        let lat2 = to_rad(points[j * 2]);
+        let d_lon = shortest_delta_lon(points[i * 2 + 1], points[j * 2 + 1]);
+        total += d_lon * (2.0 + lat1.sin() + lat2.sin());
+    }
+
+    (total * EARTH_RADIUS_KM * EARTH_RADIUS_KM / 2.0).abs()
+}
+
+/// Perimeter and area of the convex hull of a point set
+///
+/// Input array is flat: [lat0, lon0, lat1, lon1, ...]
+/// Returns [perimeterKm, areaKm2, vertexCount], both metrics measured on the
+/// same `convex_hull` vertices. A two-vertex hull has a perimeter of twice
+/// its length and zero area.
+#[wasm_bindgen]
+pub fn hull_metrics(points: &[f64]) -> Vec<f64> {
+    let hull = convex_hull(points);
+    let n = hull.len() / 2;
+
+    let perimeter_km = if n < 2 {
+        0.0
+    } else {
+        (0..n)
+            .map(|i| {
+                let j = (i + 1) % n;
+                haversine_distance(hull[i * 2], hull[i * 2 + 1], hull[j * 2], hull[j * 2 + 1])
+            })
+            .sum()
+    };
+
+    vec![perimeter_km, spherical_polygon_area_km2(&hull), n as f64]
+}
+
+/// Latitude/longitude bounding box of a point set
+///
+/// Input array is flat: [lat0, lon0, lat1, lon1, ...]
+/// Returns [minLat, minLon, maxLat, maxLon], or an empty array for no points.
+/// Like `convex_hull`, longitudes are taken as is, so a set straddling the
+/// antimeridian gets a box spanning the long way round.
+#[wasm_bindgen]
+pub fn bounding_box(points: &[f64]) -> Vec<f64> {
+    if points.len() < 2 {
+        return Vec::new();
+    }
+    let mut bounds = [
+        f64::INFINITY,
+        f64::INFINITY,
+        f64::NEG_INFINITY,
+        f64::NEG_INFINITY,
+    ];
+    for p in points.chunks_exact(2) {
+        bounds[0] = bounds[0].min(p[0]);
+        bounds[1] = bounds[1].min(p[1]);
+        bounds[2] = bounds[2].max(p[0]);
+        bounds[3] = bounds[3].max(p[1]);
+    }
+    bounds.to_vec()
+}
+
+/// Evenly spaced sample points over a lat/lon box, e.g. candidates for `score_candidates`
+///
+/// Points are row-major like `set_cost_raster`'s cells: row 0 along
+/// `max_lat`, each row running from `min_lon` east to `max_lon`, edges
+/// included. A single row sits on the box's middle latitude and a single
+/// column on its middle longitude. A `min_lon` greater than `max_lon` means
+/// the box spans the antimeridian, going east from `min_lon` across 180.
+/// Returns flat array: [lat0, lon0, lat1, lon1, ...], rows * cols points with
+/// lons in [-180, 180); empty when either count is 0
+#[wasm_bindgen]
+pub fn grid_points(
+    min_lat: f64,
+    min_lon: f64,
+    max_lat: f64,
+    max_lon: f64,
+    rows: usize,
+    cols: usize,
+) -> Vec<f64> {
+    let span_lon = if min_lon > max_lon {
+        max_lon + 360.0 - min_lon
+    } else {
+        max_lon - min_lon
+    };
+    let step = |count: usize, i: usize| {
+        if count == 1 {
+            0.5
+        } else {
+            i as f64 / (count - 1) as f64
+        }
+    };
+
+    let mut output = Vec::with_capacity(rows * cols * 2);
+    for row in 0..rows {
+        let lat = max_lat - step(rows, row) * (max_lat - min_lat);
+        for col in 0..cols {
+            output.push(lat);
+            output.push(normalize_longitude(min_lon + step(cols, col) * span_lon));
+        }
+    }
+
+    output
+}
+
+/// Convex hull area as a fraction of the bounding box area (solidity)
+///
+/// Near 1 for compact, box-filling sets; low for elongated diagonal or
+/// L-shaped ones that a single meeting point serves poorly. The box area is
+/// that of the lat/lon band, and the hull's great-circle edges can bulge a
+/// hair past it, so the ratio is capped at 1. Sets whose hull has no area
+/// (fewer than three distinct points, or all collinear) give 0.
+#[wasm_bindgen]
+pub fn hull_solidity(points: &[f64]) -> f64 {
+    let hull_area_km2 = spherical_polygon_area_km2(&convex_hull(points));
+    let bounds = bounding_box(points);
+    if hull_area_km2 == 0.0 || bounds.is_empty() {
+        return 0.0;
+    }
+
+    let box_area_km2 = EARTH_RADIUS_KM
+        * EARTH_RADIUS_KM
+        * to_rad(bounds[3] - bounds[1])
+        * (to_rad(bounds[2]).sin() - to_rad(bounds[0]).sin());
+    if box_area_km2 <= 0.0 {
+        return 0.0;
+    }
+    (hull_area_km2 / box_area_km2).min(1.0)
+}
+
+/// Result structure for a combination
+#[derive(Clone, Copy)]
+struct ComboResult {
+    index_a: u32,
+    index_b: u32,
+    score: f64,
+    midpoint_lat: f64,
+    midpoint_lon: f64,
+}
+
+/// A commute line with a width, for corridor filtering
+#[derive(Clone, Copy)]
+struct Corridor {
+    start_lat: f64,
+    start_lon: f64,
+    end_lat: f64,
+    end_lon: f64,
+    length_km: f64,
+    half_width_km: f64,
+    score_by_offset: bool,
+}
+
+impl Corridor {
+    /// Offset of a point from the corridor line, or None if it lies outside
+    #[inline]
+    fn offset(&self, lat: f64, lon: f64) -> Option<f64> {
+        let offset = cross_track_distance(
+            lat,
+            lon,
+            self.start_lat,
+            self.start_lon,
+            self.end_lat,
+            self.end_lon,
+        )
+        .abs();
+        if offset > self.half_width_km {
+            return None;
+        }
+
+        // Points past either end are outside even if they sit on the great circle
+        let along = along_track_distance(
+            lat,
+            lon,
+            self.start_lat,
+            self.start_lon,
+            self.end_lat,
+            self.end_lon,
+        );
+        if along < 0.0 || along > self.length_km {
+            return None;
+        }
+
+        Some(offset)
+    }
+}
+
+/// A preferred area whose midpoints get a score bonus
+#[derive(Clone, Copy)]
+struct BonusZone {
+    center_lat: f64,
+    center_lon: f64,
+    radius_km: f64,
+    bonus_km: f64,
+}
+
+/// Keep-out radius around the target: a hard filter without a penalty rate,
+/// otherwise a soft penalty growing toward the target
+#[derive(Clone, Copy)]
+struct ForbiddenZone {
+    min_target_km: f64,
+    penalty_per_km: Option<f64>,
+}
+
+/// Separate cost weights for north-south and east-west travel
+#[derive(Clone, Copy)]
+struct DirectionWeights {
+    north_south: f64,
+    east_west: f64,
+}
+
+impl DirectionWeights {
+    /// Weighted distance in km, splitting the displacement on a local
+    /// equirectangular approximation (accurate for regional distances)
+    #[inline]
+    fn distance(&self, lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+        let north_km = to_rad(lat2 - lat1) * EARTH_RADIUS_KM;
+        let east_km =
+            shortest_delta_lon(lon1, lon2) * to_rad((lat1 + lat2) / 2.0).cos() * EARTH_RADIUS_KM;
+
+        ((self.north_south * north_km).powi(2) + (self.east_west * east_km).powi(2)).sqrt()
+    }
+}
+
+/// Preferred bearing of the midpoint as seen from the target
+#[derive(Clone, Copy)]
+struct TargetBearing {
+    bearing: f64,
+    tolerance_deg: f64,
+    penalty_km_per_deg: f64,
+}
+
+impl TargetBearing {
+    /// Degrees the midpoint is off the preferred bearing; a midpoint on the
+    /// target itself counts as on it
+    #[inline]
+    fn deviation(&self, target_lat: f64, target_lon: f64, lat: f64, lon: f64) -> f64 {
+        if lat == target_lat && lon == target_lon {
+            return 0.0;
+        }
+        bearing_difference(
+            self.bearing,
+            initial_bearing(target_lat, target_lon, lat, lon),
+        )
+    }
+}
+
+/// A polyline scored against in place of the target point
+#[derive(Clone)]
+struct TargetLine {
+    vertices: Vec<f64>,
+}
+
+impl TargetLine {
+    /// Distance in km from a point to the nearest point on the line
+    fn distance(&self, lat: f64, lon: f64) -> f64 {
+        if self.vertices.len() == 2 {
+            return haversine_distance(lat, lon, self.vertices[0], self.vertices[1]);
+        }
+        self.vertices
+            .windows(4)
+            .step_by(2)
+            .map(|s| project_to_segment_deg(lat, lon, s[0], s[1], s[2], s[3]).2)
+            .fold(f64::INFINITY, f64::min)
+    }
+}
+
+/// Several anchor points scored against in place of the target point
+#[derive(Clone)]
+struct MultiTarget {
+    targets: Vec<f64>,
+    aggregation: TargetAggregation,
+}
+
+impl MultiTarget {
+    /// Aggregated distance from a point to every anchor
+    fn distance(&self, options: &CombinationOptions, lat: f64, lon: f64) -> f64 {
+        let total: f64 = self
+            .targets
+            .chunks_exact(2)
+            .map(|t| options.distance(lat, lon, t[0], t[1]))
+            .sum();
+        match self.aggregation {
+            TargetAggregation::Sum => total,
+            TargetAggregation::Mean => total / (self.targets.len() / 2) as f64,
+        }
+    }
+}
+
+/// Per-point UTC offsets and the shared local-hours window for overlap bonuses
+#[derive(Clone)]
+struct TimeZoneOverlap {
+    offsets_a: Vec<f64>,
+    offsets_b: Vec<f64>,
+    window_hours: f64,
+    bonus_km_per_hour: f64,
+}
+
+impl TimeZoneOverlap {
+    /// Hours of the day inside both participants' local windows, or None if
+    /// either lacks an offset
+    #[inline]
+    fn overlap_hours(&self, index_a: usize, index_b: usize) -> Option<f64> {
+        let offset_a = self.offsets_a.get(index_a)?;
+        let offset_b = self.offsets_b.get(index_b)?;
+
+        // Two equal arcs on the 24 h clock, shifted by the offset difference
+        let shift = (offset_a - offset_b).rem_euclid(24.0);
+        let window = self.window_hours;
+        Some((window - shift).max(0.0) + (window - (24.0 - shift)).max(0.0))
+    }
+}
+
+/// Regular lat/lon grid of cost multipliers
+#[derive(Clone)]
+struct CostRaster {
+    values: Vec<f64>,
+    rows: usize,
+    cols: usize,
+    min_lat: f64,
+    min_lon: f64,
+    max_lat: f64,
+    max_lon: f64,
+    sampling: RasterSampling,
+}
+
+impl CostRaster {
+    /// Multiplier at a point; 1.0 outside the raster bounds
+    fn sample(&self, lat: f64, lon: f64) -> f64 {
+        if !(self.min_lat..=self.max_lat).contains(&lat)
+            || !(self.min_lon..=self.max_lon).contains(&lon)
+        {
+            return 1.0;
+        }
+
+        // Continuous cell coordinates, row 0 along the north edge
+        let row = (self.max_lat - lat) / (self.max_lat - self.min_lat) * self.rows as f64;
+        let col = (lon - self.min_lon) / (self.max_lon - self.min_lon) * self.cols as f64;
+        let at = |r: usize, c: usize| {
+            self.values[r.min(self.rows - 1) * self.cols + c.min(self.cols - 1)]
+        };
+
+        match self.sampling {
+            RasterSampling::Nearest => at(row as usize, col as usize),
+            RasterSampling::Bilinear => {
+                // Shift to cell-center coordinates and clamp at the edges
+                let row = (row - 0.5).clamp(0.0, (self.rows - 1) as f64);
+                let col = (col - 0.5).clamp(0.0, (self.cols - 1) as f64);
+                let (r0, c0) = (row as usize, col as usize);
+                let (fr, fc) = (row - r0 as f64, col - c0 as f64);
+
+                let top = at(r0, c0) * (1.0 - fc) + at(r0, c0 + 1) * fc;
+                let bottom = at(r0 + 1, c0) * (1.0 - fc) + at(r0 + 1, c0 + 1) * fc;
+                top * (1.0 - fr) + bottom * fr
+            }
+        }
+    }
+}
+
+/// Geohash cell to region lookup with the set of regions to keep
+#[derive(Clone)]
+struct RegionFilter {
+    precision: usize,
+    cell_regions: HashMap<Vec<u8>, u32>,
+    allowed: HashSet<u32>,
+}
+
+impl RegionFilter {
+    /// Whether the point's cell maps to an allowed region
+    #[inline]
+    fn allows(&self, lat: f64, lon: f64) -> bool {
+        let (hash, len) = geohash_bytes(lat, lon, self.precision);
+        self.cell_regions
+            .get(&hash[..len])
+            .is_some_and(|region| self.allowed.contains(region))
+    }
+}
+
+/// Optional filters and scoring tweaks for `find_best_combinations_with_options`
+#[wasm_bindgen]
+#[derive(Clone, Default)]
+pub struct CombinationOptions {
+    geometry: Geometry,
+    coord_order: CoordOrder,
+    angle_unit: AngleUnit,
+    midpoint_method: MidpointMethod,
+    scoring_mode: ScoringMode,
+    group_speeds: Option<(f64, f64)>,
+    fixed_point: bool,
+    score_kernel: ScoreKernel,
+    kernel_param_km: f64,
+    score_as_rank: bool,
+    include_quality: bool,
+    include_pair_id: bool,
+    include_max_travel: bool,
+    include_midpoint_azimuth: bool,
+    include_coordinates: bool,
+    include_target_alignment: bool,
+    tier_boundaries: Option<Vec<f64>>,
+    corridor: Option<Corridor>,
+    bonus_zone: Option<BonusZone>,
+    forbidden_zone: Option<ForbiddenZone>,
+    direction_weights: Option<DirectionWeights>,
+    target_bearing: Option<TargetBearing>,
+    target_line: Option<TargetLine>,
+    multi_target: Option<MultiTarget>,
+    time_zone_overlap: Option<TimeZoneOverlap>,
+    cost_raster: Option<CostRaster>,
+    min_result_separation_km: Option<f64>,
+    max_per_a: Option<usize>,
+    max_score_km: Option<f64>,
+    region_filter: Option<RegionFilter>,
+}
+
+#[wasm_bindgen]
+impl CombinationOptions {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> CombinationOptions {
+        CombinationOptions::default()
+    }
+
+    /// Pair order of the point arrays and of the midpoints in the output
+    pub fn set_coord_order(&mut self, order: CoordOrder) {
+        self.coord_order = order;
+    }
+
+    /// Treat coordinates as spherical lat/lon or as planar x,y
+    ///
+    /// In `Planar` mode each input pair is x,y in the projection's units (the
+    /// target's `target_lat` is x and `target_lon` y), midpoints are the
+    /// average of the two points, and scores and max travel are plain
+    /// Euclidean distances in those units, with no trig in the pair loop.
+    /// Options that only make sense on the globe (radian angles, rhumb and
+    /// Vincenty midpoints, corridor, bonus zone, direction weights, target
+    /// bearing, target line, time zone overlap, cost raster, result
+    /// separation, region filter, and the quality, azimuth and alignment
+    /// fields) can't be combined with it: switching to `Planar` with one set
+    /// is an error, and so is setting one in `Planar` mode.
+    pub fn set_geometry(&mut self, geometry: Geometry) -> Result<(), JsError> {
+        if geometry == Geometry::Planar {
+            if let Some(option) = self.spherical_only_option() {
+                return Err(JsError::new(&format!("{option} needs spherical geometry")));
+            }
+        }
+        self.geometry = geometry;
+        Ok(())
+    }
+
+    /// Unit of the point arrays, the target and the midpoints in the output
+    ///
+    /// Option parameters such as corridor and bonus-zone coordinates stay in
+    /// degrees. Radian inputs feed the pair trig as given, with no round trip
+    /// through degrees.
+    pub fn set_angle_unit(&mut self, unit: AngleUnit) -> Result<(), JsError> {
+        if unit == AngleUnit::Radians {
+            self.require_spherical("radian angles")?;
+        }
+        self.angle_unit = unit;
+        Ok(())
+    }
+
+    /// How each pair's midpoint is computed
+    pub fn set_midpoint_method(&mut self, method: MidpointMethod) -> Result<(), JsError> {
+        if method != MidpointMethod::GreatCircle {
+            self.require_spherical("the rhumb and Vincenty midpoints")?;
+        }
+        self.midpoint_method = method;
+        Ok(())
+    }
+
+    /// What each pair is scored by; see `ScoringMode`
+    ///
+    /// The corridor offset and the target line only replace the
+    /// `TargetProximity` score.
+    pub fn set_scoring_mode(&mut self, mode: ScoringMode) {
+        self.scoring_mode = mode;
+    }
+
+    /// Travel speeds of groups A and B in km/h for `ScoringMode::ArrivalSkew`
+    ///
+    /// Other modes ignore them. A zero speed is allowed and drops every pair,
+    /// as that group never arrives; negative or NaN speeds are rejected.
+    pub fn set_group_speeds(&mut self, speed_a_kmh: f64, speed_b_kmh: f64) -> Result<(), JsError> {
+        if !(speed_a_kmh >= 0.0 && speed_b_kmh >= 0.0) {
+            return Err(JsError::new(&format!(
+                "group speeds must be non-negative, got {speed_a_kmh} and {speed_b_kmh}"
+            )));
+        }
+        self.group_speeds = Some((speed_a_kmh, speed_b_kmh));
+        Ok(())
+    }
+
+    pub fn clear_group_speeds(&mut self) {
+        self.group_speeds = None;
+    }
+
+    /// Quantize to a fixed grid so results reproduce across platforms
+    ///
+    /// Input coordinates and the target are snapped to 1e-7 degrees (about
+    /// 1 cm; input units in planar mode) before any math, and each pair's
+    /// score and midpoint to 1 mm and 1e-7 degrees before ranking. The last-ulp
+    /// trig differences between x86 and wasm then vanish in the rounding, so
+    /// scores that tie on one platform tie on every other and fall back to
+    /// the same index order, and the base record fields come out
+    /// byte-identical. A value landing within an ulp of a rounding boundary
+    /// could still split, which is vanishingly rare. The cost is the grid
+    /// precision itself. Extra fields are computed in plain floating point.
+    pub fn set_fixed_point(&mut self, enabled: bool) {
+        self.fixed_point = enabled;
+    }
+
+    /// Report scores as a similarity through a kernel instead of raw km
+    ///
+    /// `param_km` is the linear cutoff, the Gaussian sigma or the exponential
+    /// half-distance. Ranking is by the underlying distance, which every
+    /// kernel preserves as it only decreases with distance, so results come
+    /// out by descending similarity. Negative (bonus-zone) scores count as 0 km.
+    pub fn set_score_kernel(&mut self, kernel: ScoreKernel, param_km: f64) {
+        self.score_kernel = kernel;
+        self.kernel_param_km = param_km;
+    }
+
+    /// Write each result's rank in place of its score
+    ///
+    /// Ranks are dense and 1-based over the returned results: records whose
+    /// km scores are exactly equal share a rank, and the next distinct score
+    /// gets the next integer (1, 1, 2), with no gaps. Competition ranking
+    /// (1, 1, 3) would skip past the ties instead; dense ranks are used so
+    /// the last rank counts the distinct scores shown, as a leaderboard
+    /// numbers its places. Tied records keep the engine's index order.
+    /// Overrides the score kernel.
+    pub fn set_score_as_rank(&mut self, enabled: bool) {
+        self.score_as_rank = enabled;
+    }
+
+    /// Append each result's `midpoint_quality` as an extra field
+    pub fn set_include_quality(&mut self, include: bool) -> Result<(), JsError> {
+        if include {
+            self.require_spherical("the quality field")?;
+        }
+        self.include_quality = include;
+        Ok(())
+    }
+
+    /// Append a stable `pair_id` = indexA * numB + indexB after any quality field
+    ///
+    /// The id depends only on the pairing and the size of group B, so it
+    /// stays the same across runs whatever the pair's rank, e.g. for UI keys.
+    /// Exact as an f64 up to 2^53 pairs.
+    pub fn set_include_pair_id(&mut self, include: bool) {
+        self.include_pair_id = include;
+    }
+
+    /// Append `max_travel_km`, the longer of the two participants' distances
+    /// to the midpoint, after any quality and pair id fields
+    ///
+    /// Reported only; ranking is unaffected.
+    pub fn set_include_max_travel(&mut self, include: bool) {
+        self.include_max_travel = include;
+    }
+
+    /// Append the azimuth at the midpoint, the initial bearing from the
+    /// result's midpoint on toward B (see `midpoint_azimuth`), after any
+    /// earlier extra fields
+    pub fn set_include_midpoint_azimuth(&mut self, include: bool) -> Result<(), JsError> {
+        if include {
+            self.require_spherical("the midpoint azimuth")?;
+        }
+        self.include_midpoint_azimuth = include;
+        Ok(())
+    }
+
+    /// Append the pair's own input coordinates, latA, lonA, latB, lonB, after
+    /// any earlier extra fields
+    ///
+    /// Each pair is written like the midpoint, in the options' coordinate
+    /// order and angle unit, so records are self-contained for drawing lines
+    /// from both participants. Adds 4 values per record.
+    pub fn set_include_coordinates(&mut self, include: bool) {
+        self.include_coordinates = include;
+    }
+
+    /// Append the target alignment, the angle in degrees [0, 180] between
+    /// the A->B course and the course from the midpoint to the target, after
+    /// any earlier extra fields
+    ///
+    /// Near 0 means the pairing points straight at the target. Both are
+    /// initial bearings compared with `bearing_difference`. Reported only;
+    /// ranking is unaffected.
+    pub fn set_include_target_alignment(&mut self, include: bool) -> Result<(), JsError> {
+        if include {
+            self.require_spherical("the target alignment")?;
+        }
+        self.include_target_alignment = include;
+        Ok(())
+    }
+
+    /// Append each result's tier index, bucketed by km score, after any
+    /// earlier extra fields
+    ///
+    /// With boundaries [b0, b1, ...] in ascending order, tier 0 is scores
+    /// below b0 and tier i is b(i-1) <= score < b(i): each boundary belongs
+    /// to the tier above it. Scores at or past the last boundary (and NaN)
+    /// get the last tier, index `boundaries.len()`. Tiers use the km score
+    /// before any kernel or rank, so they stay put when those change.
+    pub fn set_tier_boundaries(&mut self, boundaries: Vec<f64>) -> Result<(), JsError> {
+        if boundaries.iter().any(|b| b.is_nan()) || boundaries.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(JsError::new(
+                "tier boundaries must be strictly ascending numbers",
+            ));
+        }
+        self.tier_boundaries = Some(boundaries);
+        Ok(())
+    }
+
+    pub fn clear_tier_boundaries(&mut self) {
+        self.tier_boundaries = None;
+    }
+
+    /// Number of f64 values per result in the output for these options
+    pub fn stride(&self) -> usize {
+        RESULT_STRIDE
+            + self.include_quality as usize
+            + self.include_pair_id as usize
+            + self.include_max_travel as usize
+            + self.include_midpoint_azimuth as usize
+            + 4 * self.include_coordinates as usize
+            + self.include_target_alignment as usize
+            + self.tier_boundaries.is_some() as usize
+    }
+
+    /// Only keep midpoints inside a corridor along the start->end segment
+    ///
+    /// `width_km` is the full width, so midpoints up to half of it either side
+    /// of the line pass. With `score_by_offset` the score becomes the
+    /// midpoint's distance from the line instead of from the target.
+    pub fn set_corridor(
+        &mut self,
+        start_lat: f64,
+        start_lon: f64,
+        end_lat: f64,
+        end_lon: f64,
+        width_km: f64,
+        score_by_offset: bool,
+    ) -> Result<(), JsError> {
+        self.require_spherical("a corridor")?;
+        self.corridor = Some(Corridor {
+            start_lat,
+            start_lon,
+            end_lat,
+            end_lon,
+            length_km: haversine_distance(start_lat, start_lon, end_lat, end_lon),
+            half_width_km: width_km / 2.0,
+            score_by_offset,
+        });
+        Ok(())
+    }
+
+    pub fn clear_corridor(&mut self) {
+        self.corridor = None;
+    }
+
+    /// Subtract `bonus_km` from the score of midpoints within `radius_km` of a center
+    ///
+    /// In-zone options move up the ranking without excluding better ones
+    /// outside it. Boosted scores can go below zero.
+    pub fn set_bonus_zone(
+        &mut self,
+        center_lat: f64,
+        center_lon: f64,
+        radius_km: f64,
+        bonus_km: f64,
+    ) -> Result<(), JsError> {
+        self.require_spherical("a bonus zone")?;
+        self.bonus_zone = Some(BonusZone {
+            center_lat,
+            center_lon,
+            radius_km,
+            bonus_km,
+        });
+        Ok(())
+    }
+
+    pub fn clear_bonus_zone(&mut self) {
+        self.bonus_zone = None;
+    }
+
+    /// Drop midpoints closer than `min_target_km` to the target
+    ///
+    /// Together with `set_max_score` this keeps results in a ring around the
+    /// target, e.g. within 10 km but not within 1 km of a stadium. Distance is
+    /// the plain midpoint-to-target distance, whatever the scoring mode.
+    pub fn set_min_target_distance(&mut self, min_target_km: f64) {
+        self.forbidden_zone = Some(ForbiddenZone {
+            min_target_km,
+            penalty_per_km: None,
+        });
+    }
+
+    /// Penalize midpoints closer than `min_target_km` to the target instead
+    /// of dropping them
+    ///
+    /// Each km short of the radius adds `penalty_per_km` km to the score, so
+    /// a midpoint on the target itself pays `min_target_km * penalty_per_km`.
+    /// Replaces a filter set by `set_min_target_distance`.
+    pub fn set_min_target_distance_penalty(&mut self, min_target_km: f64, penalty_per_km: f64) {
+        self.forbidden_zone = Some(ForbiddenZone {
+            min_target_km,
+            penalty_per_km: Some(penalty_per_km),
+        });
+    }
+
+    pub fn clear_min_target_distance(&mut self) {
+        self.forbidden_zone = None;
+    }
+
+    /// Weight the north-south and east-west parts of the midpoint-to-target distance
+    ///
+    /// The score becomes sqrt((ns * dNorth)² + (ew * dEast)²) with both
+    /// components in km, so a weight of 2 makes travel along that axis count
+    /// double. Weights of 1 approximate the plain haversine score.
+    pub fn set_direction_weights(
+        &mut self,
+        north_south: f64,
+        east_west: f64,
+    ) -> Result<(), JsError> {
+        self.require_spherical("direction weights")?;
+        self.direction_weights = Some(DirectionWeights {
+            north_south,
+            east_west,
+        });
+        Ok(())
+    }
+
+    pub fn clear_direction_weights(&mut self) {
+        self.direction_weights = None;
+    }
+
+    /// Prefer midpoints lying on `bearing` (degrees) from the target
+    ///
+    /// Midpoints more than `tolerance_deg` off that bearing line are dropped,
+    /// and the rest get `penalty_km_per_deg` added to their score for each
+    /// degree of deviation, so on-bearing midpoints outrank equally distant
+    /// ones beside the line.
+    pub fn set_target_bearing(
+        &mut self,
+        bearing: f64,
+        tolerance_deg: f64,
+        penalty_km_per_deg: f64,
+    ) -> Result<(), JsError> {
+        self.require_spherical("a target bearing")?;
+        self.target_bearing = Some(TargetBearing {
+            bearing,
+            tolerance_deg,
+            penalty_km_per_deg,
+        });
+        Ok(())
+    }
+
+    pub fn clear_target_bearing(&mut self) {
+        self.target_bearing = None;
+    }
+
+    /// Score midpoints by their distance to a polyline instead of the target
+    ///
+    /// `vertices` is a flat [lat0, lon0, lat1, lon1, ...] path in degrees,
+    /// taken as is whatever the coordinate order and angle unit. Each leg is a
+    /// great-circle segment, and a midpoint's score is its km distance to the
+    /// nearest point on any leg, so midpoints along a transit line rank well
+    /// even far from its ends. A single vertex acts as a point target.
+    /// Unlike the corridor, nothing is filtered out. The target point is still
+    /// used by the target bearing and the quality field, and
+    /// `find_best_combinations_spatial` falls back to brute force since its
+    /// search is centered on the point.
+    pub fn set_target_line(&mut self, vertices: Vec<f64>) -> Result<(), JsError> {
+        self.require_spherical("a target line")?;
+        if vertices.is_empty() || !vertices.len().is_multiple_of(2) {
+            return Err(JsError::new(&format!(
+                "target line needs at least one lat,lon pair, got {} values",
+                vertices.len()
+            )));
+        }
+        self.target_line = Some(TargetLine { vertices });
+        Ok(())
+    }
+
+    pub fn clear_target_line(&mut self) {
+        self.target_line = None;
+    }
+
+    /// Score midpoints by their distances to several targets at once
+    ///
+    /// `targets` is a flat [lat0, lon0, lat1, lon1, ...] array, read like
+    /// the target line's vertices, and each midpoint's distances to all of
+    /// them are combined by `aggregation`: the sum, or the mean to keep
+    /// scores comparable across target counts. Both rank identically for a
+    /// fixed set; a single target gives the usual score. The target point
+    /// and `find_best_combinations_spatial` behave as with a target line,
+    /// which takes precedence if both are set.
+    pub fn set_targets(
+        &mut self,
+        targets: Vec<f64>,
+        aggregation: TargetAggregation,
+    ) -> Result<(), JsError> {
+        if targets.is_empty() || !targets.len().is_multiple_of(2) {
+            return Err(JsError::new(&format!(
+                "targets need at least one lat,lon pair, got {} values",
+                targets.len()
+            )));
+        }
+        self.multi_target = Some(MultiTarget {
+            targets,
+            aggregation,
+        });
+        Ok(())
+    }
+
+    pub fn clear_targets(&mut self) {
+        self.multi_target = None;
+    }
+
+    /// Reward pairs whose participants share waking hours
+    ///
+    /// `offsets_a`/`offsets_b` hold each point's UTC offset in hours (e.g. -5
+    /// for New York in winter). Everyone is taken to be available in the same
+    /// local window, `window_start_hour` to `window_end_hour` (wrapping past
+    /// midnight if end < start; equal means an empty window). The score is
+    /// reduced by `bonus_km_per_hour` for every hour the two local windows
+    /// overlap in UTC: a same-zone pair gets the full window length, a pair 12
+    /// hours apart with a 12 hour window gets nothing. Pairs where either point
+    /// has no offset get no bonus.
+    pub fn set_time_zone_overlap(
+        &mut self,
+        offsets_a: &[f64],
+        offsets_b: &[f64],
+        window_start_hour: f64,
+        window_end_hour: f64,
+        bonus_km_per_hour: f64,
+    ) -> Result<(), JsError> {
+        self.require_spherical("time zone overlap")?;
+        self.time_zone_overlap = Some(TimeZoneOverlap {
+            offsets_a: offsets_a.to_vec(),
+            offsets_b: offsets_b.to_vec(),
+            window_hours: (window_end_hour - window_start_hour).rem_euclid(24.0),
+            bonus_km_per_hour,
+        });
+        Ok(())
+    }
+
+    pub fn clear_time_zone_overlap(&mut self) {
+        self.time_zone_overlap = None;
+    }
+
+    /// Multiply each midpoint's distance score by a travel-cost raster
+    ///
+    /// `values` is `rows * cols` multipliers, row-major with row 0 along the
+    /// north edge (`max_lat`) and column 0 along the west edge (`min_lon`);
+    /// each cell spans an equal lat/lon step of the bounds. Midpoints outside
+    /// the bounds use 1.0. The multiplier applies to the base score before
+    /// any bearing penalty or bonuses.
+    #[allow(clippy::too_many_arguments)]
+    pub fn set_cost_raster(
+        &mut self,
+        values: &[f64],
+        rows: usize,
+        cols: usize,
+        min_lat: f64,
+        min_lon: f64,
+        max_lat: f64,
+        max_lon: f64,
+        sampling: RasterSampling,
+    ) -> Result<(), JsError> {
+        self.require_spherical("a cost raster")?;
+        if rows == 0 || cols == 0 || values.len() != rows * cols {
+            return Err(JsError::new(&format!(
+                "cost raster needs rows * cols values, got {} for {rows} x {cols}",
+                values.len()
+            )));
+        }
+        if !(min_lat < max_lat && min_lon < max_lon) {
+            return Err(JsError::new("cost raster bounds must have min < max"));
+        }
+
+        self.cost_raster = Some(CostRaster {
+            values: values.to_vec(),
+            rows,
+            cols,
+            min_lat,
+            min_lon,
+            max_lat,
+            max_lon,
+            sampling,
+        });
+        Ok(())
+    }
+
+    pub fn clear_cost_raster(&mut self) {
+        self.cost_raster = None;
+    }
+
+    /// Keep results geographically distinct while filling the top N
+    ///
+    /// Going best first, a candidate whose midpoint is within
+    /// `separation_km` of an already kept result's midpoint is skipped, so a
+    /// cluster of near-identical pairings yields one suggestion and the rest
+    /// of the top N covers other places. Greedy, so the kept set is not
+    /// necessarily the best-scoring diverse set. Applies to
+    /// `find_best_combinations_with_options` and `find_best_combinations_masked`.
+    pub fn set_min_result_separation(&mut self, separation_km: f64) -> Result<(), JsError> {
+        self.require_spherical("result separation")?;
+        self.min_result_separation_km = Some(separation_km);
+        Ok(())
+    }
+
+    pub fn clear_min_result_separation(&mut self) {
+        self.min_result_separation_km = None;
+    }
+
+    /// Let each A point contribute at most `max_per_a` results to the top N
+    ///
+    /// Applied while filling the top N, best first: once an A index has its
+    /// quota, its further candidates are skipped and the next best pairings of
+    /// other A points take those places, so the output still has N results
+    /// when enough exist. Applies to the same functions as
+    /// `set_min_result_separation`.
+    pub fn set_max_per_a(&mut self, max_per_a: usize) {
+        self.max_per_a = Some(max_per_a);
+    }
+
+    pub fn clear_max_per_a(&mut self) {
+        self.max_per_a = None;
+    }
+
+    /// Drop every pair scoring worse than `max_score_km`
+    ///
+    /// Checked on the final km score, after rasters, penalties and bonuses
+    /// and before any kernel, so rejected pairs never reach the top N and a
+    /// run with nothing in range returns no results. The top N of what
+    /// remains is returned as usual. With the plain target score the
+    /// brute-force engines also prune: a pair whose participants' distances
+    /// to the target differ by more than twice the cap can't meet it and is
+    /// skipped unscored, and so is any A with no such B, so a tight cap
+    /// returns quickly.
+    pub fn set_max_score(&mut self, max_score_km: f64) {
+        self.max_score_km = Some(max_score_km);
+    }
+
+    pub fn clear_max_score(&mut self) {
+        self.max_score_km = None;
+    }
+
+    /// Only keep midpoints whose geohash cell maps to an allowed region
+    ///
+    /// `cells[i]` is a geohash assigned to region `region_ids[i]`. All cells
+    /// must have the same length, which sets the precision each midpoint is
+    /// hashed at, so the map has to cover the area at that one precision
+    /// (a coarser parent cell won't match). Midpoints in unmapped cells or in
+    /// regions not in `allowed_regions` are dropped. Cells are matched case
+    /// insensitively. Each check is one geohash plus a hash lookup, whatever
+    /// the region shapes.
+    pub fn set_region_filter(
+        &mut self,
+        cells: Vec<String>,
+        region_ids: &[u32],
+        allowed_regions: &[u32],
+    ) -> Result<(), JsError> {
+        self.require_spherical("a region filter")?;
+        if cells.len() != region_ids.len() {
+            return Err(JsError::new(&format!(
+                "{} cells but {} region ids",
+                cells.len(),
+                region_ids.len()
+            )));
+        }
+        // Geohashes are generated in lowercase, so match the map to them
+        let cells: Vec<Vec<u8>> = cells
+            .into_iter()
+            .map(|cell| cell.to_ascii_lowercase().into_bytes())
+            .collect();
+        let precision = cells.first().map_or(1, Vec::len);
+        if !(1..=GEOHASH_MAX_PRECISION).contains(&precision)
+            || cells.iter().any(|c| c.len() != precision)
+        {
+            return Err(JsError::new(
+                "region cells must all be geohashes of the same length, 1-12",
+            ));
+        }
+        if let Some(cell) = cells
+            .iter()
+            .find(|c| !c.iter().all(|b| GEOHASH_ALPHABET.contains(b)))
+        {
+            return Err(JsError::new(&format!(
+                "region cell {:?} is not a base-32 geohash",
+                String::from_utf8_lossy(cell)
+            )));
+        }
+
+        self.region_filter = Some(RegionFilter {
+            precision,
+            cell_regions: cells.into_iter().zip(region_ids.iter().copied()).collect(),
+            allowed: allowed_regions.iter().copied().collect(),
+        });
+        Ok(())
+    }
+
+    pub fn clear_region_filter(&mut self) {
+        self.region_filter = None;
+    }
+}
+
+impl CombinationOptions {
+    /// The first option set that reads coordinates as lat/lon on the globe,
+    /// named as in the `Planar` error
+    fn spherical_only_option(&self) -> Option<&'static str> {
+        [
+            (self.angle_unit == AngleUnit::Radians, "radian angles"),
+            (
+                self.midpoint_method != MidpointMethod::GreatCircle,
+                "the rhumb and Vincenty midpoints",
+            ),
+            (self.include_quality, "the quality field"),
+            (self.include_midpoint_azimuth, "the midpoint azimuth"),
+            (self.include_target_alignment, "the target alignment"),
+            (self.corridor.is_some(), "a corridor"),
+            (self.bonus_zone.is_some(), "a bonus zone"),
+            (self.direction_weights.is_some(), "direction weights"),
+            (self.target_bearing.is_some(), "a target bearing"),
+            (self.target_line.is_some(), "a target line"),
+            (self.time_zone_overlap.is_some(), "time zone overlap"),
+            (self.cost_raster.is_some(), "a cost raster"),
+            (self.min_result_separation_km.is_some(), "result separation"),
+            (self.region_filter.is_some(), "a region filter"),
+        ]
+        .into_iter()
+        .find_map(|(set, option)| set.then_some(option))
+    }
+
+    /// Error if an option that reads coordinates on the globe is being set
+    /// in `Planar` mode
+    fn require_spherical(&self, option: &str) -> Result<(), JsError> {
+        if self.geometry == Geometry::Planar {
+            return Err(JsError::new(&format!("{option} needs spherical geometry")));
+        }
+        Ok(())
+    }
+
+    /// View an input point array as lat,lon degrees
+    fn input_points<'p>(&self, points: &'p [f64]) -> Cow<'p, [f64]> {
+        let points = to_lat_lon(points, self.coord_order);
+        if self.converts_angles() || self.fixed_point {
+            Cow::Owned(points.iter().map(|&v| self.input_angle(v)).collect())
+        } else {
+            points
+        }
+    }
+
+    /// Whether inputs and outputs are radians that need converting
+    fn converts_angles(&self) -> bool {
+        self.geometry == Geometry::Spherical && self.angle_unit == AngleUnit::Radians
+    }
+
+    /// Distance between two points under the options' geometry: km on the
+    /// sphere, input units in the plane
+    fn distance(&self, lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+        match self.geometry {
+            Geometry::Spherical => haversine_distance(lat1, lon1, lat2, lon2),
+            Geometry::Planar => (lat2 - lat1).hypot(lon2 - lon1),
+        }
+    }
+
+    /// Whether a candidate is far enough from every kept result's midpoint
+    fn is_separated(&self, candidate: &ComboResult, kept: &[ComboResult]) -> bool {
+        let Some(separation_km) = self.min_result_separation_km else {
+            return true;
+        };
+        kept.iter().all(|other| {
+            self.distance(
+                candidate.midpoint_lat,
+                candidate.midpoint_lon,
+                other.midpoint_lat,
+                other.midpoint_lon,
+            ) > separation_km
+        })
+    }
+
+    /// Whether every pair scores at least its midpoint's distance to the
+    /// target, which `ScoreCapPruning` relies on
+    ///
+    /// True for the plain target score, whose only additions are the
+    /// non-negative forbidden-zone and bearing penalties. Other midpoint
+    /// methods, stand-in anchors, multipliers, bonuses and quantization can
+    /// all go below it.
+    fn score_bounded_by_midpoint_distance(&self) -> bool {
+        self.scoring_mode == ScoringMode::TargetProximity
+            && (self.midpoint_method == MidpointMethod::GreatCircle
+                || self.geometry == Geometry::Planar)
+            && !self.fixed_point
+            && self
+                .corridor
+                .as_ref()
+                .is_none_or(|corridor| !corridor.score_by_offset)
+            && self.target_line.is_none()
+            && self.multi_target.is_none()
+            && self.direction_weights.is_none()
+            && self.cost_raster.is_none()
+            && self.time_zone_overlap.is_none()
+            && self.bonus_zone.is_none()
+            && self
+                .forbidden_zone
+                .as_ref()
+                .is_none_or(|zone| zone.penalty_per_km.is_none_or(|p| p >= 0.0))
+            && self
+                .target_bearing
+                .as_ref()
+                .is_none_or(|bearing| bearing.penalty_km_per_deg >= 0.0)
+    }
+
+    /// Whether top-N selection has to go candidate by candidate
+    fn needs_greedy_selection(&self) -> bool {
+        self.min_result_separation_km.is_some() || self.max_per_a.is_some()
+    }
+
+    /// Whether the candidate's A index still has room under `max_per_a`,
+    /// counting it in `counts` if so
+    ///
+    /// As it takes a place, call this only once every other check passed.
+    fn take_quota(&self, candidate: &ComboResult, counts: &mut HashMap<u32, usize>) -> bool {
+        let Some(max_per_a) = self.max_per_a else {
+            return true;
+        };
+        let count = counts.entry(candidate.index_a).or_insert(0);
+        if *count >= max_per_a {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+
+    /// Convert a scalar input angle to degrees
+    fn input_angle(&self, value: f64) -> f64 {
+        let degrees = if self.converts_angles() {
+            to_deg(value)
+        } else {
+            value
+        };
+        if self.fixed_point {
+            quantize(degrees, FIXED_POINT_STEPS_PER_DEG)
+        } else {
+            degrees
+        }
+    }
+
+    /// Precompute trig for an input point array, matching `input_points`
+    fn input_trig(&self, points: &[f64]) -> Vec<TrigPoint> {
+        to_lat_lon(points, self.coord_order)
+            .chunks_exact(2)
+            .map(|p| self.input_trig_point(p[0], p[1]))
+            .collect()
+    }
+
+    /// Trig of one input point
+    ///
+    /// Radians go straight to `TrigPoint::from_rad` rather than through
+    /// degrees and back; fixed-point snapping happens in degrees, so it
+    /// still takes the degree path.
+    fn input_trig_point(&self, lat: f64, lon: f64) -> TrigPoint {
+        if self.converts_angles() && !self.fixed_point {
+            TrigPoint::from_rad(lat, lon)
+        } else {
+            TrigPoint::new(self.input_angle(lat), self.input_angle(lon))
+        }
+    }
+
+    /// Transform a km score through the configured kernel
+    fn output_score(&self, score: f64) -> f64 {
+        apply_kernel(self.score_kernel, self.kernel_param_km, score)
+    }
+
+    /// Midpoint of an output record as degrees (lat, lon), undoing the
+    /// coordinate order and angle unit it was written in
+    fn record_midpoint(&self, record: &[f64]) -> (f64, f64) {
+        let (lat, lon) = match self.coord_order {
+            CoordOrder::LatLon => (record[3], record[4]),
+            CoordOrder::LonLat => (record[4], record[3]),
+        };
+        (self.input_angle(lat), self.input_angle(lon))
+    }
+
+    /// Write a lat,lon degree pair to output in the options' order and unit
+    fn push_coordinate(&self, output: &mut Vec<f64>, lat: f64, lon: f64) {
+        let lat = self.output_angle(lat);
+        let lon = self.output_angle(lon);
+        match self.coord_order {
+            CoordOrder::LatLon => {
+                output.push(lat);
+                output.push(lon);
+            }
+            CoordOrder::LonLat => {
+                output.push(lon);
+                output.push(lat);
+            }
+        }
+    }
+
+    /// Convert an output angle from degrees to the options' unit
+    fn output_angle(&self, degrees: f64) -> f64 {
+        if self.converts_angles() {
+            to_rad(degrees)
+        } else {
+            degrees
+        }
+    }
+}
+
+/// Number of f64 values per result in the flat output
+const RESULT_STRIDE: usize = 5;
+
+/// Grid steps per degree (or planar input unit) in fixed-point mode
+const FIXED_POINT_STEPS_PER_DEG: f64 = 1e7;
+
+/// Grid steps per km for fixed-point scores
+const FIXED_POINT_STEPS_PER_KM: f64 = 1e6;
+
+/// Round a value to the nearest multiple of 1 / steps
+#[inline]
+fn quantize(value: f64, steps: f64) -> f64 {
+    (value * steps).round() / steps
+}
+
+/// Scores single A-B pairs against a target under a set of options
+struct PairScorer<'a> {
+    target_lat: f64,
+    target_lon: f64,
+    cos_target: f64,
+    options: &'a CombinationOptions,
+}
+
+impl<'a> PairScorer<'a> {
+    fn new(target: &TrigPoint, options: &'a CombinationOptions) -> Self {
+        PairScorer {
+            target_lat: target.lat,
+            target_lon: target.lon,
+            cos_target: target.cos_lat,
+            options,
+        }
+    }
+
+    /// Distance from a participant to the target under the options' geometry
+    #[inline]
+    fn travel(&self, p: &TrigPoint) -> f64 {
+        match self.options.geometry {
+            Geometry::Spherical => haversine_distance_cos(
+                p.lat,
+                p.lon,
+                p.cos_lat,
+                self.target_lat,
+                self.target_lon,
+                self.cos_target,
+            ),
+            Geometry::Planar => (p.lat - self.target_lat).hypot(p.lon - self.target_lon),
+        }
+    }
+
+    /// Score of a pair under one of the travel scoring modes, or None when
+    /// arrival skew has a group that never arrives
+    #[inline]
+    fn travel_score(&self, a: &TrigPoint, b: &TrigPoint) -> Option<f64> {
+        if self.options.scoring_mode == ScoringMode::PickupRoute {
+            return Some(self.options.distance(a.lat, a.lon, b.lat, b.lon) + self.travel(b));
+        }
+        let (mut da, mut db) = (self.travel(a), self.travel(b));
+        if self.options.scoring_mode == ScoringMode::ArrivalSkew {
+            if let Some((speed_a, speed_b)) = self.options.group_speeds {
+                if speed_a == 0.0 || speed_b == 0.0 {
+                    return None;
+                }
+                da /= speed_a;
+                db /= speed_b;
+            }
+        }
+        Some(combine_travel(self.options.scoring_mode, da, db))
+    }
+
+    /// Score one pair, or None if an option filters it out
+    #[inline]
+    fn score(
+        &self,
+        index_a: usize,
+        a: &TrigPoint,
+        index_b: usize,
+        b: &TrigPoint,
+    ) -> Option<ComboResult> {
+        let options = self.options;
+
+        // Calculate midpoint
+        let (mid_lat, mid_lon) = match options.midpoint_method {
+            _ if options.scoring_mode == ScoringMode::PickupRoute => (b.lat, b.lon),
+            _ if options.geometry == Geometry::Planar => {
+                ((a.lat + b.lat) / 2.0, (a.lon + b.lon) / 2.0)
+            }
+            MidpointMethod::GreatCircle => geodesic_midpoint_trig(a, b),
+            MidpointMethod::Rhumb => rhumb_midpoint_deg(a.lat, a.lon, b.lat, b.lon),
+            MidpointMethod::Vincenty => {
+                let (lat, lon) = vincenty_midpoint_deg(a.lat, a.lon, b.lat, b.lon);
+                if lat.is_nan() {
+                    return None;
+                }
+                (lat, lon)
+            }
+        };
+
+        // Drop midpoints outside the corridor, if one is set
+        let mut corridor_score = None;
+        if let Some(corridor) = &options.corridor {
+            match corridor.offset(mid_lat, mid_lon) {
+                Some(offset) if corridor.score_by_offset => corridor_score = Some(offset),
+                Some(_) => {}
+                None => return None,
+            }
+        }
+
+        if let Some(regions) = &options.region_filter {
+            if !regions.allows(mid_lat, mid_lon) {
+                return None;
+            }
+        }
+
+        let mut forbidden_penalty = 0.0;
+        if let Some(zone) = &options.forbidden_zone {
+            let shortfall = zone.min_target_km
+                - options.distance(mid_lat, mid_lon, self.target_lat, self.target_lon);
+            if shortfall > 0.0 {
+                match zone.penalty_per_km {
+                    Some(penalty_per_km) => forbidden_penalty = shortfall * penalty_per_km,
+                    None => return None,
+                }
+            }
+        }
+
+        // Calculate score (distance from midpoint to the target or its stand-ins)
+        let anchors = (&options.target_line, &options.multi_target);
+        let mut score = match (corridor_score, anchors, &options.direction_weights) {
+            _ if options.scoring_mode != ScoringMode::TargetProximity => self.travel_score(a, b)?,
+            (Some(offset), _, _) => offset,
+            (None, (Some(line), _), _) => line.distance(mid_lat, mid_lon),
+            (None, (None, Some(targets)), _) => targets.distance(options, mid_lat, mid_lon),
+            _ if options.geometry == Geometry::Planar => {
+                (mid_lat - self.target_lat).hypot(mid_lon - self.target_lon)
+            }
+            (None, (None, None), Some(weights)) => {
+                weights.distance(mid_lat, mid_lon, self.target_lat, self.target_lon)
+            }
+            (None, (None, None), None) => haversine_distance_cos(
+                mid_lat,
+                mid_lon,
+                to_rad(mid_lat).cos(),
+                self.target_lat,
+                self.target_lon,
+                self.cos_target,
+            ),
+        };
+
+        if let Some(raster) = &options.cost_raster {
+            score *= raster.sample(mid_lat, mid_lon);
+        }
+        score += forbidden_penalty;
+
+        if let Some(target_bearing) = &options.target_bearing {
+            let deviation =
+                target_bearing.deviation(self.target_lat, self.target_lon, mid_lat, mid_lon);
+            if deviation > target_bearing.tolerance_deg {
+                return None;
+            }
+            score += deviation * target_bearing.penalty_km_per_deg;
+        }
+
+        if let Some(time_zones) = &options.time_zone_overlap {
+            if let Some(hours) = time_zones.overlap_hours(index_a, index_b) {
+                score -= hours * time_zones.bonus_km_per_hour;
+            }
+        }
+
+        if let Some(zone) = &options.bonus_zone {
+            if haversine_distance(mid_lat, mid_lon, zone.center_lat, zone.center_lon)
+                <= zone.radius_km
+            {
+                score -= zone.bonus_km;
+            }
+        }
+
+        let (score, mid_lat, mid_lon) = if options.fixed_point {
+            (
+                quantize(score, FIXED_POINT_STEPS_PER_KM),
+                quantize(mid_lat, FIXED_POINT_STEPS_PER_DEG),
+                quantize(mid_lon, FIXED_POINT_STEPS_PER_DEG),
+            )
+        } else {
+            (score, mid_lat, mid_lon)
+        };
+
+        if options
+            .max_score_km
+            .is_some_and(|max_score_km| score > max_score_km)
+        {
+            return None;
+        }
+
+        Some(ComboResult {
+            index_a: index_a as u32,
+            index_b: index_b as u32,
+            score,
+            midpoint_lat: mid_lat,
+            midpoint_lon: mid_lon,
+        })
+    }
+}
+
+/// Slack on `max_score_km` pruning, covering rounding in the distances
+const SCORE_CAP_SLACK_KM: f64 = 1e-3;
+
+/// Triangle-inequality pruning of pairs against `max_score_km`
+///
+/// The midpoint M of A and B is d(A, B) / 2 from each, and d(A, B) is at
+/// most dA + dB, their distances to the target T. So d(M, T) is at least
+/// |dA - dB| / 2, and when no option can score a pair below d(M, T), a pair
+/// with |dA - dB| over twice the cap can be skipped before its midpoint is
+/// computed. With B's distances sorted, a whole row of A is ruled out by one
+/// binary search, so a cap nothing meets costs O((|A| + |B|) log |B|).
+struct ScoreCapPruning {
+    /// Largest |dA - dB| that can still pass: twice the cap, plus slack
+    reach: f64,
+    dist_a: Vec<f64>,
+    dist_b: Vec<f64>,
+    sorted_b: Vec<f64>,
+}
+
+impl ScoreCapPruning {
+    /// None without a cap, under options that can score a pair below its
+    /// midpoint's distance, or when a distance is NaN
+    fn new(
+        trig_a: &[TrigPoint],
+        trig_b: &[TrigPoint],
+        target: &TrigPoint,
+        options: &CombinationOptions,
+    ) -> Option<Self> {
+        let cap = options.max_score_km?;
+        if !options.score_bounded_by_midpoint_distance() {
+            return None;
+        }
+
+        let distances = |points: &[TrigPoint]| -> Vec<f64> {
+            points
+                .iter()
+                .map(|p| options.distance(p.lat, p.lon, target.lat, target.lon))
+                .collect()
+        };
+        let dist_a = distances(trig_a);
+        let dist_b = distances(trig_b);
+        if dist_a.iter().chain(&dist_b).any(|d| d.is_nan()) {
+            return None;
+        }
+
+        let mut sorted_b = dist_b.clone();
+        sorted_b.sort_unstable_by(f64::total_cmp);
+        Some(ScoreCapPruning {
+            reach: 2.0 * (cap + SCORE_CAP_SLACK_KM),
+            dist_a,
+            dist_b,
+            sorted_b,
+        })
+    }
+
+    /// Whether any B is close enough in target distance to A's
+    #[inline]
+    fn row_can_pass(&self, index_a: usize) -> bool {
+        let d = self.dist_a[index_a];
+        let first = self.sorted_b.partition_point(|&x| x < d - self.reach);
+        self.sorted_b
+            .get(first)
+            .is_some_and(|&x| x <= d + self.reach)
+    }
+
+    #[inline]
+    fn pair_can_pass(&self, index_a: usize, index_b: usize) -> bool {
+        (self.dist_a[index_a] - self.dist_b[index_b]).abs() <= self.reach
+    }
+}
+
+/// Score A-B combinations by midpoint distance to the target
+///
+/// Kept results are handed to `visit` in (index A, index B) order. Stops once
+/// `max_combinations` pairs have been evaluated (filtered-out pairs included)
+/// or `visit` breaks, and returns how many pairs were evaluated.
+#[allow(clippy::too_many_arguments)]
+fn visit_combinations<F>(
+    trig_a: &[TrigPoint],
+    trig_b: &[TrigPoint],
+    target: &TrigPoint,
+    options: &CombinationOptions,
+    max_combinations: usize,
+    mut visit: F,
+) -> usize
+where
+    F: FnMut(ComboResult) -> ControlFlow<()>,
+{
+    let mut evaluated = 0;
+    let scorer = PairScorer::new(target, options);
+
+    let pruning = ScoreCapPruning::new(trig_a, trig_b, target, options);
+
+    // Calculate all combinations
+    'outer: for (i, a) in trig_a.iter().enumerate() {
+        if pruning.as_ref().is_some_and(|p| !p.row_can_pass(i)) {
+            // Nothing in this row can pass, but it still counts as evaluated
+            if max_combinations - evaluated <= trig_b.len() {
+                evaluated = max_combinations;
+                break;
+            }
+            evaluated += trig_b.len();
+            continue;
+        }
+
+        for (j, b) in trig_b.iter().enumerate() {
+            if evaluated == max_combinations {
+                break 'outer;
+            }
+            evaluated += 1;
+
+            if pruning.as_ref().is_some_and(|p| !p.pair_can_pass(i, j)) {
+                continue;
+            }
+            if let Some(result) = scorer.score(i, a, j, b) {
+                if visit(result).is_break() {
+                    break 'outer;
+                }
+            }
+        }
+    }
+
+    evaluated
+}
+
+/// Score every A-B combination by midpoint distance to the target
+fn score_all_combinations(
+    trig_a: &[TrigPoint],
+    trig_b: &[TrigPoint],
+    target: &TrigPoint,
+    options: &CombinationOptions,
+) -> Vec<ComboResult> {
+    // Pre-allocate for all combinations
+    let total_combos = trig_a.len() * trig_b.len();
+    let mut results: Vec<ComboResult> = Vec::with_capacity(total_combos);
+
+    visit_combinations(trig_a, trig_b, target, options, usize::MAX, |result| {
+        results.push(result);
+        ControlFlow::Continue(())
+    });
+
+    results
+}
+
+/// Total order on results: score, then index A, then index B
+///
+/// Breaking ties on the indices keeps output identical whichever selection
+/// path is taken.
+#[inline]
+fn compare_results(a: &ComboResult, b: &ComboResult) -> std::cmp::Ordering {
+    a.score
+        .partial_cmp(&b.score)
+        .unwrap_or(std::cmp::Ordering::Equal)
+        .then_with(|| a.index_a.cmp(&b.index_a))
+        .then_with(|| a.index_b.cmp(&b.index_b))
+}
+
+/// Heap entry ordered by `compare_results`, so the max-heap top is the worst kept result
+struct HeapEntry(ComboResult);
+
+impl PartialEq for HeapEntry {
+    fn eq(&self, other: &Self) -> bool {
+        compare_results(&self.0, &other.0).is_eq()
+    }
+}
+
+impl Eq for HeapEntry {}
+
+impl PartialOrd for HeapEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for HeapEntry {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        compare_results(&self.0, &other.0)
+    }
+}
+
+/// Bounded max-heap holding the best `n` results seen so far
+struct TopN {
+    n: usize,
+    heap: BinaryHeap<HeapEntry>,
+}
+
+impl TopN {
+    fn new(n: usize) -> Self {
+        TopN {
+            n,
+            heap: BinaryHeap::with_capacity(n),
+        }
+    }
+
+    #[inline]
+    fn push(&mut self, result: ComboResult) {
+        if self.heap.len() < self.n {
+            self.heap.push(HeapEntry(result));
+        } else if let Some(mut worst) = self.heap.peek_mut() {
+            if compare_results(&result, &worst.0).is_lt() {
+                *worst = HeapEntry(result);
+            }
+        }
+    }
+
+    /// Kept results, best first
+    fn into_sorted_vec(self) -> Vec<ComboResult> {
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|entry| entry.0)
+            .collect()
+    }
+}
+
+/// The bounded heap wins while top_n is at most this fraction of the results
+const HEAP_SELECT_RATIO: usize = 256;
+
+/// Move the best `top_n` results to the front in `compare_results` order
+/// Returns how many results are in that sorted prefix; anything after it is
+/// unspecified. Results must arrive in (index A, index B) order, as produced
+/// by `score_all_combinations`.
+fn select_top_n(results: &mut [ComboResult], top_n: usize) -> usize {
+    let n = top_n.min(results.len());
+
+    // Nothing to select (empty group or top_n = 0); select_nth would panic
+    // on an empty slice
+    if n == 0 {
+        return 0;
+    }
+
+    if n == results.len() {
+        // Everything is returned, so a single full sort is all that's needed.
+        // The sort is stable and the input is in index order, so ties land
+        // exactly where compare_results would put them without its slower
+        // tie-break.
+        results.sort_by(|a, b| {
+            a.score
+                .partial_cmp(&b.score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+    } else if n.saturating_mul(HEAP_SELECT_RATIO) <= results.len() {
+        // A small bounded heap touches each result once and rarely replaces
+        let mut top = TopN::new(n);
+        for result in results.iter() {
+            top.push(*result);
+        }
+        results[..n].copy_from_slice(&top.into_sorted_vec());
+    } else {
+        // Partial sort to get top N (faster than full sort for large arrays)
+        results.select_nth_unstable_by(n - 1, compare_results);
+
+        // Sort just the top N
+        results[..n].sort_unstable_by(compare_results);
+    }
+
+    n
+}
+
+/// Context for writing result records: the options plus the inputs they refer to
+///
+/// Points must be in lat,lon degrees; they are only read by the extra fields.
+#[derive(Clone, Copy)]
+struct OutputOptions<'a> {
+    options: &'a CombinationOptions,
+    points_a: &'a [f64],
+    points_b: &'a [f64],
+    target_lat: f64,
+    target_lon: f64,
+}
+
+/// Append one result record, exactly `opts.options.stride()` values
+///
+/// Field order is stable, each extra only present when enabled:
+/// indexA, indexB, score, midpoint (2, in the options' coordinate order and
+/// angle unit), quality, pair id, max travel, midpoint azimuth, A and B
+/// coordinates (4), target alignment, tier. New fields go at the end, and
+/// into `stride` with them.
+fn push_combo(output: &mut Vec<f64>, r: &ComboResult, opts: OutputOptions) {
+    let OutputOptions {
+        options,
+        points_a,
+        points_b,
+        target_lat,
+        target_lon,
+    } = opts;
+    let start = output.len();
+    let a = r.index_a as usize * 2;
+    let b = r.index_b as usize * 2;
+
+    output.push(r.index_a as f64);
+    output.push(r.index_b as f64);
+    output.push(options.output_score(r.score));
+    options.push_coordinate(output, r.midpoint_lat, r.midpoint_lon);
+
+    if options.include_quality {
+        output.push(midpoint_quality(
+            points_a[a],
+            points_a[a + 1],
+            points_b[b],
+            points_b[b + 1],
+            target_lat,
+            target_lon,
+        ));
+    }
+
+    if options.include_pair_id {
+        let num_b = points_b.len() / 2;
+        output.push(r.index_a as f64 * num_b as f64 + r.index_b as f64);
+    }
+
+    if options.include_max_travel {
+        let (mid_lat, mid_lon) = (r.midpoint_lat, r.midpoint_lon);
+        output.push(
+            options
+                .distance(points_a[a], points_a[a + 1], mid_lat, mid_lon)
+                .max(options.distance(points_b[b], points_b[b + 1], mid_lat, mid_lon)),
+        );
+    }
+
+    if options.include_midpoint_azimuth {
+        output.push(initial_bearing(
+            r.midpoint_lat,
+            r.midpoint_lon,
+            points_b[b],
+            points_b[b + 1],
+        ));
+    }
+
+    if options.include_coordinates {
+        options.push_coordinate(output, points_a[a], points_a[a + 1]);
+        options.push_coordinate(output, points_b[b], points_b[b + 1]);
+    }
+
+    if options.include_target_alignment {
+        output.push(bearing_difference(
+            initial_bearing(points_a[a], points_a[a + 1], points_b[b], points_b[b + 1]),
+            initial_bearing(r.midpoint_lat, r.midpoint_lon, target_lat, target_lon),
+        ));
+    }
+
+    if let Some(boundaries) = &options.tier_boundaries {
+        output
+            .push(boundaries.partition_point(|&bound| r.score >= bound || r.score.is_nan()) as f64);
+    }
+
+    debug_assert_eq!(output.len() - start, options.stride());
+}
+
+/// Flatten results into the [indexA, indexB, score, midLat, midLon, ...] layout
+///
+/// Each record is written by `push_combo`; with `score_as_rank` the score
+/// field is then replaced by the dense rank. Points must be in lat,lon
+/// degrees.
+fn flatten_results(
+    results: &[ComboResult],
+    points_a: &[f64],
+    points_b: &[f64],
+    target_lat: f64,
+    target_lon: f64,
+    options: &CombinationOptions,
+) -> Vec<f64> {
+    let opts = OutputOptions {
+        options,
+        points_a,
+        points_b,
+        target_lat,
+        target_lon,
+    };
+    let mut output = Vec::with_capacity(results.len() * options.stride());
+    let mut rank = 0;
+    for (i, result) in results.iter().enumerate() {
+        let start = output.len();
+        push_combo(&mut output, result, opts);
+        if options.score_as_rank {
+            if i == 0 || result.score != results[i - 1].score {
+                rank += 1;
+            }
+            output[start + 2] = rank as f64;
+        }
+    }
+
+    output
+}
+
+/// Calculate all combination scores and return top N results
+///
+/// Input arrays are flat: [lat0, lon0, lat1, lon1, ...]
+/// Returns flat array: [indexA, indexB, score, midLat, midLon, ...] for top N
+#[wasm_bindgen]
+pub fn find_best_combinations(
+    points_a: &[f64],
+    points_b: &[f64],
+    target_lat: f64,
+    target_lon: f64,
+    top_n: usize,
+) -> Vec<f64> {
+    find_best_combinations_with_options(
+        points_a,
+        points_b,
+        target_lat,
+        target_lon,
+        top_n,
+        &CombinationOptions::default(),
+    )
+}
+
+/// The A-B pair whose midpoint is nearest the centroid of everyone
+///
+/// Input arrays are flat: [lat0, lon0, lat1, lon1, ...]
+/// The target is the 3D-vector centroid of all A and B points together,
+/// each counted once, so the answer is the group's natural meeting pair.
+/// Ties follow the engine's order.
+/// Returns [indexA, indexB, distKm, midLat, midLon], or an empty array if a
+/// group is empty or the centroid is undefined (points cancelling out)
+#[wasm_bindgen]
+pub fn most_central_pair(points_a: &[f64], points_b: &[f64]) -> Vec<f64> {
+    let sum_a = weighted_vector_sum(points_a, &vec![1.0; points_a.len() / 2]);
+    let sum_b = weighted_vector_sum(points_b, &vec![1.0; points_b.len() / 2]);
+    let sum = [
+        sum_a[0] + sum_b[0],
+        sum_a[1] + sum_b[1],
+        sum_a[2] + sum_b[2],
+    ];
+    if norm(sum) < 1e-12 {
+        return Vec::new();
+    }
+
+    let (center_lat, center_lon) = from_vector(sum);
+    find_best_combinations(points_a, points_b, center_lat, center_lon, 1)
+}
+
+/// Nelder-Mead step budget and convergence tolerance for `centrality_deficit`
+const CENTRALITY_MAX_ITERS: usize = 4000;
+const CENTRALITY_TOL_KM: f64 = 1e-3;
+
+/// How far the best combination falls short of the best midpoint possible
+///
+/// Input arrays are flat: [lat0, lon0, lat1, lon1, ...]
+/// The ideal lets the best pair's two participants move anywhere inside
+/// their group's `convex_hull` and minimizes the engine's midpoint-to-target
+/// score over those continuous positions with the Nelder-Mead optimizer
+/// behind `optimize_meeting_point`, in km east and north of the pair.
+/// Leaving a hull costs twice the distance outside it, more than the
+/// midpoint can gain, so the optimum stays inside. The search starts at the
+/// best pair itself and never accepts a worse point, so the ideal is at
+/// most the best score and the deficit is never negative. A small deficit
+/// means the discrete pairs are nearly optimal; a large one that better
+/// venues could exist between the participants.
+/// Returns [bestComboScore, idealScore, deficitKm], or an empty array if a
+/// group is empty
+#[wasm_bindgen]
+pub fn centrality_deficit(
+    points_a: &[f64],
+    points_b: &[f64],
+    target_lat: f64,
+    target_lon: f64,
+) -> Vec<f64> {
+    let best = find_best_combinations(points_a, points_b, target_lat, target_lon, 1);
+    let Some(&best_score) = best.get(2) else {
+        return Vec::new();
+    };
+    let (a, b) = (best[0] as usize * 2, best[1] as usize * 2);
+    let (seed_a, seed_b) = (
+        (points_a[a], points_a[a + 1]),
+        (points_b[b], points_b[b + 1]),
+    );
+
+    let hull_a = convex_hull(points_a);
+    let hull_b = convex_hull(points_b);
+    let options = CombinationOptions::default();
+    let target = TrigPoint::new(target_lat, target_lon);
+    let scorer = PairScorer::new(&target, &options);
+
+    // A participant offset from their seed, with the km it lies outside
+    // the hull; unmoved they are an input point, so inside it
+    let place = |(lat, lon): (f64, f64), east: f64, north: f64, hull: &[f64]| {
+        if east == 0.0 && north == 0.0 {
+            return (TrigPoint::new(lat, lon), 0.0);
+        }
+        let (lat, lon) = destination(lat, lon, to_deg(east.atan2(north)), east.hypot(north));
+        (
+            TrigPoint::new(lat, lon),
+            distance_to_polygon_boundary(lat, lon, hull).max(0.0),
+        )
+    };
+    let cost = |v: [f64; 4]| {
+        let (pa, outside_a) = place(seed_a, v[0], v[1], &hull_a);
+        let (pb, outside_b) = place(seed_b, v[2], v[3], &hull_b);
+        let score = scorer
+            .score(0, &pa, 0, &pb)
+            .map_or(f64::INFINITY, |r| r.score);
+        score + 2.0 * (outside_a + outside_b)
+    };
+
+    let size = best_score.max(CENTRALITY_TOL_KM);
+    let (_, ideal_score) = nelder_mead_restarted(
+        cost,
+        [0.0; 4],
+        size,
+        CENTRALITY_TOL_KM,
+        CENTRALITY_MAX_ITERS,
+    );
+
+    vec![best_score, ideal_score, best_score - ideal_score]
+}
+
+/// Rank every combination, returning only pair ids in score order
+///
+/// Input arrays are flat: [lat0, lon0, lat1, lon1, ...]
+/// Returns [pairId0, pairId1, ...] best first, with pair id
+/// indexA * numB + indexB (as with `set_include_pair_id`), in the same order
+/// as `find_best_combinations` with top_n covering every pair. Ids must fit
+/// in a u32, so up to about 4.29 billion combinations.
+#[wasm_bindgen]
+pub fn rank_combinations(
+    points_a: &[f64],
+    points_b: &[f64],
+    target_lat: f64,
+    target_lon: f64,
+) -> Vec<u32> {
+    let num_b = (points_b.len() / 2) as u32;
+    let mut results = score_all_combinations(
+        &precompute_trig(points_a),
+        &precompute_trig(points_b),
+        &TrigPoint::new(target_lat, target_lon),
+        &CombinationOptions::default(),
+    );
+    let n = select_top_n(&mut results, usize::MAX);
+
+    results[..n]
+        .iter()
+        .map(|result| result.index_a * num_b + result.index_b)
+        .collect()
+}
+
+/// Fraction of all combinations that score worse than a given pair
+///
+/// Input arrays are flat: [lat0, lon0, lat1, lon1, ...]
+/// Streams over every pair once without storing results, so memory stays
+/// constant. Returns count(score > pair's score) / total combinations, in
+/// [0, 1): 0.87 means the pair beats 87% of all options. NaN if an index is
+/// out of range.
+#[wasm_bindgen]
+pub fn score_percentile(
+    points_a: &[f64],
+    points_b: &[f64],
+    target_lat: f64,
+    target_lon: f64,
+    a_index: usize,
+    b_index: usize,
+) -> f64 {
+    if a_index >= points_a.len() / 2 || b_index >= points_b.len() / 2 {
+        return f64::NAN;
+    }
+
+    let options = CombinationOptions::default();
+    let trig_a = precompute_trig(points_a);
+    let trig_b = precompute_trig(points_b);
+    let target = TrigPoint::new(target_lat, target_lon);
+    let scorer = PairScorer::new(&target, &options);
+    let Some(pair) = scorer.score(a_index, &trig_a[a_index], b_index, &trig_b[b_index]) else {
+        return f64::NAN;
+    };
+
+    let mut worse = 0usize;
+    let total = visit_combinations(&trig_a, &trig_b, &target, &options, usize::MAX, |result| {
+        if result.score > pair.score {
+            worse += 1;
+        }
+        ControlFlow::Continue(())
+    });
+
+    worse as f64 / total as f64
+}
+
+/// P² streaming estimator of one quantile (Jain & Chlamtac, 1985)
+///
+/// Tracks five markers whose heights approximate the minimum, p/2, p,
+/// (1+p)/2 and maximum quantiles, nudging them with piecewise-parabolic
+/// interpolation as values arrive. Constant memory per quantile.
+struct P2Quantile {
+    p: f64,
+    count: usize,
+    heights: [f64; 5],
+    positions: [f64; 5],
+    desired: [f64; 5],
+    increments: [f64; 5],
+}
+
+impl P2Quantile {
+    fn new(p: f64) -> Self {
+        P2Quantile {
+            p,
+            count: 0,
+            heights: [0.0; 5],
+            positions: [0.0, 1.0, 2.0, 3.0, 4.0],
+            desired: [0.0, 2.0 * p, 4.0 * p, 2.0 + 2.0 * p, 4.0],
+            increments: [0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0],
+        }
+    }
+
+    fn push(&mut self, x: f64) {
+        if self.count < 5 {
+            self.heights[self.count] = x;
+            self.count += 1;
+            if self.count == 5 {
+                self.heights
+                    .sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+            }
+            return;
+        }
+        self.count += 1;
+
+        // Find the cell x falls in, stretching the extremes if needed
+        let q = &mut self.heights;
+        let k = if x < q[0] {
+            q[0] = x;
+            0
+        } else if x >= q[4] {
+            q[4] = x;
+            3
+        } else {
+            (0..4).find(|&i| x < q[i + 1]).unwrap_or(3)
+        };
+        for position in &mut self.positions[k + 1..] {
+            *position += 1.0;
+        }
+        for (desired, increment) in self.desired.iter_mut().zip(&self.increments) {
+            *desired += increment;
+        }
+
+        // Move the middle markers back towards their desired positions
+        let n = &mut self.positions;
+        for i in 1..4 {
+            let offset = self.desired[i] - n[i];
+            if (offset >= 1.0 && n[i + 1] - n[i] > 1.0)
+                || (offset <= -1.0 && n[i - 1] - n[i] < -1.0)
+            {
+                let d = offset.signum();
+                let parabolic = q[i]
+                    + d / (n[i + 1] - n[i - 1])
+                        * ((n[i] - n[i - 1] + d) * (q[i + 1] - q[i]) / (n[i + 1] - n[i])
+                            + (n[i + 1] - n[i] - d) * (q[i] - q[i - 1]) / (n[i] - n[i - 1]));
+                q[i] = if q[i - 1] < parabolic && parabolic < q[i + 1] {
+                    parabolic
+                } else {
+                    let j = if d > 0.0 { i + 1 } else { i - 1 };
+                    q[i] + d * (q[j] - q[i]) / (n[j] - n[i])
+                };
+                n[i] += d;
+            }
+        }
+    }
+
+    /// Current estimate; exact while fewer than five values have been seen
+    fn estimate(&self) -> f64 {
+        if self.count >= 5 {
+            return self.heights[2];
+        }
+        if self.count == 0 {
+            return f64::NAN;
+        }
+        let mut seen = self.heights[..self.count].to_vec();
+        seen.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        seen[(self.p * (self.count - 1) as f64).round() as usize]
+    }
+}
+
+/// Approximate quantiles of all combination scores in one bounded-memory pass
+///
+/// Input arrays are flat: [lat0, lon0, lat1, lon1, ...]
+/// Nothing is stored per combination: each requested quantile (clamped to
+/// [0, 1]) runs its own P² estimator, and 0 and 1 are tracked exactly as the
+/// minimum and maximum. P² has no worst-case error bound; on smooth score
+/// distributions with more than a few thousand combinations its estimates
+/// are typically within a fraction of a percent of the score range, while
+/// lumpy distributions (heavily duplicated inputs) can do worse.
+/// Returns one estimate per entry of `quantiles`, or NaNs with no combinations.
+#[wasm_bindgen]
+pub fn score_quantiles(
+    points_a: &[f64],
+    points_b: &[f64],
+    target_lat: f64,
+    target_lon: f64,
+    quantiles: &[f64],
+) -> Vec<f64> {
+    let mut estimators: Vec<P2Quantile> = quantiles
+        .iter()
+        .map(|&q| P2Quantile::new(q.clamp(0.0, 1.0)))
+        .collect();
+    let mut min = f64::INFINITY;
+    let mut max = f64::NEG_INFINITY;
+
+    let options = CombinationOptions::default();
+    let evaluated = visit_combinations(
+        &precompute_trig(points_a),
+        &precompute_trig(points_b),
+        &TrigPoint::new(target_lat, target_lon),
+        &options,
+        usize::MAX,
+        |result| {
+            min = min.min(result.score);
+            max = max.max(result.score);
+            for estimator in &mut estimators {
+                estimator.push(result.score);
+            }
+            ControlFlow::Continue(())
+        },
+    );
+    if evaluated == 0 {
+        return vec![f64::NAN; quantiles.len()];
+    }
+
+    estimators
+        .iter()
+        .map(|estimator| match estimator.p {
+            0.0 => min,
+            1.0 => max,
+            _ => estimator.estimate(),
+        })
+        .collect()
+}
+
+/// Calculate top N results with optional filters and scoring tweaks
+///
+/// Same input and output layout as `find_best_combinations`, plus any extra
+/// fields the options enable; `options.stride()` gives the record length
+#[wasm_bindgen]
+pub fn find_best_combinations_with_options(
+    points_a: &[f64],
+    points_b: &[f64],
+    target_lat: f64,
+    target_lon: f64,
+    top_n: usize,
+    options: &CombinationOptions,
+) -> Vec<f64> {
+    let trig_a = options.input_trig(points_a);
+    let trig_b = options.input_trig(points_b);
+    let target = options.input_trig_point(target_lat, target_lon);
+    let points_a = options.input_points(points_a);
+    let points_b = options.input_points(points_b);
+    let (target_lat, target_lon) = (target.lat, target.lon);
+
+    let mut results = score_all_combinations(&trig_a, &trig_b, &target, options);
+    if options.needs_greedy_selection() {
+        let mut per_a = HashMap::new();
+        let kept = select_top_n_accepted(&mut results, top_n, |result, kept| {
+            options.is_separated(result, kept) && options.take_quota(result, &mut per_a)
+        });
+        return flatten_results(&kept, &points_a, &points_b, target_lat, target_lon, options);
+    }
+    let n = select_top_n(&mut results, top_n);
+
+    flatten_results(
+        &results[..n],
+        &points_a,
+        &points_b,
+        target_lat,
+        target_lon,
+        options,
+    )
+}
+
+/// Calculate top N results on several threads, identical to the serial run
+///
+/// Same arguments and output as `find_best_combinations_with_options`, plus
+/// the thread count (0 uses the available parallelism). A's index range is
+/// split into fixed contiguous chunks, one per thread, rather than handed out
+/// by work stealing; each chunk keeps its own top N under `compare_results`
+/// and the chunks are merged with that same comparator. As it is a total
+/// order (score, then index A, then index B), which tied pairs make the cut
+/// never depends on scheduling, and the output is byte-identical to the
+/// serial function for any thread count. Options that select greedily
+/// (result separation, per-A quota) run serially.
+/// Native builds with the `parallel` feature only, as wasm has no threads.
+#[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+pub fn find_best_combinations_parallel(
+    points_a: &[f64],
+    points_b: &[f64],
+    target_lat: f64,
+    target_lon: f64,
+    top_n: usize,
+    options: &CombinationOptions,
+    threads: usize,
+) -> Vec<f64> {
+    if options.needs_greedy_selection() {
+        return find_best_combinations_with_options(
+            points_a, points_b, target_lat, target_lon, top_n, options,
+        );
+    }
+
+    let trig_a = options.input_trig(points_a);
+    let trig_b = options.input_trig(points_b);
+    let target = options.input_trig_point(target_lat, target_lon);
+    let points_a = options.input_points(points_a);
+    let points_b = options.input_points(points_b);
+    let (target_lat, target_lon) = (target.lat, target.lon);
+    let scorer = PairScorer::new(&target, options);
+
+    let threads = match threads {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    let chunk_len = trig_a.len().div_ceil(threads).max(1);
+
+    let mut merged: Vec<ComboResult> = std::thread::scope(|scope| {
+        let workers: Vec<_> = trig_a
+            .chunks(chunk_len)
+            .enumerate()
+            .map(|(chunk, chunk_a)| {
+                let (trig_b, scorer) = (&trig_b, &scorer);
+                scope.spawn(move || {
+                    let mut top = TopN::new(top_n.min(chunk_a.len() * trig_b.len()));
+                    for (offset, a) in chunk_a.iter().enumerate() {
+                        let i = chunk * chunk_len + offset;
+                        for (j, b) in trig_b.iter().enumerate() {
+                            if let Some(result) = scorer.score(i, a, j, b) {
+                                top.push(result);
+                            }
+                        }
+                    }
+                    top.into_sorted_vec()
+                })
+            })
+            .collect();
+
+        // Joined in chunk order, though the sort below makes order irrelevant
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("scoring thread panicked"))
+            .collect()
+    });
+    merged.sort_unstable_by(compare_results);
+    merged.truncate(top_n);
+
+    flatten_results(
+        &merged, &points_a, &points_b, target_lat, target_lon, options,
+    )
+}
+
+#[wasm_bindgen]
+extern "C" {
+    /// A JS function `(lat, lon) => boolean`, e.g. a land-mask lookup
+    #[wasm_bindgen(typescript_type = "(lat: number, lon: number) => boolean")]
+    pub type MidpointPredicate;
+
+    #[wasm_bindgen(method, catch, js_name = call)]
+    fn call_with(
+        this: &MidpointPredicate,
+        context: &JsValue,
+        lat: f64,
+        lon: f64,
+    ) -> Result<JsValue, JsValue>;
+}
+
+/// Best `top_n` results in `compare_results` order that pass `accept`
+///
+/// Candidates are drawn best-first in doubling batches, so `accept` only sees
+/// results that would make the top N if everything before them passed. It is
+/// also given the results accepted so far.
+fn select_top_n_accepted<F>(
+    results: &mut [ComboResult],
+    top_n: usize,
+    mut accept: F,
+) -> Vec<ComboResult>
+where
+    F: FnMut(&ComboResult, &[ComboResult]) -> bool,
+{
+    let mut accepted = Vec::with_capacity(top_n.min(results.len()));
+    let mut start = 0;
+    let mut batch = top_n;
+
+    while accepted.len() < top_n && start < results.len() {
+        // Unlike select_top_n this keeps the slice a permutation, so later
+        // batches still see every remaining result exactly once
+        let rest = &mut results[start..];
+        let taken = if batch >= rest.len() {
+            rest.sort_unstable_by(compare_results);
+            rest.len()
+        } else {
+            rest.select_nth_unstable_by(batch - 1, compare_results);
+            rest[..batch].sort_unstable_by(compare_results);
+            batch
+        };
+
+        for result in &rest[..taken] {
+            if accepted.len() == top_n {
+                break;
+            }
+            if accept(result, &accepted) {
+                accepted.push(*result);
+            }
+        }
+
+        start += taken;
+        batch = batch.saturating_mul(2);
+    }
+
+    accepted
+}
+
+/// Calculate top N results, keeping only midpoints a JS predicate accepts
+///
+/// `is_land` is called with each candidate midpoint as (lat, lon) in degrees,
+/// whatever the options' order and unit. It is only called lazily, best
+/// candidate first, until N results pass: about N calls when few are rejected,
+/// plus one per rejection. Every pair is still scored, but with no more than
+/// a handful of boundary crossings per result the callback stays cheap even
+/// for millions of combinations. If the predicate throws, the exception is
+/// treated as a rejection.
+/// Same output layout as `find_best_combinations_with_options`.
+#[wasm_bindgen]
+pub fn find_best_combinations_masked(
+    points_a: &[f64],
+    points_b: &[f64],
+    target_lat: f64,
+    target_lon: f64,
+    top_n: usize,
+    options: &CombinationOptions,
+    is_land: &MidpointPredicate,
+) -> Vec<f64> {
+    let on_land = |lat: f64, lon: f64| {
+        is_land
+            .call_with(&JsValue::NULL, lat, lon)
+            .is_ok_and(|verdict| verdict.is_truthy())
+    };
+    find_best_combinations_masked_by(
+        points_a, points_b, target_lat, target_lon, top_n, options, on_land,
+    )
+}
+
+/// `find_best_combinations_masked` with the predicate as a Rust closure
+fn find_best_combinations_masked_by<F>(
+    points_a: &[f64],
+    points_b: &[f64],
+    target_lat: f64,
+    target_lon: f64,
+    top_n: usize,
+    options: &CombinationOptions,
+    mut is_land: F,
+) -> Vec<f64>
+where
+    F: FnMut(f64, f64) -> bool,
+{
+    let trig_a = options.input_trig(points_a);
+    let trig_b = options.input_trig(points_b);
+    let target = options.input_trig_point(target_lat, target_lon);
+    let points_a = options.input_points(points_a);
+    let points_b = options.input_points(points_b);
+    let (target_lat, target_lon) = (target.lat, target.lon);
+
+    let mut results = score_all_combinations(&trig_a, &trig_b, &target, options);
+    let mut per_a = HashMap::new();
+    let accepted = select_top_n_accepted(&mut results, top_n, |result, kept| {
+        // Check separation first, it's far cheaper than a call into JS
+        options.is_separated(result, kept)
+            && is_land(result.midpoint_lat, result.midpoint_lon)
+            && options.take_quota(result, &mut per_a)
+    });
+
+    flatten_results(
+        &accepted, &points_a, &points_b, target_lat, target_lon, options,
+    )
+}
+
+/// Calculate top N results, giving up after a budget of evaluated combinations
+///
+/// The running top N is kept throughout, so stopping early still returns the
+/// best of everything evaluated so far.
+/// Returns flat array: [partial, evaluated, ...records], where `partial` is 1
+/// if the budget ran out before every combination was scored, `evaluated` is
+/// the number of pairs scored, and records follow the
+/// `find_best_combinations_with_options` layout.
+#[wasm_bindgen]
+pub fn find_best_combinations_partial(
+    points_a: &[f64],
+    points_b: &[f64],
+    target_lat: f64,
+    target_lon: f64,
+    top_n: usize,
+    max_combinations: usize,
+    options: &CombinationOptions,
+) -> Vec<f64> {
+    let trig_a = options.input_trig(points_a);
+    let trig_b = options.input_trig(points_b);
+    let target = options.input_trig_point(target_lat, target_lon);
+    let points_a = options.input_points(points_a);
+    let points_b = options.input_points(points_b);
+    let (target_lat, target_lon) = (target.lat, target.lon);
+    let total_combos = trig_a.len() * trig_b.len();
+
+    let mut top = TopN::new(top_n.min(total_combos));
+    let evaluated = visit_combinations(
+        &trig_a,
+        &trig_b,
+        &target,
+        options,
+        max_combinations,
+        |result| {
+            top.push(result);
+            ControlFlow::Continue(())
+        },
+    );
+
+    let partial = evaluated < total_combos;
+    let mut output = vec![partial as u8 as f64, evaluated as f64];
+    output.extend(flatten_results(
+        &top.into_sorted_vec(),
+        &points_a,
+        &points_b,
+        target_lat,
+        target_lon,
+        options,
+    ));
+
+    output
+}
+
+/// Which of the two point groups a point belongs to
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PointGroup {
+    A,
+    B,
+}
+
+/// Top N combinations kept up to date as single points move
+///
+/// Every pair's result is cached, so moving a point rescores only its row
+/// (group A) or column (group B): O(|B|) or O(|A|) pair scorings instead of
+/// O(|A|·|B|). If none of the current top N involve the moved point, the new
+/// top N is merged from the old one and the rescored pairs alone; otherwise
+/// it is reselected from the cache, which is a linear pass but skips all the
+/// trig. The result separation and per-A quota options always reselect, as
+/// their greedy pick can change anywhere in the ranking. In a native release
+/// build with 2000×2000 points (`cargo bench --bench incremental`), a merging
+/// update takes under 1 ms and a reselect about 20 ms, against about 500 ms
+/// for a full recompute. The cache takes about 40 bytes per pair.
+#[wasm_bindgen]
+pub struct IncrementalCombinations {
+    points_a: Vec<f64>,
+    points_b: Vec<f64>,
+    trig_a: Vec<TrigPoint>,
+    trig_b: Vec<TrigPoint>,
+    target: TrigPoint,
+    top_n: usize,
+    options: CombinationOptions,
+    cache: Vec<Option<ComboResult>>,
+    top: Vec<ComboResult>,
+}
+
+#[wasm_bindgen]
+impl IncrementalCombinations {
+    /// Score every combination once; same arguments as
+    /// `find_best_combinations_with_options`
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        points_a: &[f64],
+        points_b: &[f64],
+        target_lat: f64,
+        target_lon: f64,
+        top_n: usize,
+        options: &CombinationOptions,
+    ) -> IncrementalCombinations {
+        let trig_a = options.input_trig(points_a);
+        let trig_b = options.input_trig(points_b);
+        let target = options.input_trig_point(target_lat, target_lon);
+
+        let num_b = trig_b.len();
+        let mut cache = vec![None; trig_a.len() * num_b];
+        visit_combinations(&trig_a, &trig_b, &target, options, usize::MAX, |result| {
+            cache[result.index_a as usize * num_b + result.index_b as usize] = Some(result);
+            ControlFlow::Continue(())
+        });
+
+        let mut combos = IncrementalCombinations {
+            points_a: options.input_points(points_a).into_owned(),
+            points_b: options.input_points(points_b).into_owned(),
+            trig_a,
+            trig_b,
+            target,
+            top_n,
+            options: options.clone(),
+            cache,
+            top: Vec::new(),
+        };
+        combos.reselect();
+        combos
+    }
+
+    /// Move one point and refresh the affected combinations
+    ///
+    /// `lat`/`lon` are in the options' angle unit.
+    pub fn update_point(
+        &mut self,
+        group: PointGroup,
+        index: usize,
+        lat: f64,
+        lon: f64,
+    ) -> Result<(), JsError> {
+        let num_a = self.points_a.len() / 2;
+        let num_b = self.points_b.len() / 2;
+        let (points, count) = match group {
+            PointGroup::A => (&mut self.points_a, num_a),
+            PointGroup::B => (&mut self.points_b, num_b),
+        };
+        if index >= count {
+            return Err(JsError::new(&format!(
+                "point index {index} out of range for {count} points"
+            )));
+        }
+        let moved = self.options.input_trig_point(lat, lon);
+        points[index * 2] = moved.lat;
+        points[index * 2 + 1] = moved.lon;
+
+        let scorer = PairScorer::new(&self.target, &self.options);
+        let mut rescored = Vec::new();
+        match group {
+            PointGroup::A => {
+                self.trig_a[index] = moved;
+                for (j, b) in self.trig_b.iter().enumerate() {
+                    let result = scorer.score(index, &moved, j, b);
+                    self.cache[index * num_b + j] = result;
+                    rescored.extend(result);
+                }
+            }
+            PointGroup::B => {
+                self.trig_b[index] = moved;
+                for (i, a) in self.trig_a.iter().enumerate() {
+                    let result = scorer.score(i, a, index, &moved);
+                    self.cache[i * num_b + index] = result;
+                    rescored.extend(result);
+                }
+            }
+        }
+
+        let index = index as u32;
+        let involved = |result: &ComboResult| match group {
+            PointGroup::A => result.index_a == index,
+            PointGroup::B => result.index_b == index,
+        };
+        if self.options.needs_greedy_selection() || self.top.iter().any(involved) {
+            // A kept pair may have got worse, so its replacement could be
+            // anywhere; greedy options can also let a rejected pair back in
+            self.reselect();
+        } else {
+            // Everything outside the old top N is unchanged and already beaten
+            let mut top = TopN::new(self.top_n);
+            for result in self.top.drain(..).chain(rescored) {
+                top.push(result);
+            }
+            self.top = top.into_sorted_vec();
+        }
+
+        Ok(())
+    }
+
+    /// Current top N in the `find_best_combinations_with_options` layout
+    pub fn results(&self) -> Vec<f64> {
+        flatten_results(
+            &self.top,
+            &self.points_a,
+            &self.points_b,
+            self.target.lat,
+            self.target.lon,
+            &self.options,
+        )
+    }
+}
+
+impl IncrementalCombinations {
+    /// Select the top N from the whole cache
+    fn reselect(&mut self) {
+        if self.options.needs_greedy_selection() {
+            let mut results: Vec<ComboResult> = self.cache.iter().flatten().copied().collect();
+            let mut per_a = HashMap::new();
+            let options = &self.options;
+            self.top = select_top_n_accepted(&mut results, self.top_n, |result, kept| {
+                options.is_separated(result, kept) && options.take_quota(result, &mut per_a)
+            });
+        } else if self.top_n.saturating_mul(HEAP_SELECT_RATIO) <= self.cache.len() {
+            // Feed the heap straight from the cache rather than copying it out
+            let mut top = TopN::new(self.top_n);
+            for result in self.cache.iter().flatten() {
+                top.push(*result);
+            }
+            self.top = top.into_sorted_vec();
+        } else {
+            let mut results: Vec<ComboResult> = self.cache.iter().flatten().copied().collect();
+            let n = select_top_n(&mut results, self.top_n);
+            results.truncate(n);
+            self.top = results;
+        }
+    }
+}
+
+/// Top N combinations kept up to date as points join either group
+///
+/// Adding a point only scores it against the other group, O(|other|) pair
+/// scorings, and merges those and the held top N through a bounded heap, in
+/// O((|other| + N) log N) more. Growing both groups to n points this way
+/// scores each of the n² pairs once, like a batch run, so an add costs
+/// O(n log N) amortized. Existing pairs never change, so after any sequence
+/// of adds the top N equals a batch run over the same points. Only the top
+/// N and the points themselves are kept, not a cache of every pair, which
+/// is why the result separation and per-A quota options, whose greedy pick
+/// needs every candidate, are refused.
+#[wasm_bindgen]
+pub struct StreamingCombiner {
+    points_a: Vec<f64>,
+    points_b: Vec<f64>,
+    trig_a: Vec<TrigPoint>,
+    trig_b: Vec<TrigPoint>,
+    target: TrigPoint,
+    top_n: usize,
+    options: CombinationOptions,
+    top: Vec<ComboResult>,
+}
+
+#[wasm_bindgen]
+impl StreamingCombiner {
+    /// Start with both groups empty; the target is in the options' angle unit
+    ///
+    /// Errors when the options set a result separation or `max_per_a`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        target_lat: f64,
+        target_lon: f64,
+        top_n: usize,
+        options: &CombinationOptions,
+    ) -> Result<StreamingCombiner, JsError> {
+        if options.needs_greedy_selection() {
+            return Err(JsError::new(
+                "streaming cannot apply result separation or max_per_a",
+            ));
+        }
+        Ok(StreamingCombiner {
+            points_a: Vec::new(),
+            points_b: Vec::new(),
+            trig_a: Vec::new(),
+            trig_b: Vec::new(),
+            target: options.input_trig_point(target_lat, target_lon),
+            top_n,
+            options: options.clone(),
+            top: Vec::new(),
+        })
+    }
+
+    /// Add a point to group A and score it against all of B
+    ///
+    /// `lat`/`lon` are in the options' angle unit. Returns the new point's index.
+    pub fn add_a(&mut self, lat: f64, lon: f64) -> usize {
+        self.add(PointGroup::A, lat, lon)
+    }
+
+    /// Add a point to group B and score it against all of A
+    ///
+    /// `lat`/`lon` are in the options' angle unit. Returns the new point's index.
+    pub fn add_b(&mut self, lat: f64, lon: f64) -> usize {
+        self.add(PointGroup::B, lat, lon)
+    }
+
+    /// Current top N in the `find_best_combinations_with_options` layout
+    pub fn best(&self) -> Vec<f64> {
+        flatten_results(
+            &self.top,
+            &self.points_a,
+            &self.points_b,
+            self.target.lat,
+            self.target.lon,
+            &self.options,
+        )
+    }
+}
+
+impl StreamingCombiner {
+    /// Append a point to a group and merge its new pairs into the top N
+    fn add(&mut self, group: PointGroup, lat: f64, lon: f64) -> usize {
+        let added = self.options.input_trig_point(lat, lon);
+        let scorer = PairScorer::new(&self.target, &self.options);
+
+        let (index, rescored): (usize, Vec<ComboResult>) = match group {
+            PointGroup::A => {
+                let index = self.trig_a.len();
+                let results = self
+                    .trig_b
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(j, b)| scorer.score(index, &added, j, b));
+                (index, results.collect())
+            }
+            PointGroup::B => {
+                let index = self.trig_b.len();
+                let results = self
+                    .trig_a
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, a)| scorer.score(i, a, index, &added));
+                (index, results.collect())
+            }
+        };
+
+        let mut top = TopN::new(self.top_n.min(self.top.len() + rescored.len()));
+        for result in self.top.drain(..).chain(rescored) {
+            top.push(result);
+        }
+        self.top = top.into_sorted_vec();
+
+        let (points, trig) = match group {
+            PointGroup::A => (&mut self.points_a, &mut self.trig_a),
+            PointGroup::B => (&mut self.points_b, &mut self.trig_b),
+        };
+        points.extend_from_slice(&[added.lat, added.lon]);
+        trig.push(added);
+        index
+    }
+}
+
+/// Kilometers per degree of latitude
+const KM_PER_DEG_LAT: f64 = EARTH_RADIUS_KM * PI / 180.0;
+
+/// Uniform lat/lon grid of point indices for radius queries
+///
+/// Cells are ordered by (lat, lon) so a query walks each row's occupied
+/// cells as a range, however many empty cells a wide span covers.
+struct PointGrid {
+    cell_deg: f64,
+    lon_cells: i64,
+    cells: BTreeMap<(i64, i64), Vec<u32>>,
+}
+
+impl PointGrid {
+    /// Bin a flat [lat0, lon0, ...] array into square cells of `cell_deg`
+    fn new(points: &[f64], cell_deg: f64) -> Self {
+        let mut grid = PointGrid {
+            cell_deg,
+            lon_cells: (360.0 / cell_deg).ceil() as i64,
+            cells: BTreeMap::new(),
+        };
+        for (i, p) in points.chunks_exact(2).enumerate() {
+            let key = (grid.lat_cell(p[0]), grid.lon_cell(p[1]));
+            grid.cells.entry(key).or_default().push(i as u32);
+        }
+        grid
+    }
+
+    #[inline]
+    fn lat_cell(&self, lat: f64) -> i64 {
+        ((lat + 90.0) / self.cell_deg).floor() as i64
+    }
+
+    #[inline]
+    fn lon_cell(&self, lon: f64) -> i64 {
+        (((lon + 180.0) / self.cell_deg).floor() as i64).rem_euclid(self.lon_cells)
+    }
+
+    /// Call `f` with every point in a cell that may lie within `radius_km`
+    /// Points slightly outside the radius can be reported too
+    fn query<F: FnMut(u32)>(&self, lat: f64, lon: f64, radius_km: f64, mut f: F) {
+        let delta_lat = radius_km / KM_PER_DEG_LAT;
+        let lat_lo = (lat - delta_lat).max(-90.0);
+        let lat_hi = (lat + delta_lat).min(90.0);
+
+        // Widest longitude offset on the circle, asin(sin r / cos lat) for an
+        // angular radius r; once the circle takes in a pole it spans every
+        // longitude
+        let sin_ratio = to_rad(delta_lat).sin() / to_rad(lat).cos();
+        let (lon_start, lon_span) = if !(delta_lat < 90.0 && sin_ratio < 1.0) {
+            (0, self.lon_cells)
+        } else {
+            let delta_lon = to_deg(sin_ratio.asin());
+            let first = ((lon - delta_lon + 180.0) / self.cell_deg).floor() as i64;
+            let last = ((lon + delta_lon + 180.0) / self.cell_deg).floor() as i64;
+            (first, (last - first + 1).min(self.lon_cells))
+        };
+
+        // The span may run past the last longitude cell and wrap round to 0
+        let first = lon_start.rem_euclid(self.lon_cells);
+        let end = first + lon_span;
+        let (head_end, wrapped_end) = if end > self.lon_cells {
+            (self.lon_cells, end - self.lon_cells)
+        } else {
+            (end, 0)
+        };
+
+        for lat_cell in self.lat_cell(lat_lo)..=self.lat_cell(lat_hi) {
+            let head = self.cells.range((lat_cell, first)..(lat_cell, head_end));
+            let wrapped = self.cells.range((lat_cell, 0)..(lat_cell, wrapped_end));
+            for (_, indices) in head.chain(wrapped) {
+                indices.iter().for_each(|&i| f(i));
+            }
+        }
+    }
+}
+
+/// Extra room on the candidate search radius, covering where the sphere
+/// departs from the planar reflection used to find candidates
+const SPATIAL_QUERY_SLACK: f64 = 1.25;
+
+/// Calculate top N results, only scoring pairs that can land near the target
+///
+/// B points are binned into a grid. A midpoint within `search_radius_km` of
+/// the target needs B within about twice that radius of A reflected through
+/// the target, so only grid cells around that reflection are scored. The
+/// reflection is exact on a plane and approximate on the sphere, so for long
+/// baselines a pair near the edge of the radius can be missed; results are
+/// exact when the true top N all sit well inside the radius. Cells are sized
+/// to the query radius: smaller cells would skip more hopeless pairs but cost
+/// more cell lookups per A point.
+/// With a non-positive or non-finite radius, a target line, multiple targets
+/// or a travel scoring mode, this falls back to the brute-force
+/// `find_best_combinations_with_options`. Output layout is the same.
+#[wasm_bindgen]
+pub fn find_best_combinations_spatial(
+    points_a: &[f64],
+    points_b: &[f64],
+    target_lat: f64,
+    target_lon: f64,
+    top_n: usize,
+    search_radius_km: f64,
+    options: &CombinationOptions,
+) -> Vec<f64> {
+    if !(search_radius_km > 0.0 && search_radius_km.is_finite())
+        || options.target_line.is_some()
+        || options.multi_target.is_some()
+        || options.scoring_mode != ScoringMode::TargetProximity
+    {
+        return find_best_combinations_with_options(
+            points_a, points_b, target_lat, target_lon, top_n, options,
+        );
+    }
+
+    let trig_a = options.input_trig(points_a);
+    let trig_b = options.input_trig(points_b);
+    let target = options.input_trig_point(target_lat, target_lon);
+    let points_a = options.input_points(points_a);
+    let points_b = options.input_points(points_b);
+    let (target_lat, target_lon) = (target.lat, target.lon);
+
+    let query_radius_km = 2.0 * search_radius_km * SPATIAL_QUERY_SLACK;
+    let grid = PointGrid::new(&points_b, query_radius_km / KM_PER_DEG_LAT);
+    let scorer = PairScorer::new(&target, options);
+
+    let mut top = TopN::new(top_n.min(trig_a.len() * trig_b.len()));
+    for (i, a) in trig_a.iter().enumerate() {
+        let (lat_a, lon_a) = (points_a[i * 2], points_a[i * 2 + 1]);
+        let (reflected_lat, reflected_lon) = destination(
+            target_lat,
+            target_lon,
+            initial_bearing(target_lat, target_lon, lat_a, lon_a) + 180.0,
+            haversine_distance(target_lat, target_lon, lat_a, lon_a),
+        );
+
+        grid.query(reflected_lat, reflected_lon, query_radius_km, |j| {
+            let j = j as usize;
+            if let Some(result) = scorer.score(i, a, j, &trig_b[j]) {
+                top.push(result);
+            }
+        });
+    }
+
+    flatten_results(
+        &top.into_sorted_vec(),
+        &points_a,
+        &points_b,
+        target_lat,
+        target_lon,
+        options,
+    )
+}
+
+/// Density-based (DBSCAN) clustering of a flat [lat0, lon0, ...] array
+///
+/// A point with at least `min_points` points (itself included) within
+/// `eps_km` is a core point; clusters are core points chained through each
+/// other's neighborhoods plus the non-core points they reach. Everything else
+/// is noise. Unlike k-means the number of clusters comes from the data and
+/// outliers stay out of every cluster. Neighborhoods are great-circle, found
+/// through a grid of `eps_km` cells. Clusters are numbered from 0 in order of
+/// their lowest-index core point, so the labelling is deterministic.
+/// Returns one label per point: its cluster, or -1 for noise
+#[wasm_bindgen]
+pub fn dbscan_clusters(
+    midpoints: &[f64],
+    eps_km: f64,
+    min_points: usize,
+) -> Result<Vec<f64>, JsError> {
+    if !(eps_km > 0.0 && eps_km.is_finite()) {
+        return Err(JsError::new(&format!(
+            "eps_km must be positive and finite, got {eps_km}"
+        )));
+    }
+
+    const NOISE: i64 = -1;
+    const UNVISITED: i64 = -2;
+
+    let grid = PointGrid::new(midpoints, (eps_km / KM_PER_DEG_LAT).min(180.0));
+    let neighbors = |i: usize| -> Vec<usize> {
+        let (lat, lon) = (midpoints[i * 2], midpoints[i * 2 + 1]);
+        let mut found = Vec::new();
+        grid.query(lat, lon, eps_km, |j| {
+            let j = j as usize;
+            if haversine_distance(lat, lon, midpoints[j * 2], midpoints[j * 2 + 1]) <= eps_km {
+                found.push(j);
+            }
+        });
+        found
+    };
+
+    let count = midpoints.len() / 2;
+    let mut labels = vec![UNVISITED; count];
+    let mut next_cluster = 0;
+    for i in 0..count {
+        if labels[i] != UNVISITED {
+            continue;
+        }
+        let mut frontier = neighbors(i);
+        if frontier.len() < min_points {
+            labels[i] = NOISE;
+            continue;
+        }
+
+        let cluster = next_cluster;
+        next_cluster += 1;
+        labels[i] = cluster;
+        while let Some(j) = frontier.pop() {
+            match labels[j] {
+                // Noise reached from a core point is a border point
+                NOISE => labels[j] = cluster,
+                UNVISITED => {
+                    labels[j] = cluster;
+                    let reach = neighbors(j);
+                    if reach.len() >= min_points {
+                        frontier.extend(reach);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(labels.into_iter().map(|label| label as f64).collect())
+}
+
+/// Return every combination within a ratio of the best score, up to a cap
+///
+/// Keeps results with score <= best * (1 + threshold_ratio), sorted by score.
+/// Returns flat array in the same layout as `find_best_combinations`
+#[wasm_bindgen]
+pub fn find_best_combinations_adaptive(
+    points_a: &[f64],
+    points_b: &[f64],
+    target_lat: f64,
+    target_lon: f64,
+    threshold_ratio: f64,
+    max_results: usize,
+) -> Vec<f64> {
+    let options = CombinationOptions::default();
+    let mut results = score_all_combinations(
+        &precompute_trig(points_a),
+        &precompute_trig(points_b),
+        &TrigPoint::new(target_lat, target_lon),
+        &options,
+    );
+
+    // First pass finds the best score, second keeps everything near it
+    let best = results
+        .iter()
+        .map(|r| r.score)
+        .fold(f64::INFINITY, f64::min);
+    let cutoff = best * (1.0 + threshold_ratio);
+    results.retain(|r| r.score <= cutoff);
+
+    let n = select_top_n(&mut results, max_results);
+
+    flatten_results(
+        &results[..n],
+        points_a,
+        points_b,
+        target_lat,
+        target_lon,
+        &options,
+    )
+}
+
+/// Merge the top-N outputs of sharded runs into the global top N
+///
+/// `results` is up to `shard_count` outputs of the engine functions, all run
+/// with `options` and top N, concatenated in any order. Each output is a run
+/// in the engine's own ordering (score, then indexA, then indexB), so the
+/// merge walks the runs' heads instead of sorting everything, in
+/// O(top_n * shard_count); records are copied whole, extras included.
+/// Indices must be global: a worker scoring a slice of A adds the slice's
+/// start to its indexA values. As each shard returned its own top N, the
+/// merge equals a single run over all points.
+/// Errors when the records do not fit `shard_count` runs of at most `top_n`,
+/// or when `options` rank outside plain distance order: ranks and kernel
+/// scores do not compare across shards, and separation or per-A limits are
+/// picked greedily over the whole ranking.
+/// Returns flat array in the same layout, best first.
+#[wasm_bindgen]
+pub fn merge_top_n(
+    results: &[f64],
+    shard_count: usize,
+    top_n: usize,
+    options: &CombinationOptions,
+) -> Result<Vec<f64>, JsError> {
+    merge_shard_runs(results, shard_count, top_n, options).map_err(|message| JsError::new(&message))
+}
+
+/// Merge sorted shard runs, see `merge_top_n`
+fn merge_shard_runs(
+    results: &[f64],
+    shard_count: usize,
+    top_n: usize,
+    options: &CombinationOptions,
+) -> Result<Vec<f64>, String> {
+    let stride = options.stride();
+    if !results.len().is_multiple_of(stride) {
+        return Err(format!(
+            "result length {} is not a multiple of {stride}",
+            results.len()
+        ));
+    }
+    if options.score_as_rank || options.score_kernel != ScoreKernel::Distance {
+        return Err("shards can only be merged on plain distance scores".to_string());
+    }
+    if options.needs_greedy_selection() {
+        return Err("shards selected with separation or max_per_a cannot be merged".to_string());
+    }
+    let record_count = results.len() / stride;
+    if record_count > shard_count.saturating_mul(top_n) {
+        return Err(format!(
+            "{record_count} records do not fit {shard_count} shards of top {top_n}"
+        ));
+    }
+
+    let key = |k: usize| {
+        let record = &results[k * stride..];
+        ComboResult {
+            index_a: record[0] as u32,
+            index_b: record[1] as u32,
+            score: record[2],
+            midpoint_lat: record[3],
+            midpoint_lon: record[4],
+        }
+    };
+
+    // Split at every step back in the ordering: each shard is one run
+    let mut runs: Vec<std::ops::Range<usize>> = Vec::new();
+    for k in 0..record_count {
+        match runs.last_mut() {
+            Some(run) if compare_results(&key(k - 1), &key(k)).is_le() => run.end = k + 1,
+            _ => runs.push(k..k + 1),
+        }
+    }
+    if runs.len() > shard_count {
+        return Err(format!(
+            "records form {} sorted runs, more than the {shard_count} shards",
+            runs.len()
+        ));
+    }
+
+    let mut merged = Vec::with_capacity(record_count.min(top_n) * stride);
+    while merged.len() < top_n * stride {
+        let best = runs
+            .iter()
+            .enumerate()
+            .filter(|(_, run)| !run.is_empty())
+            .min_by(|(_, x), (_, y)| compare_results(&key(x.start), &key(y.start)))
+            .map(|(r, _)| r);
+        let Some(r) = best else { break };
+        let k = runs[r].start;
+        merged.extend_from_slice(&results[k * stride..(k + 1) * stride]);
+        runs[r].start += 1;
+    }
+
+    Ok(merged)
+}
+
+/// Geohash of each result's midpoint, aligned with a flat engine output
+///
+/// `results` is an output of the engine functions run with `options`, whose
+/// stride, coordinate order and angle unit are used to find each midpoint.
+/// Returns one geohash of `precision` characters (see `geohash_encode`) per
+/// record, so record i's cell is element i; nearby midpoints share a cell at
+/// coarse precisions, which suits keying a reverse-geocoding cache.
+#[wasm_bindgen]
+pub fn result_geohashes(
+    results: &[f64],
+    precision: usize,
+    options: &CombinationOptions,
+) -> Vec<String> {
+    results
+        .chunks_exact(options.stride())
+        .map(|record| {
+            let (lat, lon) = options.record_midpoint(record);
+            geohash_encode(lat, lon, precision)
+        })
+        .collect()
+}
+
+/// Spherical centroid of the midpoints in a flat engine output
+///
+/// `results` is an output of the engine functions run with `options`, whose
+/// stride, coordinate order and angle unit are used to find each midpoint.
+/// The midpoints are averaged as 3D unit vectors, as `weighted_centroid`
+/// does with equal weights; a single result returns its midpoint.
+/// Returns [lat, lon] in the options' coordinate order and angle unit, like
+/// the records' midpoints, an empty array for no results, or NaNs when the
+/// midpoints cancel out.
+#[wasm_bindgen]
+pub fn results_centroid(results: &[f64], options: &CombinationOptions) -> Vec<f64> {
+    let midpoints: Vec<f64> = results
+        .chunks_exact(options.stride())
+        .flat_map(|record| {
+            let (lat, lon) = options.record_midpoint(record);
+            [lat, lon]
+        })
+        .collect();
+
+    let center = match midpoints.len() {
+        0 => return Vec::new(),
+        2 => midpoints,
+        n => weighted_centroid(&midpoints, &vec![1.0; n / 2]),
+    };
+    let mut output = Vec::with_capacity(2);
+    options.push_coordinate(&mut output, center[0], center[1]);
+    output
+}
+
+/// Mean and spread of the scores in a flat engine output
+///
+/// `results` is an output of the engine functions run with `options`, read
+/// by the options' stride. The standard deviation is the population one,
+/// over exactly the returned records: low means the top options are all
+/// about as good, high that the best stands out.
+/// Returns [mean, stdDev], or an empty array for no results
+#[wasm_bindgen]
+pub fn result_stats(results: &[f64], options: &CombinationOptions) -> Vec<f64> {
+    let scores: Vec<f64> = results
+        .chunks_exact(options.stride())
+        .map(|record| record[2])
+        .collect();
+    if scores.is_empty() {
+        return Vec::new();
+    }
+
+    let n = scores.len() as f64;
+    let mean = scores.iter().sum::<f64>() / n;
+    let variance = scores.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / n;
+
+    vec![mean, variance.sqrt()]
+}
+
+/// Compare two top-N outputs keyed by their (indexA, indexB) pairs
+///
+/// Inputs are flat outputs of the engine functions, both run with `options`
+/// and read by its stride; rank is the 0-based record position.
+/// Returns flat array: [numAdded, numRemoved, numMoved,
+/// added: (indexA, indexB, newRank)..., removed: (indexA, indexB, oldRank)...,
+/// moved: (indexA, indexB, oldRank, newRank)...]. Added and moved pairs are
+/// listed in new-rank order, removed ones in old-rank order. Pairs whose rank
+/// is unchanged appear nowhere.
+#[wasm_bindgen]
+pub fn diff_results(old: &[f64], new: &[f64], options: &CombinationOptions) -> Vec<f64> {
+    let stride = options.stride();
+    let key = |record: &[f64]| (record[0] as u32, record[1] as u32);
+    let old_ranks: HashMap<(u32, u32), usize> = old
+        .chunks_exact(stride)
+        .enumerate()
+        .map(|(rank, record)| (key(record), rank))
+        .collect();
+    let new_ranks: HashMap<(u32, u32), usize> = new
+        .chunks_exact(stride)
+        .enumerate()
+        .map(|(rank, record)| (key(record), rank))
+        .collect();
+
+    let mut added = Vec::new();
+    let mut moved = Vec::new();
+    for (new_rank, record) in new.chunks_exact(stride).enumerate() {
+        match old_ranks.get(&key(record)) {
+            None => added.extend([record[0], record[1], new_rank as f64]),
+            Some(&old_rank) if old_rank != new_rank => {
+                moved.extend([record[0], record[1], old_rank as f64, new_rank as f64])
+            }
+            Some(_) => {}
+        }
+    }
+
+    let mut removed = Vec::new();
+    for (old_rank, record) in old.chunks_exact(stride).enumerate() {
+        if !new_ranks.contains_key(&key(record)) {
+            removed.extend([record[0], record[1], old_rank as f64]);
+        }
+    }
+
+    let mut output = vec![
+        (added.len() / 3) as f64,
+        (removed.len() / 3) as f64,
+        (moved.len() / 4) as f64,
+    ];
+    output.extend(added);
+    output.extend(removed);
+    output.extend(moved);
+    output
+}
+
+/// Magic bytes at the start of a packed result buffer
+const PACKED_MAGIC: [u8; 4] = *b"GEOC";
+
+/// Version of the packed result layout
+const PACKED_VERSION: u32 = 1;
+
+/// Calculate top N results packed as little-endian bytes for transfer
+///
+/// Layout (all little-endian, 16-byte header keeps records 8-byte aligned):
+///   bytes 0..4    magic "GEOC"
+///   bytes 4..8    u32 layout version (1)
+///   bytes 8..12   u32 record count
+///   bytes 12..16  u32 stride (f64 values per record, 5)
+///   bytes 16..    count * stride f64 values, in the same order as
+///                 `find_best_combinations`: indexA, indexB, score, midLat, midLon
+#[wasm_bindgen]
+pub fn find_best_combinations_bytes(
+    points_a: &[f64],
+    points_b: &[f64],
+    target_lat: f64,
+    target_lon: f64,
+    top_n: usize,
+) -> Vec<u8> {
+    let flat = find_best_combinations(points_a, points_b, target_lat, target_lon, top_n);
+    let count = flat.len() / RESULT_STRIDE;
+
+    let mut bytes = Vec::with_capacity(16 + flat.len() * 8);
+    bytes.extend_from_slice(&PACKED_MAGIC);
+    bytes.extend_from_slice(&PACKED_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(count as u32).to_le_bytes());
+    bytes.extend_from_slice(&(RESULT_STRIDE as u32).to_le_bytes());
+    for value in flat {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    bytes
+}
+
+/// Calculate all midpoints for heatmap generation
+/// Returns flat array: [lat0, lon0, lat1, lon1, ...]
+#[wasm_bindgen]
+pub fn calculate_all_midpoints(
+    points_a: &[f64],
+    points_b: &[f64],
+) -> Vec<f64> {
+    let num_a = points_a.len() / 2;
+    let num_b = points_b.len() / 2;
+
+    let total_combos = num_a * num_b;
+    let mut output = Vec::with_capacity(total_combos * 2);
+
+    let trig_a = precompute_trig(points_a);
+    let trig_b = precompute_trig(points_b);
+
+    for a in &trig_a {
+        for b in &trig_b {
+            let (mid_lat, mid_lon) = geodesic_midpoint_trig(a, b);
+
+            output.push(mid_lat);
+            output.push(mid_lon);
+        }
+    }
+
+    output
+}
+
+/// Calculate midpoints for heatmap generation, only for pairs close together
+///
+/// Same layout as `calculate_all_midpoints` but no longer the full cross
+/// product: pairs more than `max_pair_km` apart (haversine) are left out,
+/// so the output length varies with the data. The kept midpoints stay in
+/// (indexA, indexB) order.
+/// Returns flat array: [lat0, lon0, lat1, lon1, ...]
+#[wasm_bindgen]
+pub fn calculate_all_midpoints_within(
+    points_a: &[f64],
+    points_b: &[f64],
+    max_pair_km: f64,
+) -> Vec<f64> {
+    let trig_a = precompute_trig(points_a);
+    let trig_b = precompute_trig(points_b);
+    let mut output = Vec::new();
+
+    for a in &trig_a {
+        for b in &trig_b {
+            if haversine_distance_cos(a.lat, a.lon, a.cos_lat, b.lat, b.lon, b.cos_lat)
+                > max_pair_km
+            {
+                continue;
+            }
+            let (mid_lat, mid_lon) = geodesic_midpoint_trig(a, b);
+
+            output.push(mid_lat);
+            output.push(mid_lon);
+        }
+    }
+
+    output
+}
+
+/// Binned midpoint heatmap that recolors for a moving target
+///
+/// Midpoints depend only on the two point sets, so they are computed and
+/// binned once; `recolor` then just re-weights each cached midpoint by its
+/// distance to the new target, with no midpoint trig. The cache keeps the
+/// midpoints inside the bounds only, at 28 bytes each, so 1000×1000 points
+/// all in view take about 28 MB.
+#[wasm_bindgen]
+pub struct HeatmapCache {
+    lats: Vec<f64>,
+    lons: Vec<f64>,
+    cos_lats: Vec<f64>,
+    cells: Vec<u32>,
+    cell_count: usize,
+    kernel: ScoreKernel,
+    kernel_param_km: f64,
+}
+
+#[wasm_bindgen]
+impl HeatmapCache {
+    /// Bin every A-B midpoint into a `rows * cols` grid over the bounds
+    ///
+    /// Cells are laid out like `set_cost_raster`'s: row-major, row 0 along
+    /// the north edge, each an equal lat/lon step. Midpoints outside the
+    /// bounds are not drawn. Each midpoint adds its distance to the target
+    /// through `kernel` (see `ScoreKernel`) to its cell, so `Distance` is
+    /// rejected: heat needs a similarity that falls off with distance.
+    #[wasm_bindgen(constructor)]
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        points_a: &[f64],
+        points_b: &[f64],
+        rows: usize,
+        cols: usize,
+        min_lat: f64,
+        min_lon: f64,
+        max_lat: f64,
+        max_lon: f64,
+        kernel: ScoreKernel,
+        param_km: f64,
+    ) -> Result<HeatmapCache, JsError> {
+        if rows == 0 || cols == 0 {
+            return Err(JsError::new(&format!(
+                "heatmap grid must be non-empty, got {rows} x {cols}"
+            )));
+        }
+        if !(min_lat < max_lat && min_lon < max_lon) {
+            return Err(JsError::new("heatmap bounds must have min < max"));
+        }
+        if kernel == ScoreKernel::Distance {
+            return Err(JsError::new(
+                "heatmap needs a similarity kernel, not Distance",
+            ));
+        }
+
+        let mut cache = HeatmapCache {
+            lats: Vec::new(),
+            lons: Vec::new(),
+            cos_lats: Vec::new(),
+            cells: Vec::new(),
+            cell_count: rows * cols,
+            kernel,
+            kernel_param_km: param_km,
+        };
+        for mid in calculate_all_midpoints(points_a, points_b).chunks_exact(2) {
+            let (lat, lon) = (mid[0], mid[1]);
+            if !(min_lat..=max_lat).contains(&lat) || !(min_lon..=max_lon).contains(&lon) {
+                continue;
+            }
+            let row = ((max_lat - lat) / (max_lat - min_lat) * rows as f64) as usize;
+            let col = ((lon - min_lon) / (max_lon - min_lon) * cols as f64) as usize;
+            cache.lats.push(lat);
+            cache.lons.push(lon);
+            cache.cos_lats.push(to_rad(lat).cos());
+            cache
+                .cells
+                .push((row.min(rows - 1) * cols + col.min(cols - 1)) as u32);
+        }
+
+        Ok(cache)
+    }
+
+    /// Number of cached (in-bounds) midpoints
+    pub fn midpoint_count(&self) -> usize {
+        self.cells.len()
+    }
+
+    /// Cell intensities for a target, row-major like the grid
+    pub fn recolor(&self, target_lat: f64, target_lon: f64) -> Vec<f64> {
+        let cos_target = to_rad(target_lat).cos();
+        let mut intensities = vec![0.0; self.cell_count];
+        for (i, &cell) in self.cells.iter().enumerate() {
+            let distance = haversine_distance_cos(
+                self.lats[i],
+                self.lons[i],
+                self.cos_lats[i],
+                target_lat,
+                target_lon,
+                cos_target,
+            );
+            intensities[cell as usize] += apply_kernel(self.kernel, self.kernel_param_km, distance);
+        }
+
+        intensities
+    }
+}
+
+/// SplitMix64 step, a cheap well-mixed hash of a 64-bit seed
+#[inline]
+fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Calculate all midpoints, nudging stacked duplicates apart for point rendering
+///
+/// Same layout as `calculate_all_midpoints`. Every midpoint that exactly
+/// coincides with another is moved up to `jitter_m` meters in a direction
+/// and distance derived from its pair id (indexA * numB + indexB), so the
+/// same pair always gets the same offset and a stack spreads into a small
+/// disc. Midpoints with no duplicate are returned untouched.
+/// Returns flat array: [lat0, lon0, lat1, lon1, ...]
+#[wasm_bindgen]
+pub fn calculate_all_midpoints_jittered(
+    points_a: &[f64],
+    points_b: &[f64],
+    jitter_m: f64,
+) -> Vec<f64> {
+    let mut output = calculate_all_midpoints(points_a, points_b);
+
+    let mut stack_sizes: HashMap<(u64, u64), usize> = HashMap::new();
+    for p in output.chunks_exact(2) {
+        *stack_sizes
+            .entry((p[0].to_bits(), p[1].to_bits()))
+            .or_insert(0) += 1;
+    }
+
+    for (pair_id, p) in output.chunks_exact_mut(2).enumerate() {
+        if stack_sizes[&(p[0].to_bits(), p[1].to_bits())] < 2 {
+            continue;
+        }
+
+        let hash = splitmix64(pair_id as u64);
+        let unit = |bits: u64| (bits >> 11) as f64 / (1u64 << 53) as f64;
+        let bearing = unit(hash) * 360.0;
+        // sqrt keeps the spread uniform over the disc rather than bunched at its center
+        let distance_km = unit(splitmix64(hash)).sqrt() * jitter_m / 1000.0;
+
+        let (lat, lon) = destination(p[0], p[1], bearing, distance_km);
+        p[0] = lat;
+        p[1] = lon;
+    }
+
+    output
+}
+
+/// Calculate the midpoint of every pair within a single set
+///
+/// Input array is flat: [lat0, lon0, lat1, lon1, ...]
+/// Returns flat array: [lat, lon, i, j, ...] for every i < j, in (i, j)
+/// order: n(n-1)/2 records of 4 values
+#[wasm_bindgen]
+pub fn self_midpoints(points: &[f64]) -> Vec<f64> {
+    let trig = precompute_trig(points);
+    let num_points = trig.len();
+    let mut output = Vec::with_capacity(num_points * num_points.saturating_sub(1) * 2);
+
+    for (i, a) in trig.iter().enumerate() {
+        for (j, b) in trig.iter().enumerate().skip(i + 1) {
+            let (mid_lat, mid_lon) = geodesic_midpoint_trig(a, b);
+
+            output.push(mid_lat);
+            output.push(mid_lon);
+            output.push(i as f64);
+            output.push(j as f64);
+        }
+    }
+
+    output
+}
+
+/// Calculate the midpoint of one fixed point with each point of a set
+/// Returns flat array: [lat0, lon0, lat1, lon1, ...], one midpoint per B
+#[wasm_bindgen]
+pub fn midpoints_one_to_many(a_lat: f64, a_lon: f64, points_b: &[f64]) -> Vec<f64> {
+    let a = TrigPoint::new(a_lat, a_lon);
+    let mut output = Vec::with_capacity(points_b.len());
+
+    for b in precompute_trig(points_b) {
+        let (mid_lat, mid_lon) = geodesic_midpoint_trig(&a, &b);
+
+        output.push(mid_lat);
+        output.push(mid_lon);
+    }
+
+    output
+}
+
+/// Haversine distance from one fixed point to each point of a set
+///
+/// The origin's latitude cosine is computed once. Matches `haversine_km`
+/// for every point.
+/// Returns [d0, d1, ...] in km, one distance per point
+#[wasm_bindgen]
+pub fn distances_one_to_many(lat: f64, lon: f64, points: &[f64]) -> Vec<f64> {
+    let cos_lat = to_rad(lat).cos();
+    points
+        .chunks_exact(2)
+        .map(|p| haversine_distance_cos(lat, lon, cos_lat, p[0], p[1], to_rad(p[0]).cos()))
+        .collect()
+}
+
+/// Calculate all midpoints with the input and output pairs in the given order
+///
+/// With `CoordOrder::LonLat` both inputs and the output are [lon0, lat0, ...]
+#[wasm_bindgen]
+pub fn calculate_all_midpoints_in_order(
+    points_a: &[f64],
+    points_b: &[f64],
+    order: CoordOrder,
+) -> Vec<f64> {
+    let output =
+        calculate_all_midpoints(&to_lat_lon(points_a, order), &to_lat_lon(points_b, order));
+    to_lat_lon(&output, order).into_owned()
+}
+
+/// Calculate all midpoints with input and output angles in the given unit
+///
+/// With `AngleUnit::Radians` no degree conversion happens anywhere in the
+/// loop; inputs and output are [lat0, lon0, ...] in radians.
+#[wasm_bindgen]
+pub fn calculate_all_midpoints_in_units(
+    points_a: &[f64],
+    points_b: &[f64],
+    unit: AngleUnit,
+) -> Vec<f64> {
+    if unit == AngleUnit::Degrees {
+        return calculate_all_midpoints(points_a, points_b);
+    }
+
+    let to_trig = |points: &[f64]| -> Vec<TrigPoint> {
+        points
+            .chunks_exact(2)
+            .map(|p| TrigPoint::from_rad(p[0], p[1]))
+            .collect()
+    };
+    let trig_a = to_trig(points_a);
+    let trig_b = to_trig(points_b);
+
+    let mut output = Vec::with_capacity(trig_a.len() * trig_b.len() * 2);
+    for a in &trig_a {
+        for b in &trig_b {
+            let (mid_lat, mut mid_lon) = geodesic_midpoint_trig_rad(a, b);
+            if !(-PI..PI).contains(&mid_lon) {
+                mid_lon = to_rad(normalize_longitude(to_deg(mid_lon)));
+            }
+
+            output.push(mid_lat);
+            output.push(mid_lon);
+        }
+    }
+
+    output
+}
+
+/// Calculate, for every A-B pair, the great-circle point at a fraction from A to B
+///
+/// Same layout as `calculate_all_midpoints`, which fraction 0.5 reproduces.
+/// Fraction 0 returns each A point and 1 each B point exactly; antipodal
+/// pairs give NaNs.
+/// Returns flat array: [lat0, lon0, lat1, lon1, ...]
+#[wasm_bindgen]
+pub fn calculate_all_points_at_fraction(
+    points_a: &[f64],
+    points_b: &[f64],
+    fraction: f64,
+) -> Vec<f64> {
+    if fraction == 0.5 {
+        return calculate_all_midpoints(points_a, points_b);
+    }
+
+    let num_b = points_b.len() / 2;
+    let mut output = Vec::with_capacity((points_a.len() / 2) * num_b * 2);
+
+    let vectors = |points: &[f64]| -> Vec<[f64; 3]> {
+        points
+            .chunks_exact(2)
+            .map(|p| to_vector(p[0], p[1]))
+            .collect()
+    };
+    let vectors_a = vectors(points_a);
+    let vectors_b = vectors(points_b);
+
+    for (a, va) in points_a.chunks_exact(2).zip(&vectors_a) {
+        for (b, vb) in points_b.chunks_exact(2).zip(&vectors_b) {
+            let (lat, lon) = if fraction == 0.0 {
+                (a[0], a[1])
+            } else if fraction == 1.0 {
+                (b[0], b[1])
+            } else {
+                from_vector(interpolate_vectors(*va, *vb, fraction))
+            };
+
+            output.push(lat);
+            output.push(lon);
+        }
+    }
+
+    output
+}
+
+/// Get the number of combinations that would be calculated
+#[wasm_bindgen]
+pub fn get_combination_count(num_a: usize, num_b: usize) -> usize {
+    num_a * num_b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn direction_spread_wraps_through_north() {
+        // Points 50 km from the origin on the given bearings
+        let spread_of = |bearings: &[f64]| {
+            let d = 50.0 / EARTH_RADIUS_KM;
+            let points: Vec<f64> = bearings
+                .iter()
+                .flat_map(|&b| {
+                    let b = to_rad(b);
+                    [
+                        to_deg((d.sin() * b.cos()).asin()),
+                        to_deg((b.sin() * d.sin()).atan2(d.cos())),
+                    ]
+                })
+                .collect();
+            direction_spread(&points, 0.0, 0.0)
+        };
+        assert!((spread_of(&[350.0, 5.0, 20.0, 0.0]) - 30.0).abs() < 1e-6);
+        assert_eq!(spread_of(&[350.0]), 0.0);
+
+        // Past a half turn the spread is what the largest gap leaves over
+        assert!((spread_of(&[10.0, 200.0]) - 170.0).abs() < 1e-6);
+        assert!((spread_of(&[0.0, 120.0, 240.0]) - 240.0).abs() < 1e-6);
+        assert!((spread_of(&[0.0, 90.0, 180.0, 270.0, 300.0]) - 270.0).abs() < 1e-6);
+    }
+
+    /// Deterministic pseudo-random points in a lat/lon box, flat [lat, lon, ...]
+    fn sample_points(
+        seed: u64,
+        count: usize,
+        min_lat: f64,
+        min_lon: f64,
+        span_deg: f64,
+    ) -> Vec<f64> {
+        // SplitMix64, so every run sees the same points
+        let mut state = seed;
+        let mut next = || {
+            state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            ((z ^ (z >> 31)) >> 11) as f64 / (1u64 << 53) as f64
+        };
+        (0..count)
+            .flat_map(|_| [min_lat + next() * span_deg, min_lon + next() * span_deg])
+            .collect()
+    }
+
+    /// The batch loop before latitude trig was precomputed, converting every
+    /// coordinate of every pair
+    fn find_best_combinations_reference(
+        points_a: &[f64],
+        points_b: &[f64],
+        target_lat: f64,
+        target_lon: f64,
+        top_n: usize,
+    ) -> Vec<f64> {
+        let haversine = |lat1: f64, lon1: f64, lat2: f64, lon2: f64| {
+            let delta_lat = to_rad(lat2 - lat1);
+            let delta_lon = to_rad(lon2 - lon1);
+            let a = (delta_lat / 2.0).sin().powi(2)
+                + to_rad(lat1).cos() * to_rad(lat2).cos() * (delta_lon / 2.0).sin().powi(2);
+            EARTH_RADIUS_KM * 2.0 * a.sqrt().asin()
+        };
+        let midpoint = |lat1: f64, lon1: f64, lat2: f64, lon2: f64| {
+            let (lat1, lon1, lat2, lon2) = (to_rad(lat1), to_rad(lon1), to_rad(lat2), to_rad(lon2));
+            let bx = lat2.cos() * (lon2 - lon1).cos();
+            let by = lat2.cos() * (lon2 - lon1).sin();
+            let lat_mid =
+                (lat1.sin() + lat2.sin()).atan2(((lat1.cos() + bx).powi(2) + by.powi(2)).sqrt());
+            let lon_mid = lon1 + by.atan2(lat1.cos() + bx);
+            (lat_mid * 180.0 / PI, lon_mid * 180.0 / PI)
+        };
+
+        let mut results = Vec::new();
+        for (i, a) in points_a.chunks_exact(2).enumerate() {
+            for (j, b) in points_b.chunks_exact(2).enumerate() {
+                let (mid_lat, mid_lon) = midpoint(a[0], a[1], b[0], b[1]);
+                let score = haversine(mid_lat, mid_lon, target_lat, target_lon);
+                results.push([i as f64, j as f64, score, mid_lat, mid_lon]);
+            }
+        }
+        results.sort_by(|x, y| x[2].partial_cmp(&y[2]).unwrap());
+        results.truncate(top_n);
+        results.concat()
+    }
+
+    fn bits(values: &[f64]) -> Vec<u64> {
+        values.iter().map(|v| v.to_bits()).collect()
+    }
+
+    #[test]
+    fn precomputed_trig_matches_reference_bit_for_bit() {
+        let points_a = sample_points(1, 40, 40.0, -75.0, 2.0);
+        let points_b = sample_points(2, 35, 40.5, -74.5, 2.0);
+        for top_n in [1, 10, 40 * 35] {
+            assert_eq!(
+                bits(&find_best_combinations(
+                    &points_a, &points_b, 41.0, -74.0, top_n
+                )),
+                bits(&find_best_combinations_reference(
+                    &points_a, &points_b, 41.0, -74.0, top_n
+                )),
+            );
+        }
+
+        let reference: Vec<f64> =
+            find_best_combinations_reference(&points_a, &points_b, 0.0, 0.0, usize::MAX)
+                .chunks_exact(RESULT_STRIDE)
+                .map(|r| (r[0] as usize * 35 + r[1] as usize, [r[3], r[4]]))
+                .collect::<std::collections::BTreeMap<_, _>>()
+                .into_values()
+                .flatten()
+                .collect();
+        assert_eq!(
+            bits(&calculate_all_midpoints(&points_a, &points_b)),
+            bits(&reference)
+        );
+    }
+
+    #[test]
+    fn packed_bytes_decode_to_the_flat_output() {
+        let points_a = sample_points(3, 6, 40.0, -75.0, 1.0);
+        let points_b = sample_points(4, 5, 40.0, -75.0, 1.0);
+        let flat = find_best_combinations(&points_a, &points_b, 40.5, -74.5, 7);
+        let bytes = find_best_combinations_bytes(&points_a, &points_b, 40.5, -74.5, 7);
+
+        let word = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        assert_eq!(&bytes[..4], b"GEOC");
+        assert_eq!(word(4), 1);
+        assert_eq!(word(8), 7);
+        assert_eq!(word(12), 5);
+        assert_eq!(bytes.len(), 16 + 7 * 5 * 8);
+        let values: Vec<f64> = bytes[16..]
+            .chunks_exact(8)
+            .map(|b| f64::from_le_bytes(b.try_into().unwrap()))
+            .collect();
+        assert_eq!(bits(&values), bits(&flat));
+    }
+
+    #[test]
+    fn adaptive_cutoff_shrinks_with_the_ratio() {
+        let points_a = sample_points(5, 20, 40.0, -75.0, 1.0);
+        let points_b = sample_points(6, 20, 40.0, -75.0, 1.0);
+        let mut previous = usize::MAX;
+        for ratio in [0.5, 0.2, 0.1, 0.0] {
+            let results = find_best_combinations_adaptive(
+                &points_a,
+                &points_b,
+                41.5,
+                -73.5,
+                ratio,
+                usize::MAX,
+            );
+            let count = results.len() / RESULT_STRIDE;
+            assert!(count < previous, "ratio {ratio} kept {count}");
+            let best = results[2];
+            assert!(results
+                .chunks_exact(RESULT_STRIDE)
+                .all(|r| r[2] <= best * (1.0 + ratio)));
+            previous = count;
+        }
+        assert_eq!(previous, 1);
+        assert_eq!(
+            find_best_combinations_adaptive(&points_a, &points_b, 40.5, -74.5, 2.0, 3).len(),
+            3 * RESULT_STRIDE
+        );
+    }
+
+    #[test]
+    fn empty_groups_and_zero_top_n_return_nothing() {
+        let points = [40.0, -74.0, 41.0, -73.0];
+        let options = CombinationOptions::new();
+        let cases: [(&[f64], &[f64], usize); 4] = [
+            (&[], &points, 5),
+            (&points, &[], 5),
+            (&[], &[], 5),
+            (&points, &points, 0),
+        ];
+        for (points_a, points_b, top_n) in cases {
+            assert!(find_best_combinations(points_a, points_b, 40.5, -73.5, top_n).is_empty());
+            assert!(find_best_combinations_with_options(
+                points_a, points_b, 40.5, -73.5, top_n, &options
+            )
+            .is_empty());
+            assert!(find_best_combinations_spatial(
+                points_a, points_b, 40.5, -73.5, top_n, 50.0, &options
+            )
+            .is_empty());
+            assert_eq!(
+                find_best_combinations_partial(
+                    points_a, points_b, 40.5, -73.5, top_n, 100, &options
+                )[2..],
+                []
+            );
+        }
+
+        let mut results = Vec::new();
+        assert_eq!(select_top_n(&mut results, 3), 0);
+        let mut single = vec![ComboResult {
+            index_a: 0,
+            index_b: 0,
+            score: 1.0,
+            midpoint_lat: 0.0,
+            midpoint_lon: 0.0,
+        }];
+        assert_eq!(select_top_n(&mut single, 0), 0);
+        assert_eq!(select_top_n(&mut single, 1), 1);
+    }
+
+    #[test]
+    fn diameter_finds_non_adjacent_extremes() {
+        let points = [0.0, 5.0, 0.0, 1.0, 0.0, -3.0];
+        let result = diameter(&points);
+        assert_eq!(&result[..2], &[0.0, 2.0]);
+        assert!((result[2] - haversine_distance(0.0, 5.0, 0.0, -3.0)).abs() < 1e-9);
+        assert!(diameter(&points[..2]).is_empty());
+    }
+
+    /// Results in (index A, index B) order with many tied scores
+    fn tied_results(num_a: u32, num_b: u32) -> Vec<ComboResult> {
+        (0..num_a)
+            .flat_map(|i| (0..num_b).map(move |j| (i, j)))
+            .map(|(i, j)| ComboResult {
+                index_a: i,
+                index_b: j,
+                score: ((i * 7 + j * 13) % 11) as f64,
+                midpoint_lat: i as f64,
+                midpoint_lon: j as f64,
+            })
+            .collect()
+    }
+
+    fn result_keys(results: &[ComboResult]) -> Vec<(u64, u32, u32)> {
+        results
+            .iter()
+            .map(|r| (r.score.to_bits(), r.index_a, r.index_b))
+            .collect()
+    }
+
+    #[test]
+    fn every_selection_path_matches_a_stable_full_sort() {
+        let all = tied_results(40, 32);
+        let len = all.len();
+        let mut reference = all.clone();
+        reference.sort_by(|a, b| a.score.partial_cmp(&b.score).unwrap());
+
+        // Either side of the heap/select crossover, then up to the full sort
+        let heap_limit = len / HEAP_SELECT_RATIO;
+        for top_n in [
+            1,
+            2,
+            heap_limit - 1,
+            heap_limit,
+            heap_limit + 1,
+            len / 2,
+            len - 1,
+            len,
+            len + 1,
+        ] {
+            let mut results = all.clone();
+            let n = select_top_n(&mut results, top_n);
+            assert_eq!(n, top_n.min(len));
+            assert_eq!(
+                result_keys(&results[..n]),
+                result_keys(&reference[..n]),
+                "top_n {top_n}"
+            );
+        }
+    }
+
+    /// Degrees of latitude per kilometer on the model sphere
+    const DEG_PER_KM: f64 = 180.0 / (PI * EARTH_RADIUS_KM);
+
+    #[test]
+    fn corridor_excludes_midpoints_off_the_line() {
+        let mut options = CombinationOptions::new();
+        options
+            .set_corridor(0.0, 0.0, 0.0, 1.0, 1.0, false)
+            .unwrap();
+        // Pair 0-0 meets on the line, pair 1-1 meets 2 km north of it
+        let points_a = [0.0, 0.2, 2.0 * DEG_PER_KM, 0.2];
+        let points_b = [0.0, 0.4, 2.0 * DEG_PER_KM, 0.4];
+        let results =
+            find_best_combinations_with_options(&points_a, &points_b, 0.0, 0.5, 10, &options);
+        assert_eq!(results.len(), RESULT_STRIDE);
+        assert_eq!(&results[..2], &[0.0, 0.0]);
+
+        options
+            .set_corridor(0.0, 0.0, 0.0, 1.0, 5.0, false)
+            .unwrap();
+        let wide =
+            find_best_combinations_with_options(&points_a, &points_b, 0.0, 0.5, 10, &options);
+        assert_eq!(wide.len(), 4 * RESULT_STRIDE);
+    }
+
+    #[test]
+    fn perpendicular_great_circles_cross_at_the_right_point() {
+        // The equator and the meridian at 20°E, neither segment reaching the crossing
+        let crossing = intersection(0.0, -10.0, 0.0, 5.0, 30.0, 20.0, 50.0, 20.0);
+        assert!(
+            crossing[0].abs() < 1e-9 && (crossing[1] - 20.0).abs() < 1e-9,
+            "{crossing:?}"
+        );
+
+        let same = intersection(0.0, 0.0, 0.0, 10.0, 0.0, 20.0, 0.0, 30.0);
+        assert!(same[0].is_nan() && same[1].is_nan());
+    }
+
+    #[test]
+    fn points_in_a_concave_notch_are_excluded() {
+        // A "C" opening east: the notch spans lat 1-2, lon 1-3
+        let polygon = [
+            0.0, 0.0, 3.0, 0.0, 3.0, 3.0, 2.0, 3.0, 2.0, 1.0, 1.0, 1.0, 1.0, 3.0, 0.0, 3.0,
+        ];
+        let points = [0.5, 2.0, 1.5, 2.0, 2.5, 2.0, 1.5, 0.5, 4.0, 1.0];
+        assert_eq!(points_within_polygon(&points, &polygon), vec![0, 2, 3]);
+    }
+
+    #[test]
+    fn bonus_zone_breaks_equal_scores_in_its_favor() {
+        let points_a = [0.0, 0.0];
+        let points_b = [2.0, 0.0, -2.0, 0.0];
+        let mut options = CombinationOptions::new();
+        let plain =
+            find_best_combinations_with_options(&points_a, &points_b, 0.0, 0.0, 2, &options);
+        assert_eq!(plain[2], plain[7]);
+        assert_eq!(plain[1], 0.0);
+
+        options.set_bonus_zone(-1.0, 0.0, 10.0, 1.0).unwrap();
+        let boosted =
+            find_best_combinations_with_options(&points_a, &points_b, 0.0, 0.0, 2, &options);
+        assert_eq!(boosted[1], 1.0);
+        assert!((boosted[2] - (plain[2] - 1.0)).abs() < 1e-9);
+    }
+
+    /// Swap every lat,lon pair of a flat array
+    fn swap_pairs(points: &[f64]) -> Vec<f64> {
+        points.chunks_exact(2).flat_map(|p| [p[1], p[0]]).collect()
+    }
+
+    #[test]
+    fn lon_lat_order_matches_lat_lon() {
+        let points_a = sample_points(7, 6, 48.0, 2.0, 1.0);
+        let points_b = sample_points(8, 5, 48.5, 2.5, 1.0);
+        let lat_lon = find_best_combinations(&points_a, &points_b, 48.7, 2.4, 10);
+
+        let mut options = CombinationOptions::new();
+        options.set_coord_order(CoordOrder::LonLat);
+        let lon_lat = find_best_combinations_with_options(
+            &swap_pairs(&points_a),
+            &swap_pairs(&points_b),
+            48.7,
+            2.4,
+            10,
+            &options,
+        );
+        for (x, y) in lat_lon
+            .chunks_exact(RESULT_STRIDE)
+            .zip(lon_lat.chunks_exact(RESULT_STRIDE))
+        {
+            assert_eq!(&x[..3], &y[..3]);
+            assert_eq!((x[3], x[4]), (y[4], y[3]));
+        }
+
+        let midpoints = calculate_all_midpoints(&points_a, &points_b);
+        let swapped = calculate_all_midpoints_in_order(
+            &swap_pairs(&points_a),
+            &swap_pairs(&points_b),
+            CoordOrder::LonLat,
+        );
+        assert_eq!(swap_pairs(&midpoints), swapped);
+    }
+
+    #[test]
+    fn midpoint_quality_rewards_central_fair_pairs() {
+        let central = midpoint_quality(0.0, -0.1, 0.0, 0.1, 0.0, 0.0);
+        assert!(central > 99.0, "{central}");
+        let lopsided = midpoint_quality(0.0, 0.0, 0.0, 5.0, 0.0, 0.1);
+        assert!(lopsided < 5.0, "{lopsided}");
+    }
+
+    #[test]
+    fn rhumb_bearing_cases() {
+        assert!((rhumb_bearing(40.0, 10.0, 40.0, 20.0) - 90.0).abs() < 1e-9);
+        assert!((rhumb_bearing(40.0, 20.0, 40.0, 10.0) - 270.0).abs() < 1e-9);
+        // Across the antimeridian the short way is east
+        assert!((rhumb_bearing(10.0, 170.0, 10.0, -170.0) - 90.0).abs() < 1e-9);
+        assert!((rhumb_bearing(10.0, -170.0, 10.0, 170.0) - 270.0).abs() < 1e-9);
+
+        // New York to London: the constant course is well south of the great-circle start
+        let rhumb = rhumb_bearing(40.71, -74.0, 51.51, -0.13);
+        let great_circle = initial_bearing(40.71, -74.0, 51.51, -0.13);
+        assert!(
+            (rhumb - 78.0).abs() < 1.0 && (great_circle - 51.0).abs() < 1.0,
+            "{rhumb} {great_circle}"
+        );
+    }
+
+    #[test]
+    fn one_to_many_midpoints_match_a_single_a_cross_product() {
+        let points_b = sample_points(9, 12, -10.0, 100.0, 40.0);
+        assert_eq!(
+            bits(&midpoints_one_to_many(35.0, 139.0, &points_b)),
+            bits(&calculate_all_midpoints(&[35.0, 139.0], &points_b)),
+        );
+    }
+
+    #[test]
+    fn north_south_weight_reorders_the_ranking() {
+        // Midpoints 10 km north and 15 km east of the target
+        let points_a = [0.0, 0.0];
+        let points_b = [20.0 * DEG_PER_KM, 0.0, 0.0, 30.0 * DEG_PER_KM];
+        let mut options = CombinationOptions::new();
+        options.set_direction_weights(1.0, 1.0).unwrap();
+        let even = find_best_combinations_with_options(&points_a, &points_b, 0.0, 0.0, 2, &options);
+        assert_eq!((even[1], even[6]), (0.0, 1.0));
+        assert!((even[2] - 10.0).abs() < 1e-3 && (even[7] - 15.0).abs() < 1e-3);
+
+        options.set_direction_weights(2.0, 1.0).unwrap();
+        let weighted =
+            find_best_combinations_with_options(&points_a, &points_b, 0.0, 0.0, 2, &options);
+        assert_eq!((weighted[1], weighted[6]), (1.0, 0.0));
+        assert!((weighted[7] - 20.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn radial_counts_are_cumulative() {
+        // Points 3, 7 and 12 km north of the target
+        let points = [
+            3.0 * DEG_PER_KM,
+            0.0,
+            7.0 * DEG_PER_KM,
+            0.0,
+            12.0 * DEG_PER_KM,
+            0.0,
+        ];
+        assert_eq!(
+            radial_counts(&points, 0.0, 0.0, &[5.0, 10.0, 20.0]),
+            vec![1, 2, 3]
+        );
+        assert_eq!(radial_counts(&points, 0.0, 0.0, &[1.0]), vec![0]);
+    }
+
+    #[test]
+    fn partial_run_returns_the_best_of_what_it_scored() {
+        let points_a = sample_points(10, 10, 40.0, -75.0, 1.0);
+        let points_b = sample_points(11, 10, 40.0, -75.0, 1.0);
+        let options = CombinationOptions::new();
+
+        let halfway =
+            find_best_combinations_partial(&points_a, &points_b, 40.5, -74.5, 5, 50, &options);
+        assert_eq!(&halfway[..2], &[1.0, 50.0]);
+        // Pairs are visited A by A, so the first 50 are those of the first 5 A points
+        let expected = find_best_combinations(&points_a[..10], &points_b, 40.5, -74.5, 5);
+        assert_eq!(bits(&halfway[2..]), bits(&expected));
+
+        let complete =
+            find_best_combinations_partial(&points_a, &points_b, 40.5, -74.5, 5, 1000, &options);
+        assert_eq!(&complete[..2], &[0.0, 100.0]);
+        assert_eq!(
+            bits(&complete[2..]),
+            bits(&find_best_combinations(
+                &points_a, &points_b, 40.5, -74.5, 5
+            ))
+        );
+    }
+
+    #[test]
+    fn partial_run_accepts_an_unbounded_top_n() {
+        let points: Vec<f64> = (0..9)
+            .flat_map(|k| [(k / 3) as f64 * 0.5, (k % 3) as f64 * 0.5])
+            .collect();
+        let options = CombinationOptions::new();
+        let results = find_best_combinations_partial(
+            &points,
+            &points,
+            0.5,
+            0.5,
+            usize::MAX,
+            usize::MAX,
+            &options,
+        );
+        assert_eq!(results.len(), 2 + 81 * RESULT_STRIDE);
+        assert_eq!(
+            bits(&results[2..]),
+            bits(&find_best_combinations(
+                &points,
+                &points,
+                0.5,
+                0.5,
+                usize::MAX
+            ))
+        );
+    }
+
+    #[test]
+    fn dms_round_trips_in_every_hemisphere() {
+        assert_eq!(format_dms(-33.8675, 151.207), "33°52'3\"S 151°12'25\"E");
+        for (lat, lon) in [
+            (40.446, -79.982),
+            (-33.8675, 151.207),
+            (-22.9068, -43.1729),
+            (0.0, 0.0),
+        ] {
+            let (parsed_lat, parsed_lon) = parse_dms_pair(&format_dms(lat, lon)).unwrap();
+            // Seconds are rounded, so the round trip is exact to half a second
+            assert!(
+                (parsed_lat - lat).abs() <= 0.5 / 3600.0 + 1e-12,
+                "{lat} -> {parsed_lat}"
+            );
+            assert!(
+                (parsed_lon - lon).abs() <= 0.5 / 3600.0 + 1e-12,
+                "{lon} -> {parsed_lon}"
+            );
+        }
+    }
+
+    #[test]
+    fn spatial_engine_matches_brute_force_with_a_generous_radius() {
+        let points_a = sample_points(20, 30, 40.0, -75.0, 2.0);
+        let points_b = sample_points(21, 30, 40.0, -75.0, 2.0);
+        let options = CombinationOptions::new();
+        for top_n in [1, 10, 900] {
+            let brute = find_best_combinations_with_options(
+                &points_a, &points_b, 41.0, -74.0, top_n, &options,
+            );
+            let spatial = find_best_combinations_spatial(
+                &points_a, &points_b, 41.0, -74.0, top_n, 500.0, &options,
+            );
+            assert_eq!(bits(&spatial), bits(&brute), "top_n {top_n}");
+        }
+
+        let unbounded = find_best_combinations_spatial(
+            &points_a,
+            &points_b,
+            41.0,
+            -74.0,
+            usize::MAX,
+            500.0,
+            &options,
+        );
+        assert_eq!(unbounded.len(), 900 * RESULT_STRIDE);
+    }
+
+    #[test]
+    fn sacrifice_reports_the_unrealized_better_option() {
+        let points_a = [0.0, 0.0];
+        // B1 is far closer to A than the chosen partner B0
+        let points_b = [0.0, 1.0, 0.0, 0.1];
+        let output = participant_sacrifice(&points_a, &points_b, 0, 0);
+        assert_eq!(output.len(), 8);
+
+        let travel = haversine_distance(0.0, 0.0, 0.0, 1.0) / 2.0;
+        let best = haversine_distance(0.0, 0.0, 0.0, 0.1) / 2.0;
+        assert_eq!(&output[..4], &[travel, best, travel - best, 1.0]);
+        // B0 has only one possible partner, so it gives up nothing
+        assert_eq!(&output[4..], &[travel, travel, 0.0, 0.0]);
+
+        assert!(participant_sacrifice(&points_a, &points_b, 1, 0).is_empty());
+    }
+
+    #[test]
+    fn vincenty_midpoint_diverges_only_on_long_baselines() {
+        let gap_km = |lat1: f64, lon1: f64, lat2: f64, lon2: f64| {
+            let spherical =
+                geodesic_midpoint_trig(&TrigPoint::new(lat1, lon1), &TrigPoint::new(lat2, lon2));
+            let ellipsoidal = vincenty_midpoint(lat1, lon1, lat2, lon2);
+            haversine_distance(spherical.0, spherical.1, ellipsoidal[0], ellipsoidal[1])
+        };
+        let short = gap_km(40.7, -74.0, 40.8, -73.9);
+        let trans_pacific = gap_km(35.68, 139.69, 34.05, -118.24);
+        assert!(short < 0.005, "short pair {short} km apart");
+        assert!(
+            trans_pacific > 1.0,
+            "trans-Pacific pair only {trans_pacific} km apart"
+        );
+    }
+
+    #[test]
+    fn hull_metrics_of_a_near_square() {
+        // Corners of a 0.1° square on the equator plus two interior points
+        let points = [
+            0.0, 0.0, 0.05, 0.04, 0.0, 0.1, 0.1, 0.1, 0.03, 0.07, 0.1, 0.0,
+        ];
+        let side = haversine_distance(0.0, 0.0, 0.0, 0.1);
+        let metrics = hull_metrics(&points);
+        assert_eq!(metrics[2], 4.0);
+        assert!(
+            (metrics[0] - 4.0 * side).abs() < 1e-3 * side,
+            "perimeter {}",
+            metrics[0]
+        );
+        assert!(
+            (metrics[1] - side * side).abs() < 1e-3 * side * side,
+            "area {}",
+            metrics[1]
+        );
+    }
+
+    #[test]
+    fn radian_inputs_agree_with_degree_inputs() {
+        let points_a = sample_points(30, 12, -10.0, 100.0, 40.0);
+        let points_b = sample_points(31, 12, -10.0, 100.0, 40.0);
+        let radians = |points: &[f64]| -> Vec<f64> { points.iter().map(|&v| to_rad(v)).collect() };
+
+        let mut options = CombinationOptions::new();
+        options.set_angle_unit(AngleUnit::Radians).unwrap();
+        // The trig comes from the radians as given, not a degree round trip
+        let trig = options.input_trig(&radians(&points_a));
+        assert!(trig
+            .iter()
+            .zip(points_a.chunks_exact(2))
+            .all(|(t, p)| t.lon_rad == to_rad(p[1])));
+
+        let degree = find_best_combinations_with_options(
+            &points_a,
+            &points_b,
+            5.0,
+            120.0,
+            20,
+            &CombinationOptions::new(),
+        );
+        let radian = find_best_combinations_with_options(
+            &radians(&points_a),
+            &radians(&points_b),
+            to_rad(5.0),
+            to_rad(120.0),
+            20,
+            &options,
+        );
+        assert_eq!(radian.len(), degree.len());
+        for (d, r) in degree
+            .chunks_exact(RESULT_STRIDE)
+            .zip(radian.chunks_exact(RESULT_STRIDE))
+        {
+            assert_eq!(&d[..2], &r[..2]);
+            assert!((d[2] - r[2]).abs() < 1e-9, "score {} vs {}", d[2], r[2]);
+            assert!((d[3] - to_deg(r[3])).abs() < 1e-9 && (d[4] - to_deg(r[4])).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn incremental_updates_match_a_full_recompute() {
+        let mut quota = CombinationOptions::new();
+        quota.set_max_per_a(1);
+        let mut separated = CombinationOptions::new();
+        separated.set_min_result_separation(15.0).unwrap();
+
+        for options in [CombinationOptions::new(), quota, separated] {
+            let mut points_a = sample_points(40, 15, 40.0, -75.0, 1.0);
+            let mut points_b = sample_points(41, 15, 40.0, -75.0, 1.0);
+            let mut combos =
+                IncrementalCombinations::new(&points_a, &points_b, 40.5, -74.5, 8, &options);
+            let moves = sample_points(42, 12, 40.0, -75.0, 1.0);
+            for (step, to) in moves.chunks_exact(2).enumerate() {
+                let index = (step * 7) % 15;
+                let (group, points) = if step % 2 == 0 {
+                    (PointGroup::A, &mut points_a)
+                } else {
+                    (PointGroup::B, &mut points_b)
+                };
+                points[index * 2] = to[0];
+                points[index * 2 + 1] = to[1];
+                combos.update_point(group, index, to[0], to[1]).unwrap();
+
+                let fresh = find_best_combinations_with_options(
+                    &points_a, &points_b, 40.5, -74.5, 8, &options,
+                );
+                assert_eq!(bits(&combos.results()), bits(&fresh), "step {step}");
+            }
+        }
+    }
+
+    #[test]
+    fn each_kernel_shape_on_a_known_distance() {
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-12;
+        let apply_kernel = |kernel: ScoreKernel, param_km: f64, score: f64| {
+            let mut options = CombinationOptions::new();
+            options.set_score_kernel(kernel, param_km);
+            options.output_score(score)
+        };
+        assert_eq!(apply_kernel(ScoreKernel::Distance, 20.0, 10.0), 10.0);
+        assert!(close(apply_kernel(ScoreKernel::Linear, 20.0, 10.0), 0.5));
+        assert_eq!(apply_kernel(ScoreKernel::Linear, 20.0, 30.0), 0.0);
+        assert!(close(
+            apply_kernel(ScoreKernel::Gaussian, 20.0, 10.0),
+            (-0.125f64).exp()
+        ));
+        assert!(close(
+            apply_kernel(ScoreKernel::Exponential, 20.0, 10.0),
+            0.5f64.sqrt()
+        ));
+        assert!(close(
+            apply_kernel(ScoreKernel::Exponential, 20.0, 40.0),
+            0.25
+        ));
+        // A bonus-zone score below 0 km is full similarity
+        assert_eq!(apply_kernel(ScoreKernel::Gaussian, 20.0, -3.0), 1.0);
+
+        // Through the engine the kernel replaces the km score
+        let (a, b) = ([0.0, 0.0], [0.0, 0.2]);
+        let distance = find_best_combinations(&a, &b, 0.0, 0.0, 1)[2];
+        let mut options = CombinationOptions::new();
+        options.set_score_kernel(ScoreKernel::Linear, 2.0 * distance);
+        let results = find_best_combinations_with_options(&a, &b, 0.0, 0.0, 1, &options);
+        assert!(close(results[2], 0.5));
+    }
+
+    #[test]
+    fn medoid_picks_the_central_member_of_a_symmetric_triple() {
+        let points = [0.0, -1.0, 0.0, 1.0, 0.0, 0.0];
+        assert_eq!(medoid(&points), vec![2.0, 0.0, 0.0]);
+        assert!(medoid(&[]).is_empty());
+    }
+
+    #[test]
+    fn fraction_endpoints_return_the_inputs_exactly() {
+        let points_a = sample_points(50, 4, -60.0, -170.0, 120.0);
+        let points_b = sample_points(51, 3, -60.0, -170.0, 120.0);
+        let at_a = calculate_all_points_at_fraction(&points_a, &points_b, 0.0);
+        let at_b = calculate_all_points_at_fraction(&points_a, &points_b, 1.0);
+        for i in 0..4 {
+            for j in 0..3 {
+                let k = (i * 3 + j) * 2;
+                assert_eq!(bits(&at_a[k..k + 2]), bits(&points_a[i * 2..i * 2 + 2]));
+                assert_eq!(bits(&at_b[k..k + 2]), bits(&points_b[j * 2..j * 2 + 2]));
+            }
+        }
+    }
+
+    #[test]
+    fn diff_of_overlapping_result_sets() {
+        let record = |a: f64, b: f64| [a, b, 1.0, 0.0, 0.0];
+        let old: Vec<f64> = [record(0.0, 0.0), record(0.0, 1.0), record(1.0, 1.0)].concat();
+        let new: Vec<f64> = [record(0.0, 1.0), record(2.0, 0.0), record(1.0, 1.0)].concat();
+        assert_eq!(
+            diff_results(&old, &new, &CombinationOptions::new()),
+            vec![1.0, 1.0, 1.0, 2.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 0.0]
+        );
+        assert_eq!(
+            diff_results(&old, &old, &CombinationOptions::new()),
+            vec![0.0, 0.0, 0.0]
+        );
+
+        // Widened records are keyed the same way once read by their stride
+        let mut options = CombinationOptions::new();
+        options.set_include_quality(true).unwrap();
+        let widen = |records: &[f64]| -> Vec<f64> {
+            records
+                .chunks_exact(RESULT_STRIDE)
+                .flat_map(|r| [r, &[0.5]].concat())
+                .collect()
+        };
+        assert_eq!(
+            diff_results(&widen(&old), &widen(&new), &options),
+            diff_results(&old, &new, &CombinationOptions::new())
+        );
+    }
+
+    #[test]
+    fn transatlantic_path_crosses_fifty_north() {
+        // JFK to Heathrow; the route tops out above 50°N mid-ocean
+        let (jfk, lhr) = ([40.64, -73.78], [51.47, -0.46]);
+        let crossings = crossing_latitude(jfk[0], jfk[1], lhr[0], lhr[1], 50.0);
+
+        // Bisect the interpolated route for its first crossing of 50°N
+        let at = |f: f64| calculate_all_points_at_fraction(&jfk, &lhr, f);
+        let (mut lo, mut hi) = (0.0, 0.5);
+        for _ in 0..60 {
+            let mid = (lo + hi) / 2.0;
+            if at(mid)[0] < 50.0 {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        let westbound = at(lo)[1];
+        assert!(
+            (-60.0..-40.0).contains(&westbound),
+            "crossing at {westbound}"
+        );
+        assert!(
+            crossings.iter().any(|&lon| (lon - westbound).abs() < 1e-6),
+            "{crossings:?} vs {westbound}"
+        );
+        for &lon in &crossings {
+            assert!((crossing_longitude(jfk[0], jfk[1], lhr[0], lhr[1], lon) - 50.0).abs() < 1e-9);
+        }
+
+        assert!(crossing_latitude(jfk[0], jfk[1], lhr[0], lhr[1], 60.0)[0].is_nan());
+    }
+
+    #[test]
+    fn pair_ids_are_stable_across_targets() {
+        let points_a = sample_points(60, 5, 40.0, -75.0, 1.0);
+        let points_b = sample_points(61, 7, 40.0, -75.0, 1.0);
+        let mut options = CombinationOptions::new();
+        options.set_include_pair_id(true);
+        let ids = |target_lat: f64, target_lon: f64| -> HashMap<(u32, u32), f64> {
+            find_best_combinations_with_options(
+                &points_a, &points_b, target_lat, target_lon, 35, &options,
+            )
+            .chunks_exact(options.stride())
+            .map(|r| ((r[0] as u32, r[1] as u32), r[RESULT_STRIDE]))
+            .collect()
+        };
+        let near = ids(40.1, -74.9);
+        let far = ids(40.9, -74.1);
+        assert_eq!(near.len(), 35);
+        assert_eq!(near, far);
+        assert!(near.iter().all(|(&(a, b), &id)| id == (a * 7 + b) as f64));
+    }
+
+    #[test]
+    fn doubling_weights_keeps_the_center_and_doubles_the_total() {
+        let points = sample_points(70, 6, 30.0, 10.0, 5.0);
+        let weights = [1.0, 4.0, 2.5, 0.5, 3.0, 1.0];
+        let doubled: Vec<f64> = weights.iter().map(|w| w * 2.0).collect();
+        let single = demand_center(&points, &weights);
+        let double = demand_center(&points, &doubled);
+        assert!((single[0] - double[0]).abs() < 1e-12 && (single[1] - double[1]).abs() < 1e-12);
+        assert_eq!(single[2], 12.0);
+        assert_eq!(double[2], 24.0);
+        assert!((single[3] - double[3]).abs() < 1e-9);
+    }
+
+    #[test]
+    fn land_mask_rejects_a_known_region_lazily() {
+        let points_a = sample_points(80, 10, 40.0, -75.0, 1.0);
+        let points_b = sample_points(81, 10, 40.0, -75.0, 1.0);
+        let options = CombinationOptions::new();
+        // Everything east of 74.5°W is "water"
+        let mut calls = 0;
+        let masked = find_best_combinations_masked_by(
+            &points_a,
+            &points_b,
+            40.5,
+            -74.5,
+            5,
+            &options,
+            |_, lon| {
+                calls += 1;
+                lon <= -74.5
+            },
+        );
+
+        let ranking =
+            find_best_combinations_with_options(&points_a, &points_b, 40.5, -74.5, 100, &options);
+        let records: Vec<&[f64]> = ranking.chunks_exact(RESULT_STRIDE).collect();
+        let on_land: Vec<usize> = (0..records.len())
+            .filter(|&k| records[k][4] <= -74.5)
+            .take(5)
+            .collect();
+        let expected: Vec<f64> = on_land
+            .iter()
+            .flat_map(|&k| records[k].iter().copied())
+            .collect();
+        assert_eq!(bits(&masked), bits(&expected));
+        // The predicate only saw candidates up to the fifth accepted one
+        assert_eq!(calls, on_land[4] + 1);
+    }
+
+    #[test]
+    fn spherical_variance_of_a_cluster_and_an_antipodal_pair() {
+        let cluster = sample_points(90, 8, 48.8, 2.3, 0.01);
+        assert!(spherical_variance(&cluster) < 1e-8);
+        assert!(spherical_variance(&[10.0, 20.0, -10.0, -160.0]) > 1.0 - 1e-9);
+        assert!(spherical_variance(&[]).is_nan());
+    }
+
+    #[test]
+    fn close_midpoints_share_a_coarse_geohash() {
+        // Two midpoints about 50 m apart, and one in another city
+        let results = [
+            [0.0, 0.0, 1.0, 57.64911, 10.40744],
+            [0.0, 1.0, 2.0, 57.64950, 10.40800],
+            [1.0, 0.0, 3.0, 55.67610, 12.56830],
+        ]
+        .concat();
+        let options = CombinationOptions::new();
+        let coarse = result_geohashes(&results, 5, &options);
+        assert_eq!(coarse.len(), 3);
+        assert_eq!(coarse[0], coarse[1]);
+        assert_ne!(coarse[0], coarse[2]);
+        let fine = result_geohashes(&results, 9, &options);
+        assert_ne!(fine[0], fine[1]);
+        assert!(fine[0].starts_with(&coarse[0]));
+    }
+
+    #[test]
+    fn approx_equal_at_nine_meters() {
+        let north = 0.009 * DEG_PER_KM;
+        assert!(coords_approx_equal(45.0, 7.0, 45.0 + north, 7.0, 10.0));
+        assert!(!coords_approx_equal(45.0, 7.0, 45.0 + north, 7.0, 5.0));
+    }
+
+    #[test]
+    fn on_bearing_midpoints_outrank_equally_distant_ones() {
+        let mut options = CombinationOptions::new();
+        options.set_target_bearing(0.0, 90.0, 1.0).unwrap();
+        // Pair 0-0 meets 10 km north of the target and pair 1-1 10 km east
+        let points = [10.0 * DEG_PER_KM, 0.0, 0.0, 10.0 * DEG_PER_KM];
+        let results = find_best_combinations_with_options(&points, &points, 0.0, 0.0, 4, &options);
+        let order: Vec<(f64, f64)> = results
+            .chunks_exact(RESULT_STRIDE)
+            .map(|r| (r[0], r[1]))
+            .collect();
+        assert_eq!(order.first(), Some(&(0.0, 0.0)));
+        assert_eq!(order.last(), Some(&(1.0, 1.0)));
+
+        let plain = find_best_combinations(&points, &points, 0.0, 0.0, 4);
+        let plain_scores: Vec<f64> = plain.chunks_exact(RESULT_STRIDE).map(|r| r[2]).collect();
+        assert!((plain_scores[2] - plain_scores[3]).abs() < 1e-6);
+    }
+
+    #[test]
+    fn ranking_index_follows_the_full_output() {
+        let points_a = sample_points(100, 9, 40.0, -75.0, 1.0);
+        let points_b = sample_points(101, 6, 40.0, -75.0, 1.0);
+        let ranking = rank_combinations(&points_a, &points_b, 40.5, -74.5);
+        let full = find_best_combinations(&points_a, &points_b, 40.5, -74.5, usize::MAX);
+        let ids: Vec<u32> = full
+            .chunks_exact(RESULT_STRIDE)
+            .map(|r| r[0] as u32 * 6 + r[1] as u32)
+            .collect();
+        assert_eq!(ranking.len(), 54);
+        assert_eq!(ranking, ids);
+    }
+
+    #[test]
+    fn same_zone_pair_beats_a_twelve_hour_gap() {
+        // Both A points stand in the same place; only their UTC offsets differ
+        let points_a = [40.0, -74.0, 40.0, -74.0];
+        let points_b = [40.2, -74.2];
+        let mut options = CombinationOptions::new();
+        options
+            .set_time_zone_overlap(&[-5.0, 7.0], &[-5.0], 9.0, 21.0, 1.0)
+            .unwrap();
+        let results =
+            find_best_combinations_with_options(&points_a, &points_b, 40.0, -74.0, 2, &options);
+        assert_eq!(&results[..2], &[0.0, 0.0]);
+        assert_eq!(results[RESULT_STRIDE], 1.0);
+        assert!((results[RESULT_STRIDE + 2] - results[2] - 12.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn projection_clamps_to_the_segment_ends() {
+        // Along the equator from 0° to 10°E
+        let inside = project_to_segment(1.0, 5.0, 0.0, 0.0, 0.0, 10.0);
+        assert!(inside[0].abs() < 1e-9 && (inside[1] - 5.0).abs() < 1e-9);
+        assert!((inside[2] - KM_PER_DEG_LAT).abs() < 1e-6);
+
+        let before = project_to_segment(1.0, -3.0, 0.0, 0.0, 0.0, 10.0);
+        assert_eq!(&before[..2], &[0.0, 0.0]);
+        assert!((before[2] - haversine_distance(1.0, -3.0, 0.0, 0.0)).abs() < 1e-9);
+
+        let after = project_to_segment(-1.0, 14.0, 0.0, 0.0, 0.0, 10.0);
+        assert_eq!(&after[..2], &[0.0, 10.0]);
+        assert!((after[2] - haversine_distance(-1.0, 14.0, 0.0, 10.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn far_outlier_barely_moves_the_ninety_percent_circle() {
+        let cluster = sample_points(110, 40, 51.4, -0.2, 0.2);
+        let mut with_outlier = cluster.clone();
+        with_outlier.extend([40.4, -3.7]);
+        let before = covering_circle_quantile(&cluster, 0.9);
+        let after = covering_circle_quantile(&with_outlier, 0.9);
+        // Madrid is over 1000 km out, yet the circle shifts by about a tenth
+        // of its radius, from covering one more cluster point
+        let moved = haversine_distance(before[0], before[1], after[0], after[1]);
+        assert!(moved < 0.15 * before[2], "center moved {moved} km");
+        assert!(
+            (after[2] - before[2]).abs() < 0.15 * before[2],
+            "radius {} -> {}",
+            before[2],
+            after[2]
+        );
+        assert!(covering_circle_quantile(&with_outlier, 1.0)[2] > 500.0);
+    }
+
+    #[test]
+    fn merging_three_shards_matches_a_single_run() {
+        let points_a = sample_points(120, 12, 40.0, -75.0, 1.0);
+        let points_b = sample_points(121, 8, 40.0, -75.0, 1.0);
+        let mut shards = Vec::new();
+        for (start, end) in [(0, 5), (5, 6), (6, 12)] {
+            let mut shard =
+                find_best_combinations(&points_a[start * 2..end * 2], &points_b, 40.5, -74.5, 10);
+            for record in shard.chunks_exact_mut(RESULT_STRIDE) {
+                record[0] += start as f64;
+            }
+            shards.extend(shard);
+        }
+        let defaults = CombinationOptions::new();
+        let merged = merge_shard_runs(&shards, 3, 10, &defaults).unwrap();
+        assert_eq!(
+            bits(&merged),
+            bits(&find_best_combinations(
+                &points_a, &points_b, 40.5, -74.5, 10
+            ))
+        );
+
+        // Each shard is one sorted run of at most top N
+        assert!(merge_shard_runs(&shards, 2, 10, &defaults).is_err());
+        assert!(merge_shard_runs(&shards, 3, 9, &defaults).is_err());
+        let mut reversed: Vec<f64> = shards
+            .chunks_exact(RESULT_STRIDE)
+            .rev()
+            .flatten()
+            .copied()
+            .collect();
+        assert!(merge_shard_runs(&reversed, 3, 10, &defaults).is_err());
+        reversed.truncate(RESULT_STRIDE * 2);
+        assert!(merge_shard_runs(&reversed, 3, 10, &defaults).is_ok());
+    }
+
+    #[test]
+    fn merging_widened_shards_keeps_their_extras() {
+        let points_a = sample_points(122, 10, 40.0, -75.0, 1.0);
+        let points_b = sample_points(123, 6, 40.0, -75.0, 1.0);
+        let mut options = CombinationOptions::new();
+        options.set_include_quality(true).unwrap();
+        options.set_coord_order(CoordOrder::LonLat);
+        let swapped_a = swap_pairs(&points_a);
+        let swapped_b = swap_pairs(&points_b);
+        let mut shards = Vec::new();
+        for (start, end) in [(0, 4), (4, 10)] {
+            let slice = &swapped_a[start * 2..end * 2];
+            let mut shard =
+                find_best_combinations_with_options(slice, &swapped_b, 40.5, -74.5, 8, &options);
+            for record in shard.chunks_exact_mut(options.stride()) {
+                record[0] += start as f64;
+            }
+            shards.extend(shard);
+        }
+        let merged = merge_shard_runs(&shards, 2, 8, &options).unwrap();
+        let single =
+            find_best_combinations_with_options(&swapped_a, &swapped_b, 40.5, -74.5, 8, &options);
+        assert_eq!(bits(&merged), bits(&single));
+
+        // Kernel scores and ranks do not compare across shards
+        options.set_score_kernel(ScoreKernel::Linear, 50.0);
+        assert!(merge_shard_runs(&shards, 2, 8, &options).is_err());
+        options.set_score_kernel(ScoreKernel::Distance, 0.0);
+        options.set_score_as_rank(true);
+        assert!(merge_shard_runs(&shards, 2, 8, &options).is_err());
+    }
+
+    #[test]
+    fn high_cost_cell_demotes_a_good_midpoint() {
+        // Pair 0-0 meets 1 km north of the target, pair 1-1 3 km south
+        let points = [1.0 * DEG_PER_KM, 0.0, -3.0 * DEG_PER_KM, 0.0];
+        let plain = find_best_combinations_with_options(
+            &points,
+            &points,
+            0.0,
+            0.0,
+            4,
+            &CombinationOptions::new(),
+        );
+        assert_eq!(&plain[..2], &[0.0, 0.0]);
+
+        // A 2 x 1 raster: the northern cell costs ten times as much
+        let mut options = CombinationOptions::new();
+        options
+            .set_cost_raster(
+                &[10.0, 1.0],
+                2,
+                1,
+                -0.1,
+                -0.1,
+                0.1,
+                0.1,
+                RasterSampling::Nearest,
+            )
+            .unwrap();
+        let costed = find_best_combinations_with_options(&points, &points, 0.0, 0.0, 4, &options);
+        let order: Vec<(f64, f64)> = costed
+            .chunks_exact(RESULT_STRIDE)
+            .map(|r| (r[0], r[1]))
+            .collect();
+        assert_eq!(order.last(), Some(&(0.0, 0.0)));
+        assert!((costed[costed.len() - 3] - 10.0 * plain[2]).abs() < 1e-6);
+    }
+
+    #[test]
+    fn self_join_yields_one_midpoint_per_unordered_pair() {
+        for n in [0, 1, 2, 7] {
+            let points = sample_points(130, n, 10.0, 10.0, 5.0);
+            let output = self_midpoints(&points);
+            assert_eq!(output.len(), n * n.saturating_sub(1) / 2 * 4, "n = {n}");
+            assert!(output.chunks_exact(4).all(|r| r[2] < r[3]));
+        }
+    }
+
+    #[test]
+    fn max_travel_is_the_longer_leg() {
+        // A rhumb midpoint of a long oblique pair is off-center on the sphere,
+        // so the two legs can differ
+        let points_a = [10.0, -60.0, 42.0, 3.0];
+        let points_b = [55.0, 20.0, -5.0, 100.0];
+        let mut options = CombinationOptions::new();
+        options.set_midpoint_method(MidpointMethod::Rhumb).unwrap();
+        options.set_include_max_travel(true);
+        let results =
+            find_best_combinations_with_options(&points_a, &points_b, 30.0, 20.0, 4, &options);
+        assert_eq!(results.len(), 4 * options.stride());
+        let mut largest_gap: f64 = 0.0;
+        for r in results.chunks_exact(options.stride()) {
+            let (a, b) = (r[0] as usize * 2, r[1] as usize * 2);
+            let leg_a = haversine_distance(points_a[a], points_a[a + 1], r[3], r[4]);
+            let leg_b = haversine_distance(points_b[b], points_b[b + 1], r[3], r[4]);
+            largest_gap = largest_gap.max((leg_a - leg_b).abs());
+            assert_eq!(r[RESULT_STRIDE], leg_a.max(leg_b));
+        }
+        assert!(
+            largest_gap > 1.0,
+            "legs never differ by more than {largest_gap} km"
+        );
+    }
+
+    #[test]
+    fn stacked_midpoints_get_distinct_nearby_offsets() {
+        // A0 and A1 coincide, so pairs 0-0 and 1-0 stack; pair 2-0 stands alone
+        let points_a = [10.0, 20.0, 10.0, 20.0, 12.0, 21.0];
+        let points_b = [11.0, 23.0];
+        let plain = calculate_all_midpoints(&points_a, &points_b);
+        let jittered = calculate_all_midpoints_jittered(&points_a, &points_b, 50.0);
+        assert_eq!(bits(&plain[0..2]), bits(&plain[2..4]));
+        for k in [0, 1] {
+            let offset_m =
+                haversine_distance(plain[0], plain[1], jittered[k * 2], jittered[k * 2 + 1])
+                    * 1000.0;
+            assert!(offset_m <= 50.0 + 1e-6, "moved {offset_m} m");
+        }
+        assert_ne!(bits(&jittered[0..2]), bits(&jittered[2..4]));
+        assert_eq!(bits(&jittered[4..6]), bits(&plain[4..6]));
+        // The offset depends only on the pair id
+        assert_eq!(
+            bits(&jittered),
+            bits(&calculate_all_midpoints_jittered(
+                &points_a, &points_b, 50.0
+            ))
+        );
+    }
+
+    #[test]
+    fn percentile_on_a_hand_countable_set() {
+        // Midpoints 1°, 2°, 3° and 4° east of the target
+        let points_a = [0.0, 0.0];
+        let points_b = [0.0, 2.0, 0.0, 4.0, 0.0, 6.0, 0.0, 8.0];
+        assert_eq!(score_percentile(&points_a, &points_b, 0.0, 0.0, 0, 0), 0.75);
+        assert_eq!(score_percentile(&points_a, &points_b, 0.0, 0.0, 0, 1), 0.5);
+        assert_eq!(score_percentile(&points_a, &points_b, 0.0, 0.0, 0, 3), 0.0);
+        assert!(score_percentile(&points_a, &points_b, 0.0, 0.0, 0, 4).is_nan());
+    }
+
+    #[test]
+    fn full_fan_sits_on_the_ring() {
+        let bearings: Vec<f64> = (0..360).map(f64::from).collect();
+        let fan = destination_fan(-33.9, 18.4, 250.0, &bearings);
+        assert_eq!(fan.len(), 720);
+        for p in fan.chunks_exact(2) {
+            assert!((haversine_distance(-33.9, 18.4, p[0], p[1]) - 250.0).abs() < 1e-6);
+            assert!((-180.0..180.0).contains(&p[1]));
+        }
+    }
+
+    #[test]
+    fn separation_collapses_near_identical_midpoints() {
+        // A0 and A1 are 11 m apart, so each B gets two midpoints about 6 m apart
+        let points_a = [0.0, 0.0, 0.0, 0.0001];
+        let points_b = [0.0, 1.0, 0.0, 3.0];
+        let plain = find_best_combinations_with_options(
+            &points_a,
+            &points_b,
+            0.0,
+            0.5,
+            4,
+            &CombinationOptions::new(),
+        );
+        assert_eq!(plain.len(), 4 * RESULT_STRIDE);
+
+        let mut options = CombinationOptions::new();
+        options.set_min_result_separation(1.0).unwrap();
+        let separated =
+            find_best_combinations_with_options(&points_a, &points_b, 0.0, 0.5, 4, &options);
+        let pairs: Vec<(f64, f64)> = separated
+            .chunks_exact(RESULT_STRIDE)
+            .map(|r| (r[0], r[1]))
+            .collect();
+        assert_eq!(pairs, vec![(0.0, 0.0), (0.0, 1.0)]);
+    }
+
+    #[test]
+    fn karcher_mean_converges_near_the_extrinsic_mean() {
+        // A lopsided few-degree cluster, so the two means can differ
+        let points = [50.0, 10.0, 50.5, 10.2, 51.0, 13.0, 49.0, 9.5, 50.2, 12.0];
+        let centroid = weighted_centroid(&points, &[1.0; 5]);
+        let mean = karcher_mean(&points, 100, 1e-9);
+        assert!(
+            mean[2] < 100.0 && mean[3] < 1e-9,
+            "iterations {}, step {}",
+            mean[2],
+            mean[3]
+        );
+
+        let gap_km = haversine_distance(mean[0], mean[1], centroid[0], centroid[1]);
+        assert!(gap_km > 0.0 && gap_km < 1.0, "means {gap_km} km apart");
+
+        // One step is not enough to converge to that tolerance
+        assert!(karcher_mean(&points, 1, 1e-9)[3] > 1e-9);
+    }
+
+    #[test]
+    fn quota_of_one_never_repeats_an_a_index() {
+        let points_a = sample_points(150, 6, 40.0, -75.0, 1.0);
+        let points_b = sample_points(151, 20, 40.0, -75.0, 1.0);
+        let mut options = CombinationOptions::new();
+        options.set_max_per_a(1);
+        for top_n in [3, 6, 50] {
+            let results = find_best_combinations_with_options(
+                &points_a, &points_b, 40.5, -74.5, top_n, &options,
+            );
+            let a_indices: Vec<u32> = results
+                .chunks_exact(RESULT_STRIDE)
+                .map(|r| r[0] as u32)
+                .collect();
+            let distinct: std::collections::HashSet<u32> = a_indices.iter().copied().collect();
+            assert_eq!(a_indices.len(), top_n.min(6));
+            assert_eq!(distinct.len(), a_indices.len());
+        }
+    }
+
+    #[test]
+    fn east_west_pair_heads_due_east_at_the_midpoint() {
+        // At its midpoint the circle through two points on a parallel is at
+        // its highest latitude, so it runs exactly east-west there
+        assert!((midpoint_azimuth(45.0, 0.0, 45.0, 10.0) - 90.0).abs() < 1e-9);
+        assert!((midpoint_azimuth(45.0, 10.0, 45.0, 0.0) - 270.0).abs() < 1e-9);
+        assert!((midpoint_azimuth(0.0, -20.0, 0.0, 20.0) - 90.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn region_filter_matches_cells_in_either_case() {
+        // Pair 0-0 meets in Manhattan, pair 1-1 in Brooklyn
+        let points = [40.7580, -73.9855, 40.6782, -73.9442];
+        let manhattan = geohash_encode(points[0], points[1], 5);
+        let brooklyn = geohash_encode(points[2], points[3], 5);
+        assert_ne!(manhattan, brooklyn);
+
+        let mut options = CombinationOptions::new();
+        options
+            .set_region_filter(
+                vec![manhattan.to_uppercase(), brooklyn.clone()],
+                &[1, 2],
+                &[1],
+            )
+            .unwrap();
+        let results =
+            find_best_combinations_with_options(&points, &points, 40.7, -74.0, 4, &options);
+        let pairs: Vec<(f64, f64)> = results
+            .chunks_exact(RESULT_STRIDE)
+            .map(|r| (r[0], r[1]))
+            .collect();
+        assert_eq!(pairs, vec![(0.0, 0.0)]);
+
+        options
+            .set_region_filter(vec![manhattan, brooklyn.to_uppercase()], &[1, 2], &[2])
+            .unwrap();
+        let results =
+            find_best_combinations_with_options(&points, &points, 40.7, -74.0, 4, &options);
+        assert_eq!(&results[..2], &[1.0, 1.0]);
+        assert_eq!(results.len(), RESULT_STRIDE);
+    }
+
+    #[test]
+    fn streamed_quantiles_track_the_exact_ones() {
+        let points_a = sample_points(160, 60, 40.0, -75.0, 2.0);
+        let points_b = sample_points(161, 60, 40.0, -75.0, 2.0);
+        let quantiles = [0.0, 0.1, 0.25, 0.5, 0.9, 1.0];
+        let estimates = score_quantiles(&points_a, &points_b, 41.0, -74.0, &quantiles);
+
+        let mut scores: Vec<f64> =
+            find_best_combinations(&points_a, &points_b, 41.0, -74.0, usize::MAX)
+                .chunks_exact(RESULT_STRIDE)
+                .map(|r| r[2])
+                .collect();
+        scores.sort_by(f64::total_cmp);
+        let range = scores[scores.len() - 1] - scores[0];
+        for (&q, &estimate) in quantiles.iter().zip(&estimates) {
+            let exact = scores[((scores.len() - 1) as f64 * q).round() as usize];
+            assert!(
+                (estimate - exact).abs() < 0.01 * range,
+                "q {q}: {estimate} vs {exact}"
+            );
+        }
+        assert_eq!(estimates[0], scores[0]);
+        assert_eq!(estimates[5], scores[scores.len() - 1]);
+    }
+
+    #[test]
+    fn planar_mode_on_a_square() {
+        let mut options = CombinationOptions::new();
+        options.set_geometry(Geometry::Planar).unwrap();
+        // Corners of a 10-unit square; the target is the origin corner
+        let points_a = [0.0, 0.0, 10.0, 10.0];
+        let points_b = [0.0, 10.0, 10.0, 0.0];
+        let results =
+            find_best_combinations_with_options(&points_a, &points_b, 0.0, 0.0, 4, &options);
+        let expected = [
+            [0.0, 0.0, 5.0, 0.0, 5.0],
+            [0.0, 1.0, 5.0, 5.0, 0.0],
+            [1.0, 0.0, 125f64.sqrt(), 5.0, 10.0],
+            [1.0, 1.0, 125f64.sqrt(), 10.0, 5.0],
+        ];
+        assert_eq!(results, expected.concat());
+
+        // Globe-only options can still be turned off in the plane, and one
+        // already set keeps options from switching to it
+        options.set_angle_unit(AngleUnit::Degrees).unwrap();
+        options.set_include_quality(false).unwrap();
+        let mut spherical = CombinationOptions::new();
+        assert_eq!(spherical.spherical_only_option(), None);
+        spherical.set_bonus_zone(0.0, 0.0, 1.0, 1.0).unwrap();
+        spherical.set_min_result_separation(1.0).unwrap();
+        assert_eq!(spherical.spherical_only_option(), Some("a bonus zone"));
+        spherical.clear_bonus_zone();
+        assert_eq!(spherical.spherical_only_option(), Some("result separation"));
+    }
+
+    #[test]
+    fn results_centroid_of_three_axis_midpoints() {
+        // Unit vectors along x, y and z average to the (1, 1, 1) direction
+        let results = [
+            [0.0, 0.0, 1.0, 0.0, 0.0],
+            [0.0, 1.0, 2.0, 0.0, 90.0],
+            [1.0, 0.0, 3.0, 90.0, 0.0],
+        ]
+        .concat();
+        let defaults = CombinationOptions::new();
+        let centroid = results_centroid(&results, &defaults);
+        assert!((centroid[0] - to_deg((1.0 / 2f64.sqrt()).atan())).abs() < 1e-9);
+        assert!((centroid[1] - 45.0).abs() < 1e-9);
+
+        assert_eq!(
+            results_centroid(&results[..RESULT_STRIDE * 2], &defaults),
+            vec![0.0, 45.0]
+        );
+        assert_eq!(
+            results_centroid(&[1.0, 2.0, 3.0, 12.5, -7.25], &defaults),
+            vec![12.5, -7.25]
+        );
+        assert!(results_centroid(&[], &defaults).is_empty());
+    }
+
+    #[test]
+    fn results_centroid_reads_widened_lon_lat_records() {
+        let points_a = swap_pairs(&sample_points(172, 6, 40.0, -75.0, 1.0));
+        let points_b = swap_pairs(&sample_points(173, 5, 40.0, -75.0, 1.0));
+        let mut options = CombinationOptions::new();
+        options.set_include_quality(true).unwrap();
+        options.set_coord_order(CoordOrder::LonLat);
+        let results =
+            find_best_combinations_with_options(&points_a, &points_b, 40.5, -74.5, 10, &options);
+
+        // Same midpoints, unpacked by hand from the 6-value lon,lat records
+        let midpoints: Vec<f64> = results
+            .chunks_exact(options.stride())
+            .flat_map(|record| [record[4], record[3]])
+            .collect();
+        let expected = weighted_centroid(&midpoints, &vec![1.0; midpoints.len() / 2]);
+        let centroid = results_centroid(&results, &options);
+        assert!(
+            (centroid[0] - expected[1]).abs() < 1e-12,
+            "{centroid:?} vs {expected:?}"
+        );
+        assert!(
+            (centroid[1] - expected[0]).abs() < 1e-12,
+            "{centroid:?} vs {expected:?}"
+        );
+    }
+
+    #[test]
+    fn echoed_coordinates_match_the_reported_indices() {
+        let points_a = sample_points(170, 5, 40.0, -75.0, 1.0);
+        let points_b = sample_points(171, 4, 40.0, -75.0, 1.0);
+        let mut options = CombinationOptions::new();
+        options.set_include_coordinates(true);
+        let results =
+            find_best_combinations_with_options(&points_a, &points_b, 40.5, -74.5, 20, &options);
+        assert_eq!(options.stride(), RESULT_STRIDE + 4);
+        for r in results.chunks_exact(options.stride()) {
+            let (a, b) = (r[0] as usize * 2, r[1] as usize * 2);
+            assert_eq!(
+                &r[5..9],
+                &[points_a[a], points_a[a + 1], points_b[b], points_b[b + 1]]
+            );
+        }
+
+        // In lon,lat order the echo follows the input order too
+        options.set_coord_order(CoordOrder::LonLat);
+        let swapped_a = swap_pairs(&points_a);
+        let swapped_b = swap_pairs(&points_b);
+        let results =
+            find_best_combinations_with_options(&swapped_a, &swapped_b, 40.5, -74.5, 20, &options);
+        for r in results.chunks_exact(options.stride()) {
+            let (a, b) = (r[0] as usize * 2, r[1] as usize * 2);
+            assert_eq!(
+                &r[5..9],
+                &[
+                    swapped_a[a],
+                    swapped_a[a + 1],
+                    swapped_b[b],
+                    swapped_b[b + 1]
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn normalize_longitude_edge_values() {
+        assert_eq!(normalize_longitude(180.0), -180.0);
+        assert_eq!(normalize_longitude(-180.0), -180.0);
+        assert_eq!(normalize_longitude(540.0), -180.0);
+        assert_eq!(normalize_longitude(-270.0), 90.0);
+        assert_eq!(normalize_longitude(179.5), 179.5);
+        assert_eq!(normalize_longitude(-1e-20), -1e-20);
+        assert!(normalize_longitude(f64::NAN).is_nan());
+    }
+
+    #[test]
+    fn midpoint_beside_the_middle_of_a_line_scores_well() {
+        let mut options = CombinationOptions::new();
+        options.set_target_line(vec![0.0, 0.0, 0.0, 10.0]).unwrap();
+        // Pair 0-0 meets 1 km off the middle of the line, 550 km from either end;
+        // pair 1-1 meets near the start but 50 km off the line
+        let points = [DEG_PER_KM, 5.0, 50.0 * DEG_PER_KM, 0.1];
+        let results = find_best_combinations_with_options(&points, &points, 0.0, 0.0, 4, &options);
+        assert_eq!(&results[..2], &[0.0, 0.0]);
+        assert!((results[2] - 1.0).abs() < 1e-6, "score {}", results[2]);
+        assert!(haversine_distance(results[3], results[4], 0.0, 0.0) > 500.0);
+    }
+
+    #[test]
+    fn isoline_vertices_sit_at_the_distance() {
+        let ring = isoline(35.0, 179.5, 120.0, 36);
+        // 36 vertices plus the closing repeat
+        assert_eq!(ring.len(), 37 * 2);
+        assert_eq!(&ring[..2], &ring[72..]);
+        for p in ring.chunks_exact(2) {
+            assert!((haversine_distance(35.0, 179.5, p[0], p[1]) - 120.0).abs() < 1e-6);
+        }
+        // Across the antimeridian the ring unwraps rather than jumping
+        assert!(ring
+            .chunks_exact(2)
+            .zip(ring.chunks_exact(2).skip(1))
+            .all(|(p, q)| (p[1] - q[1]).abs() < 180.0));
+        assert!(isoline(0.0, 0.0, 10.0, 2).is_empty());
+    }
+
+    #[test]
+    fn harmonic_travel_sits_between_and_leans_to_the_shorter_leg() {
+        let mut options = CombinationOptions::new();
+        options.set_scoring_mode(ScoringMode::HarmonicTravel);
+        let score = |lat_b: f64| {
+            // A is 10 km north of the target, B the given distance south
+            let a = [10.0 * DEG_PER_KM, 0.0];
+            let b = [-lat_b * DEG_PER_KM, 0.0];
+            find_best_combinations_with_options(&a, &b, 0.0, 0.0, 1, &options)[2]
+        };
+
+        let balanced = score(12.0);
+        assert!(balanced > 10.0 && balanced < 12.0);
+        let lopsided = score(90.0);
+        // The mean would be 50 km; the harmonic mean is 18
+        assert!((lopsided - 18.0).abs() < 1e-6, "score {lopsided}");
+        // Both on the target: zero legs score 0 rather than 0 / 0
+        let on_target = [0.0, 0.0];
+        assert_eq!(
+            find_best_combinations_with_options(&on_target, &on_target, 0.0, 0.0, 1, &options)[2],
+            0.0
+        );
+    }
+
+    #[test]
+    fn dbscan_labels_scattered_points_as_noise() {
+        let mut points = sample_points(180, 15, 48.85, 2.35, 0.01);
+        points.extend(sample_points(181, 15, 52.52, 13.40, 0.01));
+        // Lone points hundreds of km from each other and from both blobs
+        let noise = [45.0, -1.0, 55.0, 25.0, 41.0, 12.0, 60.0, 5.0];
+        points.extend(noise);
+        let labels = dbscan_clusters(&points, 2.0, 4).unwrap();
+        assert!(labels[..15].iter().all(|&l| l == 0.0));
+        assert!(labels[15..30].iter().all(|&l| l == 1.0));
+        assert_eq!(&labels[30..], &[-1.0; 4]);
+    }
+
+    #[test]
+    fn polar_grid_query_only_spans_reachable_longitudes() {
+        // A ring at 89.5°N with a point every 0.1° of longitude, about 100 m apart
+        let ring: Vec<f64> = (0..3600)
+            .flat_map(|i| [89.5, i as f64 * 0.1 - 180.0])
+            .collect();
+        let grid = PointGrid::new(&ring, 1.0 / KM_PER_DEG_LAT);
+        let mut reported = Vec::new();
+        grid.query(89.5, 0.0, 1.0, |i| reported.push(i as usize));
+
+        let within: Vec<usize> = (0..3600)
+            .filter(|&i| haversine_distance(89.5, 0.0, ring[i * 2], ring[i * 2 + 1]) <= 1.0)
+            .collect();
+        assert!(within.iter().all(|i| reported.contains(i)));
+        // 1 km reaches about 1° of longitude either way at this latitude
+        assert!(reported.len() < 40, "reported {} of 3600", reported.len());
+
+        // With the pole inside the circle every longitude is in range
+        let mut around_pole = 0;
+        grid.query(89.9, 0.0, 60.0, |_| around_pole += 1);
+        assert_eq!(around_pole, 3600);
+    }
+
+    #[test]
+    fn inverse_geodesic_reference_pairs() {
+        // Land's End to John o'Groats: 968.9 km, 009°07′11″ out, 011°16′31″ in
+        let lands_end = inverse_geodesic(50.0664, -5.7147, 58.6439, -3.0700);
+        assert!(
+            (lands_end[0] - 968.9).abs() < 0.1,
+            "distance {}",
+            lands_end[0]
+        );
+        assert!((lands_end[1] - (9.0 + 7.0 / 60.0 + 11.0 / 3600.0)).abs() < 1e-3);
+        assert!((lands_end[2] - (11.0 + 16.0 / 60.0 + 31.0 / 3600.0)).abs() < 1e-3);
+
+        // A quarter of the equator
+        let equator = inverse_geodesic(0.0, 0.0, 0.0, 90.0);
+        assert!((equator[0] - EARTH_RADIUS_KM * PI / 2.0).abs() < 1e-9);
+        assert!((equator[1] - 90.0).abs() < 1e-9 && (equator[2] - 90.0).abs() < 1e-9);
+
+        // JFK to Heathrow sets off northeast and arrives heading southeast
+        let transatlantic = inverse_geodesic(40.64, -73.78, 51.47, -0.46);
+        assert!((transatlantic[0] - haversine_distance(40.64, -73.78, 51.47, -0.46)).abs() < 1e-9);
+        assert!(
+            transatlantic[2] - transatlantic[1] > 40.0,
+            "{transatlantic:?}"
+        );
+        assert!(transatlantic[1] < 90.0 && transatlantic[2] > 90.0);
+    }
+
+    #[test]
+    fn raising_the_score_cap_only_grows_the_results() {
+        let points_a = sample_points(190, 25, 40.0, -75.0, 2.0);
+        let points_b = sample_points(191, 25, 40.0, -75.0, 2.0);
+        let all = find_best_combinations(&points_a, &points_b, 41.0, -74.0, usize::MAX);
+
+        let mut previous: HashSet<(u32, u32)> = HashSet::new();
+        for cap in [0.5, 5.0, 20.0, 40.0, 80.0, 200.0] {
+            let mut options = CombinationOptions::new();
+            options.set_max_score(cap);
+            let results = find_best_combinations_with_options(
+                &points_a,
+                &points_b,
+                41.0,
+                -74.0,
+                usize::MAX,
+                &options,
+            );
+            // Pruning drops nothing a plain filter would keep
+            let filtered: Vec<f64> = all
+                .chunks_exact(RESULT_STRIDE)
+                .filter(|r| r[2] <= cap)
+                .flatten()
+                .copied()
+                .collect();
+            assert_eq!(bits(&results), bits(&filtered), "cap {cap}");
+
+            let pairs: HashSet<(u32, u32)> = results
+                .chunks_exact(RESULT_STRIDE)
+                .map(|r| (r[0] as u32, r[1] as u32))
+                .collect();
+            assert!(previous.is_subset(&pairs), "cap {cap} lost results");
+            previous = pairs;
+
+            // Skipped pairs still count towards the budget
+            let partial = find_best_combinations_partial(
+                &points_a,
+                &points_b,
+                41.0,
+                -74.0,
+                10,
+                usize::MAX,
+                &options,
+            );
+            assert_eq!(&partial[..2], &[0.0, 625.0]);
+            let halfway = find_best_combinations_partial(
+                &points_a, &points_b, 41.0, -74.0, 10, 300, &options,
+            );
+            assert_eq!(&halfway[..2], &[1.0, 300.0]);
+        }
+        assert_eq!(previous.len(), 625);
+
+        // A tight cap skips pairs unscored, and whole rows when A and B sit at
+        // different distances from the target
+        let mut options = CombinationOptions::new();
+        options.set_max_score(5.0);
+        let target = TrigPoint::new(41.0, -74.0);
+        let (trig_a, trig_b) = (precompute_trig(&points_a), precompute_trig(&points_b));
+        let pruning = ScoreCapPruning::new(&trig_a, &trig_b, &target, &options).unwrap();
+        assert!((0..25).any(|i| (0..25).any(|j| !pruning.pair_can_pass(i, j))));
+        let near_target = precompute_trig(&sample_points(192, 25, 40.9, -74.1, 0.2));
+        let far_away = precompute_trig(&sample_points(193, 25, 43.0, -74.1, 0.2));
+        let pruning = ScoreCapPruning::new(&near_target, &far_away, &target, &options).unwrap();
+        assert!((0..25).all(|i| !pruning.row_can_pass(i)));
+        options.set_midpoint_method(MidpointMethod::Rhumb).unwrap();
+        assert!(ScoreCapPruning::new(&trig_a, &trig_b, &target, &options).is_none());
+
+        // Both groups lie at least 60 km from this target, a cap of 1 km leaves nothing
+        let mut options = CombinationOptions::new();
+        options.set_max_score(1.0);
+        assert!(find_best_combinations_with_options(
+            &points_a, &points_b, 43.0, -74.0, 10, &options
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn snaps_to_the_candidate_nearest_the_midpoint() {
+        // Two candidates sit right next to the endpoints, one 0.5° off the midpoint
+        let candidates = [0.0, 0.1, 0.5, 5.0, 0.0, 9.9];
+        let snapped = midpoint_then_nearest(0.0, 0.0, 0.0, 10.0, &candidates);
+        assert_eq!(&snapped[..3], &[1.0, 0.5, 5.0]);
+        assert!((snapped[3] - 0.5 * KM_PER_DEG_LAT).abs() < 1e-6);
+        assert!(midpoint_then_nearest(0.0, 0.0, 0.0, 10.0, &[]).is_empty());
+    }
+
+    #[test]
+    fn central_venue_scores_best() {
+        let points_a = [0.1, -1.0, -0.1, -1.1];
+        let points_b = [0.0, 1.0, 0.2, 1.2];
+        // Near the A group, between the groups, near the B group
+        let candidates = [0.0, -0.8, 0.05, 0.0, 0.1, 0.9];
+        let scored =
+            score_candidates(&candidates, &points_a, &points_b, ScoringMode::MaxTravel).unwrap();
+        assert_eq!(scored.len(), 12);
+        assert_eq!(scored[0], 1.0);
+        assert_eq!(&scored[2..4], &[0.0, 0.0]);
+        assert!(scored[1] < scored[5] && scored[1] < scored[9]);
+    }
+
+    #[test]
+    fn fixed_point_outputs_are_byte_identical_for_the_same_quantized_inputs() {
+        // Inputs on the 1e-7° grid, and copies nudged well inside half a step
+        let on_grid: Vec<f64> = sample_points(200, 30, 40.0, -75.0, 1.0)
+            .iter()
+            .map(|&v| quantize(v, 1e7))
+            .collect();
+        let nudged: Vec<f64> = on_grid
+            .iter()
+            .enumerate()
+            .map(|(k, &v)| v + if k % 2 == 0 { 1e-9 } else { -1e-9 })
+            .collect();
+        let (points_a, points_b) = on_grid.split_at(30);
+        let (nudged_a, nudged_b) = nudged.split_at(30);
+
+        let mut options = CombinationOptions::new();
+        options.set_fixed_point(true);
+        let bytes = |results: Vec<f64>| -> Vec<u8> {
+            results.iter().flat_map(|v| v.to_le_bytes()).collect()
+        };
+        let reference = bytes(find_best_combinations_with_options(
+            points_a, points_b, 40.5, -74.5, 50, &options,
+        ));
+        assert_eq!(reference.len(), 50 * RESULT_STRIDE * 8);
+        assert_eq!(
+            reference,
+            bytes(find_best_combinations_with_options(
+                nudged_a, nudged_b, 40.5, -74.5, 50, &options
+            ))
+        );
+        assert_eq!(
+            reference,
+            bytes(find_best_combinations_with_options(
+                nudged_a,
+                nudged_b,
+                40.5 + 1e-9,
+                -74.5,
+                50,
+                &options
+            ))
+        );
+
+        // Without quantization the nudge shows up in the output
+        let plain = CombinationOptions::new();
+        assert_ne!(
+            bytes(find_best_combinations_with_options(
+                points_a, points_b, 40.5, -74.5, 50, &plain
+            )),
+            bytes(find_best_combinations_with_options(
+                nudged_a, nudged_b, 40.5, -74.5, 50, &plain
+            ))
+        );
+    }
+
+    #[test]
+    fn square_is_solid_and_l_shape_is_not() {
+        // A small square fills its own box
+        let mut square = Vec::new();
+        for i in 0..5 {
+            for j in 0..5 {
+                square.extend([10.0 + i as f64 * 0.1, 20.0 + j as f64 * 0.1]);
+            }
+        }
+        let solid = hull_solidity(&square);
+        assert!(solid > 0.99 && solid <= 1.0, "{solid}");
+
+        // An L along the bottom and left edges: the hull cuts off half the box
+        let mut ell = Vec::new();
+        for k in 0..5 {
+            ell.extend([10.0, 20.0 + k as f64 * 0.1]);
+            ell.extend([10.0 + k as f64 * 0.1, 20.0]);
+        }
+        let l_solidity = hull_solidity(&ell);
+        assert!((l_solidity - 0.5).abs() < 0.01, "{l_solidity}");
+        assert!(l_solidity < solid);
+
+        assert_eq!(hull_solidity(&[10.0, 20.0, 10.1, 20.0, 10.2, 20.0]), 0.0);
+    }
+
+    #[test]
+    fn recolored_heatmap_matches_a_fresh_binning() {
+        let points_a = sample_points(210, 20, 40.0, -75.0, 2.0);
+        let points_b = sample_points(211, 20, 40.5, -74.5, 2.0);
+        let (rows, cols) = (4, 5);
+        let (min_lat, min_lon, max_lat, max_lon) = (40.2, -74.9, 41.8, -73.1);
+        let cache = HeatmapCache::new(
+            &points_a,
+            &points_b,
+            rows,
+            cols,
+            min_lat,
+            min_lon,
+            max_lat,
+            max_lon,
+            ScoreKernel::Gaussian,
+            30.0,
+        )
+        .unwrap();
+
+        let fresh = |target_lat: f64, target_lon: f64| {
+            let mut cells = vec![0.0; rows * cols];
+            for mid in calculate_all_midpoints(&points_a, &points_b).chunks_exact(2) {
+                if !(min_lat..=max_lat).contains(&mid[0]) || !(min_lon..=max_lon).contains(&mid[1])
+                {
+                    continue;
+                }
+                let row = (((max_lat - mid[0]) / (max_lat - min_lat) * rows as f64) as usize)
+                    .min(rows - 1);
+                let col = (((mid[1] - min_lon) / (max_lon - min_lon) * cols as f64) as usize)
+                    .min(cols - 1);
+                let distance = haversine_distance(mid[0], mid[1], target_lat, target_lon);
+                cells[row * cols + col] += (-distance * distance / (2.0 * 30.0 * 30.0)).exp();
+            }
+            cells
+        };
+
+        assert!(cache.midpoint_count() > 0 && cache.midpoint_count() < 400);
+        // The same cache serves a moving target
+        for (target_lat, target_lon) in [(41.0, -74.0), (40.3, -74.8), (41.7, -73.2)] {
+            let recolored = cache.recolor(target_lat, target_lon);
+            let expected = fresh(target_lat, target_lon);
+            assert!(expected.iter().sum::<f64>() > 0.0);
+            for (got, want) in recolored.iter().zip(&expected) {
+                assert!((got - want).abs() < 1e-9, "{got} vs {want}");
+            }
+        }
+    }
+
+    #[test]
+    fn most_central_pair_of_a_mirrored_layout() {
+        // B mirrors A across the prime meridian and the off-equator points
+        // balance, so the centroid is (0, 0): only the equatorial pair meets
+        // there, every other midpoint is at least 0.5° off
+        let points_a = [0.0, -1.0, 2.0, -4.0, -2.0, -5.0];
+        let points_b = [0.0, 1.0, 2.0, 4.0, -2.0, 5.0];
+        let pair = most_central_pair(&points_a, &points_b);
+        assert_eq!(pair.len(), RESULT_STRIDE);
+        assert_eq!((pair[0], pair[1]), (0.0, 0.0));
+        assert!(pair[2] < 1e-6, "{}", pair[2]);
+
+        assert!(most_central_pair(&points_a, &[]).is_empty());
+    }
+
+    #[test]
+    fn tied_scores_share_a_dense_rank_without_gaps() {
+        // Coincident A points tie exactly: scores go a, a, b, c, c, and the
+        // rank after each tie is the next integer, not its position
+        let points_a = [0.0, 1.0, 0.0, 1.0, 0.0, 2.0, 0.0, 3.0, 0.0, 3.0];
+        let points_b = [0.0, 0.0];
+        let mut options = CombinationOptions::new();
+        let scores =
+            find_best_combinations_with_options(&points_a, &points_b, 0.0, 0.0, 5, &options);
+        options.set_score_as_rank(true);
+        let ranked =
+            find_best_combinations_with_options(&points_a, &points_b, 0.0, 0.0, 5, &options);
+
+        let ranks: Vec<f64> = ranked.chunks_exact(RESULT_STRIDE).map(|r| r[2]).collect();
+        assert_eq!(ranks, [1.0, 1.0, 2.0, 3.0, 3.0]);
+        // Everything but the score field is untouched
+        for (plain, rank) in scores
+            .chunks_exact(RESULT_STRIDE)
+            .zip(ranked.chunks_exact(RESULT_STRIDE))
+        {
+            assert_eq!(
+                (plain[0], plain[1], plain[3], plain[4]),
+                (rank[0], rank[1], rank[3], rank[4])
+            );
+        }
+    }
+
+    #[test]
+    fn forbidden_zone_drops_midpoints_near_the_target() {
+        let points_a = sample_points(220, 25, 40.0, -75.0, 2.0);
+        let points_b = sample_points(221, 25, 40.0, -75.0, 2.0);
+        let (target_lat, target_lon) = (41.0, -74.0);
+        let near_target = |results: &[f64]| {
+            results
+                .chunks_exact(RESULT_STRIDE)
+                .filter(|r| haversine_distance(r[3], r[4], target_lat, target_lon) < 30.0)
+                .count()
+        };
+
+        let mut options = CombinationOptions::new();
+        let open = find_best_combinations_with_options(
+            &points_a, &points_b, target_lat, target_lon, 625, &options,
+        );
+        assert!(near_target(&open) > 0);
+
+        options.set_min_target_distance(30.0);
+        let ring = find_best_combinations_with_options(
+            &points_a, &points_b, target_lat, target_lon, 625, &options,
+        );
+        assert_eq!(near_target(&ring), 0);
+        assert_eq!(ring.len() / RESULT_STRIDE, 625 - near_target(&open));
+
+        // As a penalty the near midpoints stay, just ranked lower
+        options.set_min_target_distance_penalty(30.0, 10.0);
+        let penalized = find_best_combinations_with_options(
+            &points_a, &points_b, target_lat, target_lon, 625, &options,
+        );
+        assert_eq!(penalized.len(), open.len());
+        assert!(near_target(&penalized[..ring.len() / 2]) < near_target(&open[..ring.len() / 2]));
+    }
+
+    #[test]
+    fn point_just_inside_a_square_is_slightly_negative() {
+        let square = [0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0, 0.0];
+        // 0.01° east of the western wall, far from the others
+        let inside = distance_to_polygon_boundary(0.5, 0.01, &square);
+        assert!(inside < 0.0);
+        assert!((inside + 0.01 * KM_PER_DEG_LAT).abs() < 0.01, "{inside}");
+
+        let outside = distance_to_polygon_boundary(0.5, -0.01, &square);
+        assert!((outside - 0.01 * KM_PER_DEG_LAT).abs() < 0.01, "{outside}");
+        assert!(distance_to_polygon_boundary(0.5, 0.0, &square).abs() < 1e-6);
+    }
+
+    #[test]
+    fn reachability_counts_members_within_range() {
+        // Members due north of the candidate at 0.5°, 1.5° and 3° (about 56,
+        // 167 and 334 km)
+        let points_a = [0.5, 0.0, 1.5, 0.0, 3.0, 0.0];
+        let points_b = [-0.5, 0.0, -3.0, 0.0];
+        assert_eq!(reachability(0.0, 0.0, &points_a, &points_b, 200.0), [2, 1]);
+        assert_eq!(reachability(0.0, 0.0, &points_a, &points_b, 400.0), [3, 2]);
+        assert_eq!(reachability(0.0, 0.0, &points_a, &points_b, 10.0), [0, 0]);
+    }
+
+    #[test]
+    fn result_stats_of_hand_picked_scores() {
+        // The textbook set: mean 5, population standard deviation 2
+        let results: Vec<f64> = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]
+            .iter()
+            .enumerate()
+            .flat_map(|(k, &score)| [k as f64, 0.0, score, 40.0, -74.0])
+            .collect();
+        let defaults = CombinationOptions::new();
+        let stats = result_stats(&results, &defaults);
+        assert!(
+            (stats[0] - 5.0).abs() < 1e-12 && (stats[1] - 2.0).abs() < 1e-12,
+            "{stats:?}"
+        );
+
+        assert_eq!(
+            result_stats(&results[..RESULT_STRIDE], &defaults),
+            [2.0, 0.0]
+        );
+        assert!(result_stats(&[], &defaults).is_empty());
+    }
+
+    #[test]
+    fn result_stats_reads_widened_records() {
+        let points_a = sample_points(174, 6, 40.0, -75.0, 1.0);
+        let points_b = sample_points(175, 5, 40.0, -75.0, 1.0);
+        let mut options = CombinationOptions::new();
+        options.set_include_coordinates(true);
+        let results =
+            find_best_combinations_with_options(&points_a, &points_b, 40.5, -74.5, 10, &options);
+        let scores: Vec<f64> = results
+            .chunks_exact(options.stride())
+            .map(|record| record[2])
+            .collect();
+        let mean = scores.iter().sum::<f64>() / scores.len() as f64;
+        let stats = result_stats(&results, &options);
+        assert!((stats[0] - mean).abs() < 1e-12, "{stats:?} vs {mean}");
+    }
+
+    #[test]
+    fn one_to_many_distances_match_single_calls() {
+        let points = sample_points(230, 50, -60.0, 100.0, 120.0);
+        let distances = distances_one_to_many(35.0, 139.0, &points);
+        assert_eq!(distances.len(), 50);
+        for (p, &d) in points.chunks_exact(2).zip(&distances) {
+            let single = haversine_km(35.0, 139.0, p[0], p[1]);
+            assert!(
+                (d - single).abs() <= 1e-9 * single.max(1.0),
+                "{d} vs {single}"
+            );
+        }
+        assert!(distances_one_to_many(35.0, 139.0, &[]).is_empty());
+    }
+
+    #[test]
+    fn two_symmetric_targets_pull_the_best_midpoint_between_them() {
+        // Midpoints at (0, 0), (0, 1.5) and (2, 0)
+        let points_a = [0.0, 0.0, 0.0, 3.0, 4.0, 0.0];
+        let points_b = [0.0, 0.0];
+
+        // Alone, the eastern target prefers the midpoint just past it
+        let options = CombinationOptions::new();
+        let single =
+            find_best_combinations_with_options(&points_a, &points_b, 0.0, 1.0, 1, &options);
+        assert_eq!(single[0], 1.0);
+
+        let mut options = CombinationOptions::new();
+        options
+            .set_targets(vec![0.0, -1.0, 0.0, 1.0], TargetAggregation::Sum)
+            .unwrap();
+        let results =
+            find_best_combinations_with_options(&points_a, &points_b, 0.0, 1.0, 3, &options);
+        let best = &results[..RESULT_STRIDE];
+        assert_eq!(best[0], 0.0);
+        assert!(best[3].abs() < 1e-9 && best[4].abs() < 1e-9);
+        // On the segment the sum is the targets' separation, 2°
+        assert!((best[2] - 2.0 * KM_PER_DEG_LAT).abs() < 1e-6, "{}", best[2]);
+
+        options
+            .set_targets(vec![0.0, -1.0, 0.0, 1.0], TargetAggregation::Mean)
+            .unwrap();
+        let mean = find_best_combinations_with_options(&points_a, &points_b, 0.0, 1.0, 3, &options);
+        let pairs = |results: &[f64]| -> Vec<(f64, f64)> {
+            results
+                .chunks_exact(RESULT_STRIDE)
+                .map(|r| (r[0], r[1]))
+                .collect()
+        };
+        assert_eq!(pairs(&mean), pairs(&results));
+        assert!((mean[2] - KM_PER_DEG_LAT).abs() < 1e-6);
+    }
+
+    #[test]
+    fn total_travel_optimum_is_the_geometric_median() {
+        // By the triangle inequality the geometric median of a convex
+        // quadrilateral is where its great-circle diagonals cross
+        let quad = [0.0, 0.0, 1.0, 3.0, 3.0, 2.5, 2.0, -1.0];
+        let crossing = intersection(
+            quad[0], quad[1], quad[4], quad[5], quad[2], quad[3], quad[6], quad[7],
+        );
+        let median_total: f64 = quad
+            .chunks_exact(2)
+            .map(|p| haversine_distance(crossing[0], crossing[1], p[0], p[1]))
+            .sum();
+
+        let optimum = optimize_meeting_point(&quad, Objective::TotalTravel, 500, 1e-4);
+        assert!(
+            haversine_distance(optimum[0], optimum[1], crossing[0], crossing[1]) < 0.1,
+            "{optimum:?} vs {crossing:?}"
+        );
+        assert!(optimum[2] >= median_total - 1e-6 && optimum[2] - median_total < 1e-3);
+
+        // It beats the centroid it starts from
+        let centroid = weighted_centroid(&quad, &[1.0; 4]);
+        let centroid_total: f64 = quad
+            .chunks_exact(2)
+            .map(|p| haversine_distance(centroid[0], centroid[1], p[0], p[1]))
+            .sum();
+        assert!(optimum[2] < centroid_total);
+    }
+
+    #[test]
+    fn dateline_crossing_splits_into_two_meeting_segments() {
+        // Symmetric about 180, so the cut is at the pair's great-circle midpoint
+        let path = [10.0, 170.0, 10.0, -170.0, 12.0, -160.0];
+        let mid = midpoints_one_to_many(10.0, 170.0, &[10.0, -170.0]);
+        assert!((mid[1].abs() - 180.0).abs() < 1e-9 && mid[0] > 10.0);
+
+        let split = split_antimeridian(&path);
+        assert_eq!(split.len(), 12, "{split:?}");
+        let (west, east) = (&split[..4], &split[6..]);
+        assert!(split[4].is_nan() && split[5].is_nan());
+        assert_eq!(&west[..2], &[10.0, 170.0]);
+        assert_eq!((west[3], east[1]), (180.0, -180.0));
+        assert_eq!(west[2], east[0]);
+        assert!((west[2] - mid[0]).abs() < 1e-9, "{} vs {}", west[2], mid[0]);
+        assert_eq!(&east[2..], &[10.0, -170.0, 12.0, -160.0]);
+
+        // Westbound the cut ends on -180 and resumes on 180
+        let reversed = split_antimeridian(&[10.0, -170.0, 10.0, 170.0]);
+        assert_eq!((reversed[3], reversed[7]), (-180.0, 180.0));
+        assert_eq!(reversed[2], reversed[6]);
+    }
+
+    #[test]
+    fn tightening_the_pair_threshold_strictly_drops_midpoints() {
+        let points_a = sample_points(240, 12, 40.0, -75.0, 3.0);
+        let points_b = sample_points(241, 12, 40.0, -75.0, 3.0);
+        let all = calculate_all_midpoints(&points_a, &points_b);
+        let mut pair_km = Vec::new();
+        for a in points_a.chunks_exact(2) {
+            for b in points_b.chunks_exact(2) {
+                pair_km.push(haversine_distance(a[0], a[1], b[0], b[1]));
+            }
+        }
+        assert_eq!(
+            calculate_all_midpoints_within(&points_a, &points_b, f64::INFINITY),
+            all
+        );
+
+        // Thresholds just under successive pair distances each lose a pair
+        let mut sorted = pair_km.clone();
+        sorted.sort_by(f64::total_cmp);
+        let mut previous = all.len();
+        for &cut in sorted.iter().rev().step_by(20) {
+            let kept = calculate_all_midpoints_within(&points_a, &points_b, cut - 1e-6);
+            assert!(kept.len() < previous, "{cut}");
+            previous = kept.len();
+
+            // The survivors are the full product's, in order
+            let expected: Vec<f64> = all
+                .chunks_exact(2)
+                .zip(&pair_km)
+                .filter(|&(_, &d)| d <= cut - 1e-6)
+                .flat_map(|(mid, _)| mid.to_vec())
+                .collect();
+            assert_eq!(kept, expected);
+        }
+    }
+
+    #[test]
+    fn pairing_aimed_at_the_target_has_near_zero_alignment() {
+        // An A-B pair heading due east along the equator toward the target,
+        // one heading due south across it, and one heading away from it
+        let points_a = [0.0, -2.0, 1.0, 2.0, 0.0, 2.5];
+        let points_b = [0.0, -1.0, -1.0, 2.0, 0.0, 1.5];
+        let mut options = CombinationOptions::new();
+        options.set_include_target_alignment(true).unwrap();
+        assert_eq!(options.stride(), RESULT_STRIDE + 1);
+
+        let results =
+            find_best_combinations_with_options(&points_a, &points_b, 0.0, 5.0, 9, &options);
+        let alignment = |i: f64, j: f64| {
+            results
+                .chunks_exact(options.stride())
+                .find(|r| (r[0], r[1]) == (i, j))
+                .unwrap()[RESULT_STRIDE]
+        };
+        assert!(alignment(0.0, 0.0) < 1e-6, "{}", alignment(0.0, 0.0));
+        assert!(
+            (alignment(1.0, 1.0) - 90.0).abs() < 0.1,
+            "{}",
+            alignment(1.0, 1.0)
+        );
+        assert!(
+            (alignment(2.0, 2.0) - 180.0).abs() < 1e-6,
+            "{}",
+            alignment(2.0, 2.0)
+        );
+    }
+
+    #[test]
+    fn packed_records_follow_the_documented_layout() {
+        // Pair (1, 2) of 2 by 3 points: A at (0, 0), B at (0, 2), the midpoint at
+        // (0, 1), and the target due east of it at (0, 5)
+        let points_a = [9.0, 9.0, 0.0, 0.0];
+        let points_b = [9.0, 9.0, 9.0, 9.0, 0.0, 2.0];
+        let result = ComboResult {
+            index_a: 1,
+            index_b: 2,
+            score: 3.0,
+            midpoint_lat: 0.0,
+            midpoint_lon: 1.0,
+        };
+        let quality = midpoint_quality(0.0, 0.0, 0.0, 2.0, 0.0, 5.0);
+
+        for mask in 0..128u32 {
+            let on = |bit: u32| mask & (1 << bit) != 0;
+            let mut options = CombinationOptions::new();
+            options.set_include_quality(on(0)).unwrap();
+            options.set_include_pair_id(on(1));
+            options.set_include_max_travel(on(2));
+            options.set_include_midpoint_azimuth(on(3)).unwrap();
+            options.set_include_coordinates(on(4));
+            options.set_include_target_alignment(on(5)).unwrap();
+            if on(6) {
+                options.set_tier_boundaries(vec![1.0, 10.0]).unwrap();
+            }
+
+            let mut expected = vec![1.0, 2.0, 3.0, 0.0, 1.0];
+            if on(0) {
+                expected.push(quality);
+            }
+            if on(1) {
+                expected.push(5.0);
+            }
+            if on(2) {
+                expected.push(KM_PER_DEG_LAT);
+            }
+            if on(3) {
+                expected.push(90.0);
+            }
+            if on(4) {
+                expected.extend([0.0, 0.0, 0.0, 2.0]);
+            }
+            if on(5) {
+                expected.push(0.0);
+            }
+            if on(6) {
+                expected.push(1.0);
+            }
+
+            let packed = flatten_results(&[result], &points_a, &points_b, 0.0, 5.0, &options);
+            assert_eq!(packed.len(), options.stride(), "mask {mask:#b}");
+            assert_eq!(packed.len(), expected.len(), "mask {mask:#b}");
+            for (k, (got, want)) in packed.iter().zip(&expected).enumerate() {
+                assert!(
+                    (got - want).abs() < 1e-9,
+                    "mask {mask:#b} field {k}: {got} vs {want}"
+                );
+            }
+        }
+
+        // Lon,lat order swaps every coordinate pair; a rank replaces the score
+        let mut options = CombinationOptions::new();
+        options.set_include_coordinates(true);
+        options.set_coord_order(CoordOrder::LonLat);
+        options.set_score_as_rank(true);
+        let packed = flatten_results(&[result], &points_a, &points_b, 0.0, 5.0, &options);
+        assert_eq!(packed, [1.0, 2.0, 1.0, 1.0, 0.0, 0.0, 0.0, 2.0, 0.0]);
+    }
+
+    #[test]
+    fn point_inside_a_cluster_classifies_to_its_group() {
+        let groups = [
+            sample_points(250, 20, 48.0, 2.0, 0.5),
+            sample_points(251, 20, 52.3, 13.2, 0.5),
+            sample_points(252, 20, 41.7, 12.3, 0.5),
+        ];
+        let centroids: Vec<f64> = groups
+            .iter()
+            .flat_map(|g| weighted_centroid(g, &[1.0; 20]))
+            .collect();
+
+        for (k, group) in groups.iter().enumerate() {
+            for member in group.chunks_exact(2) {
+                let class = classify_point(member[0], member[1], &centroids);
+                assert_eq!(class[0], k as f64);
+                assert!(class[1] < 50.0);
+            }
+        }
+        assert!(classify_point(48.0, 2.0, &[]).is_empty());
+    }
+
+    #[test]
+    fn equal_speeds_favor_the_pair_equidistant_from_the_target() {
+        // Along the equator with the target at 0: A0 and B0 are both 1° out,
+        // A1 is 2° out and B1 is 1° out on the same side
+        let points_a = [0.0, -1.0, 0.0, -2.0];
+        let points_b = [0.0, 1.0, 0.0, -1.0];
+        let mut options = CombinationOptions::new();
+        options.set_scoring_mode(ScoringMode::ArrivalSkew);
+        options.set_group_speeds(50.0, 50.0).unwrap();
+        let results =
+            find_best_combinations_with_options(&points_a, &points_b, 0.0, 0.0, 4, &options);
+        // The target is the equidistant point of pair (0, 0), which arrives together
+        assert_eq!((results[0], results[1]), (0.0, 0.0));
+        assert!(results[2].abs() < 1e-9);
+        assert!(results[3].abs() < 1e-9 && results[4].abs() < 1e-9);
+
+        // Equal speeds rank like the plain distance gap, scaled to hours
+        let mut unscaled = CombinationOptions::new();
+        unscaled.set_scoring_mode(ScoringMode::ArrivalSkew);
+        let km = find_best_combinations_with_options(&points_a, &points_b, 0.0, 0.0, 4, &unscaled);
+        for (hours, km) in results
+            .chunks_exact(RESULT_STRIDE)
+            .zip(km.chunks_exact(RESULT_STRIDE))
+        {
+            assert_eq!((hours[0], hours[1]), (km[0], km[1]));
+            assert!((hours[2] * 50.0 - km[2]).abs() < 1e-9);
+        }
+
+        // With A twice as fast, A1 (twice as far as either B) arrives with them
+        options.set_group_speeds(100.0, 50.0).unwrap();
+        let results =
+            find_best_combinations_with_options(&points_a, &points_b, 0.0, 0.0, 1, &options);
+        assert_eq!((results[0], results[1]), (1.0, 0.0));
+    }
+
+    #[test]
+    fn three_by_three_grid_over_a_two_degree_box() {
+        let grid = grid_points(10.0, 20.0, 12.0, 22.0, 3, 3);
+        let expected = [
+            12.0, 20.0, 12.0, 21.0, 12.0, 22.0, 11.0, 20.0, 11.0, 21.0, 11.0, 22.0, 10.0, 20.0,
+            10.0, 21.0, 10.0, 22.0,
+        ];
+        assert_eq!(grid, expected);
+
+        // Across the antimeridian the middle column lands on -180
+        let wrapped = grid_points(10.0, 179.0, 12.0, -179.0, 3, 3);
+        assert_eq!(&wrapped[..6], &[12.0, 179.0, 12.0, -180.0, 12.0, -179.0]);
+        assert!(grid_points(10.0, 20.0, 12.0, 22.0, 0, 3).is_empty());
+    }
+
+    #[test]
+    fn tiers_split_at_boundaries_and_clamp_to_the_last() {
+        let mut options = CombinationOptions::new();
+        options.set_tier_boundaries(vec![10.0, 20.0]).unwrap();
+        let scores = [5.0, 10.0 - 1e-9, 10.0, 19.99, 20.0, 500.0, f64::NAN];
+        let results: Vec<ComboResult> = scores
+            .iter()
+            .map(|&score| ComboResult {
+                index_a: 0,
+                index_b: 0,
+                score,
+                midpoint_lat: 0.0,
+                midpoint_lon: 0.0,
+            })
+            .collect();
+        let packed = flatten_results(&results, &[0.0, 0.0], &[0.0, 0.0], 0.0, 0.0, &options);
+        let tiers: Vec<f64> = packed
+            .chunks_exact(options.stride())
+            .map(|r| r[RESULT_STRIDE])
+            .collect();
+        // Each boundary opens the tier above it; past the last is tier 2
+        assert_eq!(tiers, [0.0, 0.0, 1.0, 1.0, 2.0, 2.0, 2.0]);
+
+        // In a real run every record's tier agrees with its score
+        let points_a = sample_points(260, 15, 40.0, -75.0, 1.0);
+        let points_b = sample_points(261, 15, 40.0, -75.0, 1.0);
+        let run =
+            find_best_combinations_with_options(&points_a, &points_b, 40.5, -74.5, 225, &options);
+        let counts = run
+            .chunks_exact(options.stride())
+            .fold([0; 3], |mut counts, r| {
+                let tier = if r[2] < 10.0 {
+                    0
+                } else if r[2] < 20.0 {
+                    1
+                } else {
+                    2
+                };
+                assert_eq!(r[RESULT_STRIDE], tier as f64);
+                counts[tier] += 1;
+                counts
+            });
+        assert!(counts.iter().all(|&c| c > 0), "{counts:?}");
+    }
+
+    #[test]
+    fn great_circle_saves_at_high_latitude_and_not_on_a_meridian() {
+        // Along the 60th parallel the rhumb line runs 60° · cos 60° = 30°
+        // of arc; the great circle spans acos(0.875) ≈ 28.955°
+        let high = route_comparison(60.0, -30.0, 60.0, 30.0);
+        assert!((high[1] - 30.0 * KM_PER_DEG_LAT).abs() < 1e-6, "{high:?}");
+        assert!(
+            (high[0] - 0.875f64.acos().to_degrees() * KM_PER_DEG_LAT).abs() < 1e-6,
+            "{high:?}"
+        );
+        assert!(high[2] > 100.0 && high[3] > 3.0, "{high:?}");
+
+        // Due north across the equator both routes are the meridian
+        let meridian = route_comparison(-10.0, 5.0, 20.0, 5.0);
+        assert!((meridian[0] - 30.0 * KM_PER_DEG_LAT).abs() < 1e-6);
+        assert!(
+            meridian[2].abs() < 1e-6 && meridian[3].abs() < 1e-6,
+            "{meridian:?}"
+        );
+        assert_eq!(route_comparison(10.0, 5.0, 10.0, 5.0)[3], 0.0);
+    }
+
+    #[test]
+    fn pickup_route_prefers_the_passenger_on_the_way() {
+        // The driver heads east along the equator to the target at 10° east;
+        // B1 waits right on that road, B0 is as far east but 3° north of it
+        let drivers = [0.0, 0.0];
+        let passengers = [3.0, 5.0, 0.0, 5.0];
+        let mut options = CombinationOptions::new();
+        options.set_scoring_mode(ScoringMode::PickupRoute);
+        let results =
+            find_best_combinations_with_options(&drivers, &passengers, 0.0, 10.0, 2, &options);
+
+        assert_eq!(results[1], 1.0);
+        // No detour: the route is just the drive to the target
+        assert!(
+            (results[2] - 10.0 * KM_PER_DEG_LAT).abs() < 1e-6,
+            "{}",
+            results[2]
+        );
+        assert!(results[RESULT_STRIDE + 2] > results[2] + 50.0);
+        // The passenger's location stands in for the midpoint
+        assert_eq!((results[3], results[4]), (0.0, 5.0));
+        assert_eq!(
+            (results[RESULT_STRIDE + 3], results[RESULT_STRIDE + 4]),
+            (3.0, 5.0)
+        );
+    }
+
+    #[test]
+    fn diagonal_cluster_gets_a_rectangle_along_its_axis() {
+        // A thin band along the lat = lon diagonal, 0.1° either side of it
+        let mut points = Vec::new();
+        for t in 0..=10 {
+            let t = t as f64 * 0.5;
+            points.extend([
+                10.0 + t + 0.1,
+                20.0 + t - 0.1,
+                10.0 + t - 0.1,
+                20.0 + t + 0.1,
+            ]);
+        }
+        let rect = min_bounding_rect(&points);
+        assert_eq!(rect.len(), 8);
+
+        let corners: Vec<(f64, f64)> = rect.chunks_exact(2).map(|p| (p[1], p[0])).collect();
+        let side = |k: usize| {
+            let (p, q) = (corners[k], corners[(k + 1) % 4]);
+            (q.0 - p.0, q.1 - p.1)
+        };
+        let (long, short) = if side(0).0.hypot(side(0).1) > side(1).0.hypot(side(1).1) {
+            (side(0), side(1))
+        } else {
+            (side(1), side(0))
+        };
+        // The long side runs along the diagonal, at 45° in lon/lat
+        assert!(
+            (long.1.atan2(long.0).to_degrees().rem_euclid(180.0) - 45.0).abs() < 1e-6,
+            "{long:?}"
+        );
+        let area = long.0.hypot(long.1) * short.0.hypot(short.1);
+        assert!(
+            (area - 5.0 * 2f64.sqrt() * 0.2 * 2f64.sqrt()).abs() < 1e-9,
+            "{area}"
+        );
+
+        let bounds = bounding_box(&points);
+        let box_area = (bounds[2] - bounds[0]) * (bounds[3] - bounds[1]);
+        assert!(area < box_area / 10.0, "{area} vs {box_area}");
+    }
+
+    #[test]
+    fn streamed_points_end_up_with_the_batch_result() {
+        let points_a = sample_points(270, 30, 40.0, -75.0, 2.0);
+        let points_b = sample_points(271, 25, 40.0, -75.0, 2.0);
+        let mut options = CombinationOptions::new();
+        options.set_min_target_distance(5.0);
+
+        for top_n in [1, 20, 750, 2000] {
+            let mut combiner = StreamingCombiner::new(40.8, -74.2, top_n, &options).unwrap();
+            // Interleave the two groups unevenly, A running ahead at first
+            let (mut next_a, mut next_b) = (0, 0);
+            for step in 0..55 {
+                if next_b == 25 || (next_a < 30 && step % 3 != 2) {
+                    assert_eq!(
+                        combiner.add_a(points_a[next_a * 2], points_a[next_a * 2 + 1]),
+                        next_a
+                    );
+                    next_a += 1;
+                } else {
+                    assert_eq!(
+                        combiner.add_b(points_b[next_b * 2], points_b[next_b * 2 + 1]),
+                        next_b
+                    );
+                    next_b += 1;
+                }
+            }
+            assert_eq!((next_a, next_b), (30, 25));
+
+            let batch = find_best_combinations_with_options(
+                &points_a, &points_b, 40.8, -74.2, top_n, &options,
+            );
+            assert_eq!(bits(&combiner.best()), bits(&batch), "top_n {top_n}");
+        }
+    }
+
+    #[test]
+    fn centrality_deficit_is_never_negative() {
+        for seed in 0..12 {
+            let points_a = sample_points(280 + seed, 6, 40.0, -75.0, 2.0);
+            let points_b = sample_points(300 + seed, 5, 40.5, -74.0, 2.0);
+            // Targets inside the groups and well away from them
+            for (target_lat, target_lon) in [(41.0, -74.2), (43.5, -71.0), (39.0, -77.5)] {
+                let deficit = centrality_deficit(&points_a, &points_b, target_lat, target_lon);
+                let best = find_best_combinations(&points_a, &points_b, target_lat, target_lon, 1);
+                assert_eq!(deficit[0], best[2]);
+                assert!(
+                    deficit[1] >= 0.0 && deficit[2] >= 0.0,
+                    "seed {seed}: {deficit:?}"
+                );
+                assert_eq!(deficit[2], deficit[0] - deficit[1]);
+            }
+        }
+        assert!(centrality_deficit(&[], &[40.0, -74.0], 40.0, -74.0).is_empty());
+    }
+
+    #[test]
+    fn centrality_deficit_vanishes_for_dense_candidates() {
+        // Both groups fill the same 1° box on a grid; the midpoints then lie
+        // on a lattice of half the spacing, so the best misses a target
+        // inside by at most half that lattice's diagonal. For a target due
+        // north of the box some midpoint already sits on the nearest edge.
+        let mut previous = f64::INFINITY;
+        for cells in [6, 11, 21] {
+            let grid = grid_points(40.0, -75.0, 41.0, -74.0, cells, cells);
+            let half_diagonal = 0.5 / (cells - 1) as f64 * 2f64.sqrt() / 2.0 * KM_PER_DEG_LAT;
+            let inside = centrality_deficit(&grid, &grid, 40.37, -74.61);
+            assert!(inside[1] < 0.01, "{inside:?}");
+            assert!(
+                inside[2] >= 0.0 && inside[2] <= half_diagonal,
+                "{cells}: {inside:?}"
+            );
+            assert!(inside[2] < previous, "{cells}: {inside:?}");
+            previous = inside[2];
+
+            let outside = centrality_deficit(&grid, &grid, 41.6, -74.5);
+            assert!(
+                outside[2] >= 0.0 && outside[2] < 0.01,
+                "{cells}: {outside:?}"
+            );
+        }
+
+        // Two A members straddling the target's latitude, one B: the pairs
+        // meet 1° north and south of the target, but a participant standing
+        // between the two A members would meet right on it
+        let sparse = centrality_deficit(&[2.0, -1.0, -2.0, -1.0], &[0.0, 1.0], 0.0, 0.0);
+        assert!((sparse[0] - KM_PER_DEG_LAT).abs() < 1.0, "{sparse:?}");
+        assert!(sparse[1] < 0.01 && sparse[2] > 100.0, "{sparse:?}");
+    }
+
+    #[test]
+    fn accurate_fix_dominates_a_vague_geocode() {
+        // Weights 1/5² and 1/500²: the center sits 1/10001 of the way over
+        let points = [40.0, -74.0, 40.0, -73.0];
+        let center = accuracy_weighted_center(&points, &[5.0, 500.0]).unwrap();
+        let from_accurate = haversine_distance(center[0], center[1], 40.0, -74.0);
+        let span = haversine_distance(40.0, -74.0, 40.0, -73.0);
+        assert!(
+            (from_accurate / span - 1.0 / 10001.0).abs() < 1e-5,
+            "{center:?}"
+        );
+
+        // Equal accuracies meet in the middle; an exact fix takes over entirely
+        let even = accuracy_weighted_center(&points, &[50.0, 50.0]).unwrap();
+        assert!((haversine_distance(even[0], even[1], 40.0, -74.0) - span / 2.0).abs() < 1e-6);
+        let exact = accuracy_weighted_center(&points, &[0.0, 500.0]).unwrap();
+        assert!(haversine_distance(exact[0], exact[1], 40.0, -74.0) < 1e-9);
+    }
+}
+
+#[cfg(all(test, feature = "parallel"))]
+mod parallel_tests {
+    use super::*;
+
+    #[test]
+    fn parallel_output_is_byte_identical_to_serial_on_ties() {
+        // A 10×10 grid of three A sites and two B sites: only six distinct
+        // scores, each shared by a dozen or more pairs
+        let sites_a = [[40.0, -74.0], [40.2, -73.9], [39.9, -74.1]];
+        let sites_b = [[40.1, -74.2], [40.3, -74.0]];
+        let points_a: Vec<f64> = (0..10).flat_map(|i| sites_a[i % 3]).collect();
+        let points_b: Vec<f64> = (0..10).flat_map(|j| sites_b[j % 2]).collect();
+        let bytes = |results: Vec<f64>| -> Vec<u8> {
+            results.iter().flat_map(|v| v.to_le_bytes()).collect()
+        };
+
+        let options = CombinationOptions::new();
+        let all =
+            find_best_combinations_with_options(&points_a, &points_b, 40.1, -74.0, 100, &options);
+        let distinct: HashSet<u64> = all
+            .chunks_exact(RESULT_STRIDE)
+            .map(|r| r[2].to_bits())
+            .collect();
+        assert_eq!(distinct.len(), 6);
+
+        for top_n in [1, 5, 17, 50, 99, 100, 150] {
+            let serial = bytes(find_best_combinations_with_options(
+                &points_a, &points_b, 40.1, -74.0, top_n, &options,
+            ));
+            assert_eq!(serial.len(), top_n.min(100) * RESULT_STRIDE * 8);
+            for threads in [1, 2, 3, 7, 16] {
+                let parallel = find_best_combinations_parallel(
+                    &points_a, &points_b, 40.1, -74.0, top_n, &options, threads,
+                );
+                assert_eq!(bytes(parallel), serial, "top_n {top_n}, {threads} threads");
+            }
+        }
+    }
 }